@@ -0,0 +1,50 @@
+// Times Multiq push and pop against the number of values already queued. Each push is O(1),
+// so the time per push stays flat from an empty queue to one holding 100k values, where a
+// push that walks the queued values to reach the end would take thousands of times longer.
+// Run with: cargo bench --bench multiq
+use concurrency::multiq::Multiq;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const VALUES: usize = 100_000;
+
+fn empty_queue() -> Multiq<usize> {
+    let queue = Multiq::new(0);
+    queue.pop();
+    queue
+}
+
+fn per_value(elapsed: Duration, values: usize) -> f64 {
+    elapsed.as_nanos() as f64 / values as f64
+}
+
+fn main() {
+    println!(
+        "{:>10} {:>14} {:>14}",
+        "queued", "ns per push", "ns per pop"
+    );
+    for queued in [0, 1_000, 10_000, VALUES] {
+        let queue = empty_queue();
+        (0..queued).for_each(|value| queue.push(value));
+        let start = Instant::now();
+        for value in 0..1_000 {
+            queue.push(black_box(value));
+        }
+        let push = per_value(start.elapsed(), 1_000);
+        let start = Instant::now();
+        for _ in 0..1_000 {
+            black_box(queue.pop());
+        }
+        let pop = per_value(start.elapsed(), 1_000);
+        println!("{queued:>10} {push:>14.1} {pop:>14.1}");
+    }
+
+    let queue = empty_queue();
+    let start = Instant::now();
+    (0..VALUES).for_each(|value| queue.push(black_box(value)));
+    let filled = start.elapsed();
+    let start = Instant::now();
+    while black_box(queue.pop()).is_some() {}
+    let drained = start.elapsed();
+    println!("{VALUES} values pushed in {filled:?} and popped in {drained:?}");
+}
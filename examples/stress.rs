@@ -0,0 +1,265 @@
+// Soak test of the queues and stacks of the crate: producer, consumer and mixed threads hammer
+// one container for a while, and every second the counters are checked against each other. A
+// lost or duplicated value makes it exit with an error, so it can be left running for hours to
+// validate the crate on new hardware.
+// Run with: cargo run --release --example stress -- --producers 4 --consumers 4 --seconds 60
+//
+// Options, all optional:
+//   --container multiq|stackus  container under test, multiq by default
+//   --producers N               threads that only push, 2 by default
+//   --consumers N               threads that only pop, 2 by default
+//   --mixed N                   threads that push and pop in turns, 0 by default
+//   --seconds N                 how long to run, 10 by default
+use concurrency::container::{ConcurrentQueue, ConcurrentStack};
+use concurrency::multiq::Multiq;
+use concurrency::stackus::Stackus;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The operations the workload needs from a container.
+trait Target: Sync {
+    fn push(&self, value: u64);
+    fn pop(&self) -> Option<u64>;
+    fn len(&self) -> usize;
+}
+
+impl Target for Multiq<u64> {
+    fn push(&self, value: u64) {
+        ConcurrentQueue::push(self, value)
+    }
+
+    fn pop(&self) -> Option<u64> {
+        ConcurrentQueue::try_pop(self)
+    }
+
+    fn len(&self) -> usize {
+        ConcurrentQueue::len(self)
+    }
+}
+
+impl Target for Stackus<u64> {
+    fn push(&self, value: u64) {
+        ConcurrentStack::push(self, value)
+    }
+
+    fn pop(&self) -> Option<u64> {
+        ConcurrentStack::try_pop(self)
+    }
+
+    fn len(&self) -> usize {
+        ConcurrentStack::len(self)
+    }
+}
+
+#[derive(Debug)]
+struct Config {
+    container: String,
+    producers: usize,
+    consumers: usize,
+    mixed: usize,
+    seconds: u64,
+}
+
+/// Counters shared by the threads. A value is counted as pushed before the push and as popped
+/// after the pop, so popped never exceeds pushed. The sums catch values that are lost or
+/// handed out twice even when the counts match.
+#[derive(Debug, Default)]
+struct Totals {
+    pushed: AtomicU64,
+    popped: AtomicU64,
+    pushed_sum: AtomicU64,
+    popped_sum: AtomicU64,
+}
+
+fn parse_args() -> Result<Config, String> {
+    let mut config = Config {
+        container: "multiq".to_string(),
+        producers: 2,
+        consumers: 2,
+        mixed: 0,
+        seconds: 10,
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args.next().ok_or(format!("{flag} needs a value"))?;
+        let number = || {
+            value
+                .parse()
+                .map_err(|_| format!("{flag}: {value} is not a number"))
+        };
+        match flag.as_str() {
+            "--container" => config.container = value.clone(),
+            "--producers" => config.producers = number()?,
+            "--consumers" => config.consumers = number()?,
+            "--mixed" => config.mixed = number()?,
+            "--seconds" => config.seconds = number()? as u64,
+            _ => return Err(format!("unknown option {flag}")),
+        }
+    }
+    if config.producers + config.mixed == 0 {
+        return Err("nothing would push, add --producers or --mixed".to_string());
+    }
+    Ok(config)
+}
+
+/// Returns the value thread `thread` pushes as its `sequence`th, unique across threads.
+fn value_of(thread: usize, sequence: u64) -> u64 {
+    (thread as u64) << 40 | sequence
+}
+
+fn push(target: &dyn Target, totals: &Totals, value: u64) {
+    totals.pushed.fetch_add(1, Ordering::Relaxed);
+    totals.pushed_sum.fetch_add(value, Ordering::Relaxed);
+    target.push(value);
+}
+
+fn pop(target: &dyn Target, totals: &Totals) -> bool {
+    match target.pop() {
+        Some(value) => {
+            totals.popped_sum.fetch_add(value, Ordering::Relaxed);
+            // Release, so a check that sees this count sees the push counted too
+            totals.popped.fetch_add(1, Ordering::Release);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Checks the counters while threads are running, returns the values popped so far.
+fn check_running(totals: &Totals) -> Result<u64, String> {
+    // popped first, so every value it counts was counted as pushed before pushed is read
+    let popped = totals.popped.load(Ordering::Acquire);
+    let pushed = totals.pushed.load(Ordering::Relaxed);
+    if popped > pushed {
+        return Err(format!(
+            "popped {popped} values but only {pushed} were pushed"
+        ));
+    }
+    Ok(popped)
+}
+
+/// Checks that every pushed value was popped exactly once, after draining the container.
+fn check_final(target: &dyn Target, totals: &Totals) -> Result<(), String> {
+    while pop(target, totals) {}
+    let pushed = totals.pushed.load(Ordering::Relaxed);
+    let popped = totals.popped.load(Ordering::Relaxed);
+    if pushed != popped {
+        return Err(format!("pushed {pushed} values but popped {popped}"));
+    }
+    let pushed_sum = totals.pushed_sum.load(Ordering::Relaxed);
+    let popped_sum = totals.popped_sum.load(Ordering::Relaxed);
+    if pushed_sum != popped_sum {
+        return Err(format!(
+            "the popped values sum to {popped_sum}, the pushed ones to {pushed_sum}"
+        ));
+    }
+    if target.len() != 0 {
+        return Err(format!("drained but len is {}", target.len()));
+    }
+    Ok(())
+}
+
+fn run(target: &dyn Target, config: &Config) -> Result<(), String> {
+    let totals = Totals::default();
+    let running = AtomicBool::new(true);
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for thread in 0..config.producers {
+            let (totals, running) = (&totals, &running);
+            scope.spawn(move || {
+                let mut sequence = 0;
+                while running.load(Ordering::Relaxed) {
+                    push(target, totals, value_of(thread, sequence));
+                    sequence += 1;
+                    // keeps the producers from outrunning the consumers by gigabytes
+                    if target.len() > 1 << 20 {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        for _ in 0..config.consumers {
+            let (totals, running) = (&totals, &running);
+            scope.spawn(move || {
+                while running.load(Ordering::Relaxed) {
+                    if !pop(target, totals) {
+                        thread::yield_now();
+                    }
+                }
+            });
+        }
+        for thread in config.producers..config.producers + config.mixed {
+            let (totals, running) = (&totals, &running);
+            scope.spawn(move || {
+                let mut sequence = 0;
+                while running.load(Ordering::Relaxed) {
+                    push(target, totals, value_of(thread, sequence));
+                    sequence += 1;
+                    pop(target, totals);
+                }
+            });
+        }
+
+        let duration = Duration::from_secs(config.seconds);
+        let mut last = 0;
+        let result = loop {
+            thread::sleep(Duration::from_secs(1).min(duration.saturating_sub(start.elapsed())));
+            let popped = match check_running(&totals) {
+                Ok(popped) => popped,
+                Err(error) => break Err(error),
+            };
+            println!(
+                "{:>6.1}s {:>12} popped {:>10} per second {:>10} queued",
+                start.elapsed().as_secs_f64(),
+                popped,
+                popped - last,
+                target.len()
+            );
+            last = popped;
+            if start.elapsed() >= duration {
+                break Ok(());
+            }
+        };
+        running.store(false, Ordering::Relaxed);
+        result
+    })?;
+    check_final(target, &totals)?;
+    println!(
+        "{} values pushed and popped exactly once",
+        totals.pushed.load(Ordering::Relaxed)
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let config = match parse_args() {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{error}");
+            return ExitCode::FAILURE;
+        }
+    };
+    println!("{config:?}");
+    let result = match config.container.as_str() {
+        "multiq" => {
+            let queue = Multiq::new(0);
+            queue.pop();
+            run(&queue, &config)
+        }
+        "stackus" => {
+            let stack = Stackus::new(0);
+            stack.pop();
+            run(&stack, &config)
+        }
+        container => Err(format!("unknown container {container}")),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("invariant violated: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
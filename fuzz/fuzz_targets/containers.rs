@@ -0,0 +1,120 @@
+#![no_main]
+
+// Interprets the input as two threads' programs of push/pop/drain operations, runs them at
+// the same time against Stackus and Multiq and checks the values against a model: every
+// pushed value comes out exactly once, either popped, drained or left in the container.
+
+use concurrency::{multiq::Multiq, stackus::Stackus};
+use libfuzzer_sys::fuzz_target;
+use std::{sync::Barrier, thread};
+
+const THREADS: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Push,
+    Pop,
+    Drain,
+}
+
+trait Container: Sync {
+    fn push(&self, value: u32);
+    fn pop(&self) -> Option<u32>;
+    fn drain(&self) -> Vec<u32>;
+}
+
+impl Container for Stackus<u32> {
+    fn push(&self, value: u32) {
+        Stackus::push(self, value)
+    }
+
+    fn pop(&self) -> Option<u32> {
+        Stackus::pop(self)
+    }
+
+    fn drain(&self) -> Vec<u32> {
+        self.pop_all().collect()
+    }
+}
+
+impl Container for Multiq<u32> {
+    fn push(&self, value: u32) {
+        Multiq::push(self, value)
+    }
+
+    fn pop(&self) -> Option<u32> {
+        Multiq::pop(self)
+    }
+
+    fn drain(&self) -> Vec<u32> {
+        Multiq::drain(self).collect()
+    }
+}
+
+/// Splits the input into one program per thread, the low bit of a byte picks the thread.
+fn programs(data: &[u8]) -> Vec<Vec<Op>> {
+    let mut programs = vec![Vec::new(); THREADS];
+    for byte in data {
+        let op = match (byte >> 1) % 4 {
+            0 | 1 => Op::Push,
+            2 => Op::Pop,
+            _ => Op::Drain,
+        };
+        programs[(byte & 1) as usize].push(op);
+    }
+    programs
+}
+
+/// Runs the programs against `container`, returns the values pushed and the values that came
+/// out, including the ones left at the end.
+fn run<C: Container>(container: &C, programs: &[Vec<Op>]) -> (Vec<u32>, Vec<u32>) {
+    let barrier = Barrier::new(programs.len());
+    let results: Vec<_> = thread::scope(|scope| {
+        let handles: Vec<_> = programs
+            .iter()
+            .enumerate()
+            .map(|(thread, program)| {
+                let barrier = &barrier;
+                scope.spawn(move || {
+                    let (mut pushed, mut taken) = (Vec::new(), Vec::new());
+                    barrier.wait();
+                    for (step, op) in program.iter().enumerate() {
+                        match op {
+                            Op::Push => {
+                                // unique values, so a duplicate shows up in the model
+                                let value = (thread << 16 | step) as u32;
+                                container.push(value);
+                                pushed.push(value);
+                            }
+                            Op::Pop => taken.extend(container.pop()),
+                            Op::Drain => taken.extend(container.drain()),
+                        }
+                    }
+                    (pushed, taken)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let (mut pushed, mut taken) = (Vec::new(), container.drain());
+    for (thread_pushed, thread_taken) in results {
+        pushed.extend(thread_pushed);
+        taken.extend(thread_taken);
+    }
+    (pushed, taken)
+}
+
+fn check<C: Container>(container: C, programs: &[Vec<Op>]) {
+    // the constructors need a first value, take it out before the run
+    assert_eq!(container.pop(), Some(u32::MAX));
+    let (mut pushed, mut taken) = run(&container, programs);
+    pushed.sort_unstable();
+    taken.sort_unstable();
+    assert_eq!(pushed, taken, "values lost or duplicated");
+}
+
+fuzz_target!(|data: &[u8]| {
+    let programs = programs(data);
+    check(Stackus::new(u32::MAX), &programs);
+    check(Multiq::new(u32::MAX), &programs);
+});
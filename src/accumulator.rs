@@ -0,0 +1,82 @@
+use std::{
+    mem,
+    ops::Add,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+/// Hands out stripe numbers to threads in the order they first add to any accumulator.
+static THREADS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD: usize = THREADS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Sums values added by many threads, e.g. partial results of workers popping from a
+/// [crate::multiq::Multiq], without every add contending on one `Mutex<f64>`. Each thread
+/// adds to its own stripe, stripes are only combined by [Accumulator::sum]. Works for any
+/// `T: Add` with [Default] as zero, including floats where atomics don't help.
+#[derive(Debug)]
+pub struct Accumulator<T> {
+    pub stripes: Vec<Stripe<T>>,
+}
+
+/// A partial sum, aligned to its own cache lines so threads adding to neighbouring stripes
+/// don't slow each other down.
+#[derive(Debug, Default)]
+#[repr(align(128))]
+pub struct Stripe<T> {
+    pub value: Mutex<T>,
+}
+
+impl<T: Add<Output = T> + Default> Accumulator<T> {
+    /// Creates a new accumulator with one stripe per available CPU.
+    pub fn new() -> Self {
+        Self::with_stripes(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// Creates a new accumulator with `stripes` partial sums, threads beyond that share them.
+    pub fn with_stripes(stripes: usize) -> Self {
+        assert!(stripes > 0, "stripes must be greater than zero");
+        Accumulator {
+            stripes: (0..stripes).map(|_| Stripe::default()).collect(),
+        }
+    }
+
+    /// Adds `value` to the current thread's partial sum.
+    pub fn add(&self, value: T) {
+        let stripe = THREAD.with(|thread| thread % self.stripes.len());
+        let mut sum = self.stripes[stripe]
+            .value
+            .lock()
+            .expect("lock acquire failed");
+        *sum = mem::take(&mut *sum) + value;
+    }
+
+    /// Returns the sum of everything added so far. Adds that run meanwhile may or may not be
+    /// included.
+    pub fn sum(&self) -> T
+    where
+        T: Clone,
+    {
+        self.stripes.iter().fold(T::default(), |total, stripe| {
+            total + stripe.value.lock().expect("lock acquire failed").clone()
+        })
+    }
+
+    /// Returns the sum and starts over from zero, no add is counted twice or lost.
+    pub fn reset(&self) -> T {
+        self.stripes.iter().fold(T::default(), |total, stripe| {
+            total + mem::take(&mut *stripe.value.lock().expect("lock acquire failed"))
+        })
+    }
+}
+
+impl<T: Add<Output = T> + Default> Default for Accumulator<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,45 @@
+/// Restricts the current thread to the `index`-th CPU it is allowed to run on, wrapping around,
+/// so pinning respects taskset and cgroup limits. Returns false if the OS refused.
+#[cfg(target_os = "linux")]
+pub(crate) fn pin_current_thread(index: usize) -> bool {
+    unsafe {
+        let size = std::mem::size_of::<libc::cpu_set_t>();
+        let mut allowed: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, size, &mut allowed) != 0 {
+            return false;
+        }
+        let cpus: Vec<usize> = (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &allowed))
+            .collect();
+        if cpus.is_empty() {
+            return false;
+        }
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpus[index % cpus.len()], &mut set);
+        libc::sched_setaffinity(0, size, &set) == 0
+    }
+}
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn GetCurrentThread() -> isize;
+    fn SetThreadAffinityMask(thread: isize, mask: usize) -> usize;
+}
+
+/// Restricts the current thread to the `index`-th CPU of its processor group, wrapping around.
+/// Returns false if the OS refused.
+#[cfg(windows)]
+pub(crate) fn pin_current_thread(index: usize) -> bool {
+    let cpus = std::thread::available_parallelism()
+        .map_or(1, |cpus| cpus.get())
+        .min(usize::BITS as usize);
+    let mask = 1 << (index % cpus);
+    unsafe { SetThreadAffinityMask(GetCurrentThread(), mask) != 0 }
+}
+
+/// Pinning isn't supported on this platform, threads run wherever the OS puts them.
+#[cfg(not(any(target_os = "linux", windows)))]
+pub(crate) fn pin_current_thread(_index: usize) -> bool {
+    false
+}
@@ -0,0 +1,238 @@
+use std::{
+    cell::UnsafeCell,
+    mem::{self, MaybeUninit},
+    ptr::{self, null_mut},
+    slice,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+/// Number of slots in the first bucket, every further bucket is twice as large.
+const FIRST_BUCKET: usize = 8;
+/// Enough buckets to address every index below `usize::MAX - FIRST_BUCKET`.
+const BUCKETS: usize = (usize::BITS - FIRST_BUCKET.trailing_zeros()) as usize;
+
+/// A concurrent append-only vector, e.g. an event log that producers append to while
+/// consumers read it at their own pace. A push reserves an index with a single fetch_add and
+/// writes its value into a bucket that never moves, so reads never wait for or block pushes.
+/// Buckets double in size and are allocated by the first push that needs them. An index can
+/// also be reserved first and written later, see [Appendus::reserve]. The fields are private,
+/// every index must be handed out and written exactly once.
+#[derive(Debug)]
+pub struct Appendus<T> {
+    buckets: [AtomicPtr<Slot<T>>; BUCKETS],
+    /// Number of indices handed out, values below it may still be written.
+    reserved: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Slot<T> {
+    /// Set once the value is written, it is never changed after that.
+    ready: AtomicBool,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// An index of an [Appendus] handed out by [Appendus::reserve] and written by
+/// [Reservation::set], e.g. by a pool job that was given it when submitted, so the results of
+/// all jobs end up in submission order. Dropping it without a value leaves the index empty.
+#[derive(Debug)]
+pub struct Reservation<'a, T> {
+    log: &'a Appendus<T>,
+    index: usize,
+}
+
+/// Iterator over the published prefix of an [Appendus], see [Appendus::iter].
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    pub log: &'a Appendus<T>,
+    pub index: usize,
+}
+
+unsafe impl<T: Send> Send for Appendus<T> {}
+unsafe impl<T: Send + Sync> Sync for Appendus<T> {}
+
+/// Returns the bucket holding `index` and the position inside it.
+fn locate(index: usize) -> (usize, usize) {
+    let position = index.checked_add(FIRST_BUCKET).expect("index out of range");
+    let bucket = (position.ilog2() - FIRST_BUCKET.ilog2()) as usize;
+    (bucket, position - (FIRST_BUCKET << bucket))
+}
+
+impl<T> Appendus<T> {
+    /// Creates a new empty vector.
+    pub fn new() -> Self {
+        Appendus {
+            buckets: [const { AtomicPtr::new(null_mut()) }; BUCKETS],
+            reserved: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends `value` and returns its index. Lock-free, pushes only contend on the index
+    /// counter and on allocating a new bucket.
+    pub fn push(&self, value: T) -> usize {
+        let index = self.reserved.fetch_add(1, Ordering::Relaxed);
+        // the index was just handed out to this thread
+        unsafe { self.write(index, value) };
+        index
+    }
+
+    /// Hands out the next index without writing it, [Appendus::get] returns [None] for it and
+    /// iterations stop there until [Reservation::set] publishes its value.
+    pub fn reserve(&self) -> Reservation<'_, T> {
+        Reservation {
+            log: self,
+            index: self.reserved.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Publishes `value` at `index`.
+    ///
+    /// # Safety
+    /// `index` must have been handed out by [Appendus::push] or [Appendus::reserve] and be
+    /// written only once.
+    unsafe fn write(&self, index: usize, value: T) {
+        let (bucket, offset) = locate(index);
+        let slots = self.bucket(bucket);
+        let slot = &*slots.add(offset);
+        // nobody reads the slot before ready is set
+        (*slot.value.get()).write(value);
+        slot.ready.store(true, Ordering::Release);
+    }
+
+    /// Returns the value at `index`, or [None] if it is not published yet. Wait-free.
+    pub fn get(&self, index: usize) -> Option<&T> {
+        let (bucket, offset) = locate(index);
+        let slots = self.buckets[bucket].load(Ordering::Acquire);
+        if slots.is_null() {
+            return None;
+        }
+        let slot = unsafe { &*slots.add(offset) };
+        if !slot.ready.load(Ordering::Acquire) {
+            return None;
+        }
+        Some(unsafe { (*slot.value.get()).assume_init_ref() })
+    }
+
+    /// Returns the number of pushes and reservations that started, values near the end may not
+    /// be published yet.
+    pub fn len(&self) -> usize {
+        self.reserved.load(Ordering::Acquire)
+    }
+
+    /// Returns true if nothing was pushed yet.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the values from index zero up to the first one that is not published
+    /// yet, so a consumer never skips an entry that is still being written.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            log: self,
+            index: 0,
+        }
+    }
+
+    /// Consumes the vector and returns its values in index order. Indices that were reserved
+    /// but never written are skipped.
+    pub fn into_vec(mut self) -> Vec<T> {
+        let mut values = Vec::with_capacity(*self.reserved.get_mut());
+        for (bucket, slots) in self.buckets.iter_mut().enumerate() {
+            let slots = *slots.get_mut();
+            if slots.is_null() {
+                continue;
+            }
+            let slots = unsafe { slice::from_raw_parts_mut(slots, FIRST_BUCKET << bucket) };
+            for slot in slots {
+                if mem::take(slot.ready.get_mut()) {
+                    // ready is cleared, so the drop of self frees only the bucket
+                    values.push(unsafe { slot.value.get_mut().assume_init_read() });
+                }
+            }
+        }
+        values
+    }
+
+    /// Returns the slots of `bucket`, allocating them if no push did yet.
+    fn bucket(&self, bucket: usize) -> *mut Slot<T> {
+        let slots = self.buckets[bucket].load(Ordering::Acquire);
+        if !slots.is_null() {
+            return slots;
+        }
+        let new_slots = Box::into_raw(
+            (0..FIRST_BUCKET << bucket)
+                .map(|_| Slot {
+                    ready: AtomicBool::new(false),
+                    value: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect::<Box<[Slot<T>]>>(),
+        )
+        .cast::<Slot<T>>();
+        match self.buckets[bucket].compare_exchange(
+            null_mut(),
+            new_slots,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => new_slots,
+            Err(current) => {
+                // another push allocated it first
+                drop(unsafe { Self::bucket_box(new_slots, bucket) });
+                current
+            }
+        }
+    }
+
+    /// Takes back ownership of the slots of `bucket`.
+    ///
+    /// # Safety
+    /// `slots` must be the allocation of `bucket` and not be used afterwards.
+    unsafe fn bucket_box(slots: *mut Slot<T>, bucket: usize) -> Box<[Slot<T>]> {
+        Box::from_raw(ptr::slice_from_raw_parts_mut(slots, FIRST_BUCKET << bucket))
+    }
+}
+
+impl<T> Default for Appendus<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Reservation<'_, T> {
+    /// Returns the reserved index.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Publishes `value` at the reserved index.
+    pub fn set(self, value: T) {
+        // the reservation is consumed, so its index is written once
+        unsafe { self.log.write(self.index, value) };
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let value = self.log.get(self.index)?;
+        self.index += 1;
+        Some(value)
+    }
+}
+
+impl<T> Drop for Appendus<T> {
+    fn drop(&mut self) {
+        for (bucket, slots) in self.buckets.iter_mut().enumerate() {
+            let slots = *slots.get_mut();
+            if slots.is_null() {
+                continue;
+            }
+            let mut slots = unsafe { Self::bucket_box(slots, bucket) };
+            for slot in slots.iter_mut() {
+                if *slot.ready.get_mut() {
+                    unsafe { slot.value.get_mut().assume_init_drop() };
+                }
+            }
+        }
+    }
+}
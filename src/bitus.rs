@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const WORD_BITS: usize = u64::BITS as usize;
+
+/// A lock-free fixed size bitset. Bits are packed into atomic words, so every operation on a
+/// single bit is one atomic instruction. Searches and iteration look at each word atomically,
+/// but not at the whole set at once, so they may miss changes made while they run.
+#[derive(Debug)]
+pub struct Bitus {
+    pub words: Vec<AtomicU64>,
+    pub len: usize,
+}
+
+/// Iterator over the indices of set bits, created by [Bitus::iter].
+#[derive(Debug)]
+pub struct SetBits<'a> {
+    pub bitset: &'a Bitus,
+    pub word_index: usize,
+    pub word: u64,
+}
+
+impl Bitus {
+    /// Creates a new bitset with `len` bits, all of them cleared.
+    pub fn new(len: usize) -> Self {
+        Bitus {
+            words: (0..len.div_ceil(WORD_BITS))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            len,
+        }
+    }
+
+    /// Sets the bit at `index`, returns its previous state.
+    pub fn set(&self, index: usize) -> bool {
+        let (word, mask) = self.locate(index);
+        word.fetch_or(mask, Ordering::AcqRel) & mask != 0
+    }
+
+    /// Clears the bit at `index`, returns its previous state.
+    pub fn clear(&self, index: usize) -> bool {
+        let (word, mask) = self.locate(index);
+        word.fetch_and(!mask, Ordering::AcqRel) & mask != 0
+    }
+
+    /// Returns true if the bit at `index` is set.
+    pub fn test(&self, index: usize) -> bool {
+        let (word, mask) = self.locate(index);
+        word.load(Ordering::Acquire) & mask != 0
+    }
+
+    /// Returns the index of the first cleared bit, or [None] if all bits are set.
+    pub fn find_first_zero(&self) -> Option<usize> {
+        self.words.iter().enumerate().find_map(|(i, word)| {
+            let current = word.load(Ordering::Acquire);
+            let index = i * WORD_BITS + current.trailing_ones() as usize;
+            (current != u64::MAX && index < self.len).then_some(index)
+        })
+    }
+
+    /// Finds the first cleared bit and sets it in one step, returns its index or [None] if all
+    /// bits are set. Two threads never get the same index, which makes it usable for slot allocation.
+    pub fn acquire_first_zero(&self) -> Option<usize> {
+        for (i, word) in self.words.iter().enumerate() {
+            let mut current = word.load(Ordering::Acquire);
+            loop {
+                let index = i * WORD_BITS + current.trailing_ones() as usize;
+                if current == u64::MAX || index >= self.len {
+                    break;
+                }
+                let mask = 1 << (index % WORD_BITS);
+                match word.compare_exchange_weak(
+                    current,
+                    current | mask,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => return Some(index),
+                    Err(actual) => current = actual,
+                }
+            }
+        }
+        None
+    }
+
+    /// Returns the number of set bits.
+    pub fn count_ones(&self) -> usize {
+        self.words
+            .iter()
+            .map(|word| word.load(Ordering::Acquire).count_ones() as usize)
+            .sum()
+    }
+
+    /// Returns an iterator over the indices of set bits in ascending order.
+    pub fn iter(&self) -> SetBits<'_> {
+        SetBits {
+            bitset: self,
+            word_index: 0,
+            word: self
+                .words
+                .first()
+                .map_or(0, |word| word.load(Ordering::Acquire)),
+        }
+    }
+
+    /// Returns the number of bits in the set.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the set holds no bits at all.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn locate(&self, index: usize) -> (&AtomicU64, u64) {
+        assert!(
+            index < self.len,
+            "index {index} out of range for {} bits",
+            self.len
+        );
+        (&self.words[index / WORD_BITS], 1 << (index % WORD_BITS))
+    }
+}
+
+impl Iterator for SetBits<'_> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        while self.word == 0 {
+            self.word_index += 1;
+            self.word = self
+                .bitset
+                .words
+                .get(self.word_index)?
+                .load(Ordering::Acquire);
+        }
+        let bit = self.word.trailing_zeros() as usize;
+        // clear the lowest set bit of the snapshot
+        self.word &= self.word - 1;
+        Some(self.word_index * WORD_BITS + bit)
+    }
+}
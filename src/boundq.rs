@@ -0,0 +1,246 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::Instant,
+};
+
+/// A lock-based bounded queue. Based on the same book as [crate::multiq::Multiq], but holds
+/// at most `capacity` values, producers block while it is full and consumers block while it is empty.
+/// Unlike Multiq it uses a single lock guarding a ring buffer, with one condvar per direction.
+#[derive(Debug, Clone)]
+pub struct Boundq<T> {
+    pub queue: Arc<InnerBoundq<T>>,
+}
+
+#[derive(Debug)]
+pub struct InnerBoundq<T> {
+    pub not_empty: Condvar,
+    pub not_full: Condvar,
+    pub values: Mutex<VecDeque<T>>,
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// Number of values [Boundq::push] dropped because the queue was full.
+    pub dropped: AtomicU64,
+}
+
+/// What [Boundq::push] does when the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait until a consumer made room.
+    #[default]
+    Block,
+    /// Leave the queue as it is and give the value back from [Boundq::push_with_policy].
+    Fail,
+    /// Drop the value at the front to make room, e.g. telemetry where new samples matter more.
+    DropOldest,
+    /// Drop the pushed value.
+    DropNewest,
+}
+
+impl<T> Boundq<T> {
+    /// Creates a new queue that holds at most `capacity` values.
+    pub fn new(capacity: usize) -> Boundq<T> {
+        Self::with_overflow(capacity, OverflowPolicy::Block)
+    }
+
+    /// Creates a new queue that holds at most `capacity` values and handles pushes to a full
+    /// queue according to `overflow`.
+    pub fn with_overflow(capacity: usize, overflow: OverflowPolicy) -> Boundq<T> {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        Boundq {
+            queue: InnerBoundq {
+                not_empty: Condvar::new(),
+                not_full: Condvar::new(),
+                values: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+                overflow,
+                dropped: AtomicU64::new(0),
+            }
+            .into(),
+        }
+    }
+
+    /// Pushes a value into the back of the queue. If it is full, waits for free space or drops
+    /// a value as the [OverflowPolicy] of the queue says, [OverflowPolicy::Fail] drops the
+    /// pushed value like [OverflowPolicy::DropNewest]. Dropped values are counted in
+    /// [Boundq::dropped].
+    pub fn push(&self, value: T) {
+        if self.queue.overflow == OverflowPolicy::Block {
+            return self.push_blocking(value);
+        }
+        if !matches!(self.push_with_policy(value), Ok(None)) {
+            self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Pushes a value into the back of the queue according to the [OverflowPolicy] and hands
+    /// back whatever didn't stay in the queue: `Ok(Some(oldest))` if the oldest value made
+    /// room, `Err(value)` if the pushed value was rejected. Never waits, with
+    /// [OverflowPolicy::Block] it rejects like [Boundq::try_push].
+    pub fn push_with_policy(&self, value: T) -> Result<Option<T>, T> {
+        let mut values = self.queue.values.lock().expect("lock acquire failed");
+        let mut evicted = None;
+        if values.len() == self.queue.capacity {
+            if self.queue.overflow != OverflowPolicy::DropOldest {
+                return Err(value);
+            }
+            evicted = values.pop_front();
+        }
+        values.push_back(value);
+        drop(values);
+        self.queue.not_empty.notify_one();
+        Ok(evicted)
+    }
+
+    /// Returns the number of values [Boundq::push] dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    fn push_blocking(&self, value: T) {
+        let mut values = self.queue.values.lock().expect("lock acquire failed");
+        while values.len() == self.queue.capacity {
+            values = self
+                .queue
+                .not_full
+                .wait(values)
+                .expect("lock acquire failed");
+        }
+        values.push_back(value);
+        drop(values);
+        self.queue.not_empty.notify_one();
+    }
+
+    /// Pushes a value into the back of the queue, or gives it back if the queue is full.
+    pub fn try_push(&self, value: T) -> Result<(), T> {
+        let mut values = self.queue.values.lock().expect("lock acquire failed");
+        if values.len() == self.queue.capacity {
+            return Err(value);
+        }
+        values.push_back(value);
+        drop(values);
+        self.queue.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Pushes a value into the back of the queue, waiting for free space until `deadline`.
+    /// Gives the value back if the queue is still full when the deadline passes.
+    pub fn push_deadline(&self, value: T, deadline: Instant) -> Result<(), T> {
+        let mut values = self.queue.values.lock().expect("lock acquire failed");
+        while values.len() == self.queue.capacity {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(value);
+            }
+            values = self
+                .queue
+                .not_full
+                .wait_timeout(values, deadline - now)
+                .expect("lock acquire failed")
+                .0;
+        }
+        values.push_back(value);
+        drop(values);
+        self.queue.not_empty.notify_one();
+        Ok(())
+    }
+
+    /// Takes a value from the front of the queue, waiting for one to be pushed if it is empty.
+    pub fn pop(&self) -> T {
+        let mut values = self.queue.values.lock().expect("lock acquire failed");
+        loop {
+            if let Some(value) = values.pop_front() {
+                drop(values);
+                self.queue.not_full.notify_one();
+                return value;
+            }
+            values = self
+                .queue
+                .not_empty
+                .wait(values)
+                .expect("lock acquire failed");
+        }
+    }
+
+    /// Takes up to `n` values from the front of the queue under a single lock acquisition,
+    /// waiting for at least one if it is empty, e.g. for a consumer that writes batches to a
+    /// database. Returns an empty batch only if `n` is zero.
+    pub fn pop_up_to(&self, n: usize) -> Vec<T> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let mut values = self.queue.values.lock().expect("lock acquire failed");
+        while values.is_empty() {
+            values = self
+                .queue
+                .not_empty
+                .wait(values)
+                .expect("lock acquire failed");
+        }
+        let count = n.min(values.len());
+        let batch: Vec<T> = values.drain(..count).collect();
+        drop(values);
+        // every freed slot can take a waiting producer
+        if count == 1 {
+            self.queue.not_full.notify_one();
+        } else {
+            self.queue.not_full.notify_all();
+        }
+        batch
+    }
+
+    /// Takes a value from the front of the queue, or [None] if it is empty.
+    pub fn try_pop(&self) -> Option<T> {
+        let value = self
+            .queue
+            .values
+            .lock()
+            .expect("lock acquire failed")
+            .pop_front();
+        if value.is_some() {
+            self.queue.not_full.notify_one();
+        }
+        value
+    }
+
+    /// Takes a value from the front of the queue, waiting for one until `deadline`.
+    /// Returns [None] if the queue is still empty when the deadline passes.
+    pub fn pop_deadline(&self, deadline: Instant) -> Option<T> {
+        let mut values = self.queue.values.lock().expect("lock acquire failed");
+        loop {
+            if let Some(value) = values.pop_front() {
+                drop(values);
+                self.queue.not_full.notify_one();
+                return Some(value);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            values = self
+                .queue
+                .not_empty
+                .wait_timeout(values, deadline - now)
+                .expect("lock acquire failed")
+                .0;
+        }
+    }
+
+    /// Returns the number of values in the queue.
+    pub fn len(&self) -> usize {
+        self.queue.values.lock().expect("lock acquire failed").len()
+    }
+
+    /// Returns true if the queue contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of values the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A lock-based broadcast queue. Unlike [crate::multiq::Multiq], where consumers compete
+/// for values, every subscriber receives every value pushed after it subscribed.
+/// Values are kept in a fixed size ring buffer, each subscriber keeps its own cursor into it.
+/// A subscriber that falls behind by more than the capacity of the ring loses the oldest
+/// values and is told how many were skipped via [Lagged].
+#[derive(Debug, Clone)]
+pub struct Broadcastus<T: Clone> {
+    pub ring: Arc<InnerBroadcastus<T>>,
+}
+
+#[derive(Debug)]
+pub struct InnerBroadcastus<T: Clone> {
+    pub cvar: Condvar,
+    pub slots: Mutex<Ring<T>>,
+}
+
+#[derive(Debug)]
+pub struct Ring<T: Clone> {
+    pub values: Vec<Option<T>>,
+    /// Total number of values ever pushed, position of the next write is `written % capacity`.
+    pub written: u64,
+}
+
+/// Receiving half of [Broadcastus], created by [Broadcastus::subscribe].
+#[derive(Debug, Clone)]
+pub struct Subscriber<T: Clone> {
+    pub ring: Arc<InnerBroadcastus<T>>,
+    pub cursor: u64,
+}
+
+/// Returned when a subscriber fell behind and the ring overwrote values it did not read yet.
+/// Holds the number of skipped values, the subscriber continues from the oldest value still kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lagged(pub u64);
+
+impl std::fmt::Display for Lagged {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "subscriber lagged behind by {} values", self.0)
+    }
+}
+
+impl std::error::Error for Lagged {}
+
+impl<T: Clone> Broadcastus<T> {
+    /// Creates a new broadcast queue which keeps up to `capacity` values for slow subscribers.
+    pub fn new(capacity: usize) -> Broadcastus<T> {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        Broadcastus {
+            ring: InnerBroadcastus {
+                cvar: Condvar::new(),
+                slots: Mutex::new(Ring {
+                    values: vec![None; capacity],
+                    written: 0,
+                }),
+            }
+            .into(),
+        }
+    }
+
+    /// Creates a new subscriber which will receive every value pushed from now on.
+    pub fn subscribe(&self) -> Subscriber<T> {
+        let ring = self.ring.slots.lock().expect("lock acquire failed");
+        Subscriber {
+            ring: self.ring.clone(),
+            cursor: ring.written,
+        }
+    }
+
+    /// Pushes a value to all subscribers, overwriting the oldest one if the ring is full.
+    pub fn push(&self, value: T) {
+        let mut ring = self.ring.slots.lock().expect("lock acquire failed");
+        let capacity = ring.values.len() as u64;
+        let position = (ring.written % capacity) as usize;
+        ring.values[position] = Some(value);
+        ring.written += 1;
+        drop(ring);
+        self.ring.cvar.notify_all();
+    }
+}
+
+impl<T: Clone> Subscriber<T> {
+    /// Takes the next value for this subscriber, or [None] if it has seen everything pushed so far.
+    pub fn try_recv(&mut self) -> Result<Option<T>, Lagged> {
+        let ring = self.ring.slots.lock().expect("lock acquire failed");
+        ring.read(&mut self.cursor)
+    }
+
+    /// Takes the next value for this subscriber, waiting for it to be pushed if necessary.
+    pub fn recv(&mut self) -> Result<T, Lagged> {
+        let mut ring = self.ring.slots.lock().expect("lock acquire failed");
+        while ring.written == self.cursor {
+            ring = self.ring.cvar.wait(ring).expect("lock acquire failed");
+        }
+        // written is ahead of cursor so there is always a value to read
+        ring.read(&mut self.cursor).map(|value| value.unwrap())
+    }
+}
+
+impl<T: Clone> Ring<T> {
+    /// Reads the value at `cursor` and advances it, moving it forward first if it was overwritten.
+    fn read(&self, cursor: &mut u64) -> Result<Option<T>, Lagged> {
+        if self.written == *cursor {
+            return Ok(None);
+        }
+        let capacity = self.values.len() as u64;
+        let oldest = self.written.saturating_sub(capacity);
+        if *cursor < oldest {
+            // values were overwritten, skip to the oldest one still in the ring
+            let skipped = oldest - *cursor;
+            *cursor = oldest;
+            return Err(Lagged(skipped));
+        }
+        let value = self.values[(*cursor % capacity) as usize].clone();
+        *cursor += 1;
+        Ok(value)
+    }
+}
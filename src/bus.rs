@@ -0,0 +1,214 @@
+use crate::boundq::{Boundq, OverflowPolicy};
+use crate::spin::Backoff;
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, RwLock,
+    },
+    time::Instant,
+};
+
+/// An in-process publish/subscribe bus. Publishers send to dot separated topics such as
+/// `sensors.kitchen.temperature`, every subscription whose pattern matches gets a clone of the
+/// payload in its own bounded queue. In a pattern `*` matches exactly one segment and a final
+/// `#` matches any number of remaining segments, so `sensors.*.temperature` and `sensors.#`
+/// both match the topic above. What happens when a subscriber falls behind is chosen per
+/// subscription with [Backpressure], so a slow logger can lose old messages while a consumer
+/// that must see everything slows the publishers down instead.
+#[derive(Debug)]
+pub struct Bus<T> {
+    pub bus: Arc<InnerBus<T>>,
+}
+
+#[derive(Debug)]
+pub struct InnerBus<T> {
+    pub subscribers: RwLock<Vec<Arc<Subscriber<T>>>>,
+    pub next_id: AtomicU64,
+}
+
+#[derive(Debug)]
+pub struct Subscriber<T> {
+    pub id: u64,
+    pub pattern: Vec<String>,
+    /// Follows the [Backpressure] of the subscription.
+    pub queue: Boundq<Message<T>>,
+    /// Set when the subscription is dropped, publishes skip the subscriber from then on.
+    pub dead: AtomicBool,
+    /// Publishes pushing into the queue right now, the drop of the subscription drains the
+    /// queue until they are done.
+    pub publishing: AtomicUsize,
+}
+
+/// What a publish does when a matching subscriber's queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until the subscriber made room.
+    Block,
+    /// Drop the oldest queued message to make room.
+    DropOldest,
+}
+
+/// A message received from a [Bus].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message<T> {
+    pub topic: Arc<str>,
+    pub payload: T,
+}
+
+/// Receives the messages of the topics matching its pattern, unsubscribes when dropped.
+#[derive(Debug)]
+pub struct Subscription<T> {
+    pub bus: Arc<InnerBus<T>>,
+    pub subscriber: Arc<Subscriber<T>>,
+}
+
+impl<T> Clone for Bus<T> {
+    fn clone(&self) -> Self {
+        Bus {
+            bus: self.bus.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Bus<T> {
+    /// Creates a new bus without subscribers.
+    pub fn new() -> Self {
+        Bus {
+            bus: InnerBus {
+                subscribers: RwLock::new(Vec::new()),
+                next_id: AtomicU64::new(0),
+            }
+            .into(),
+        }
+    }
+
+    /// Subscribes to the topics matching `pattern`, with a queue of up to `capacity` messages.
+    pub fn subscribe(
+        &self,
+        pattern: &str,
+        capacity: usize,
+        backpressure: Backpressure,
+    ) -> Subscription<T> {
+        let subscriber = Arc::new(Subscriber {
+            id: self.bus.next_id.fetch_add(1, Ordering::Relaxed),
+            pattern: pattern.split('.').map(str::to_owned).collect(),
+            queue: Boundq::with_overflow(
+                capacity,
+                match backpressure {
+                    Backpressure::Block => OverflowPolicy::Block,
+                    Backpressure::DropOldest => OverflowPolicy::DropOldest,
+                },
+            ),
+            dead: AtomicBool::new(false),
+            publishing: AtomicUsize::new(0),
+        });
+        self.bus
+            .subscribers
+            .write()
+            .expect("lock acquire failed")
+            .push(subscriber.clone());
+        Subscription {
+            bus: self.bus.clone(),
+            subscriber,
+        }
+    }
+
+    /// Sends `payload` to every subscription matching `topic`, returns how many there were.
+    /// Waits for subscribers with [Backpressure::Block] and a full queue.
+    pub fn publish(&self, topic: &str, payload: T) -> usize {
+        let segments: Vec<_> = topic.split('.').collect();
+        // collected first, so a blocked publish doesn't keep subscriptions from being dropped
+        let matching: Vec<_> = self
+            .bus
+            .subscribers
+            .read()
+            .expect("lock acquire failed")
+            .iter()
+            .filter(|subscriber| matches(&subscriber.pattern, &segments))
+            .cloned()
+            .collect();
+        let topic: Arc<str> = topic.into();
+        for subscriber in &matching {
+            // counted before the check, so a drop that sets dead afterwards waits for the push
+            subscriber.publishing.fetch_add(1, Ordering::SeqCst);
+            if !subscriber.dead.load(Ordering::SeqCst) {
+                subscriber.queue.push(Message {
+                    topic: topic.clone(),
+                    payload: payload.clone(),
+                });
+            }
+            subscriber.publishing.fetch_sub(1, Ordering::SeqCst);
+        }
+        matching.len()
+    }
+
+    /// Returns the number of subscriptions.
+    pub fn subscribers(&self) -> usize {
+        self.bus
+            .subscribers
+            .read()
+            .expect("lock acquire failed")
+            .len()
+    }
+}
+
+impl<T: Clone> Default for Bus<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Subscription<T> {
+    /// Waits for the next message.
+    pub fn recv(&self) -> Message<T> {
+        self.subscriber.queue.pop()
+    }
+
+    /// Returns the next message, or [None] if none is queued.
+    pub fn try_recv(&self) -> Option<Message<T>> {
+        self.subscriber.queue.try_pop()
+    }
+
+    /// Waits for the next message until `deadline`, returns [None] if none arrived in time.
+    pub fn recv_deadline(&self, deadline: Instant) -> Option<Message<T>> {
+        self.subscriber.queue.pop_deadline(deadline)
+    }
+
+    /// Returns the number of messages dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.subscriber.queue.dropped()
+    }
+}
+
+impl<T> Drop for Subscription<T> {
+    fn drop(&mut self) {
+        self.bus
+            .subscribers
+            .write()
+            .expect("lock acquire failed")
+            .retain(|subscriber| subscriber.id != self.subscriber.id);
+        // publishes that matched before the removal may still be blocked on the full queue,
+        // the queue is drained until the last of them got its message in
+        self.subscriber.dead.store(true, Ordering::SeqCst);
+        let mut backoff = Backoff::new();
+        loop {
+            while self.subscriber.queue.try_pop().is_some() {}
+            if self.subscriber.publishing.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            backoff.snooze();
+        }
+    }
+}
+
+/// Returns true if the topic `segments` match the `pattern` segments.
+fn matches(pattern: &[String], segments: &[&str]) -> bool {
+    match (pattern.split_first(), segments.split_first()) {
+        (Some((wildcard, [])), _) if wildcard == "#" => true,
+        (Some((expected, pattern)), Some((segment, segments))) => {
+            (expected == "*" || expected == segment) && matches(pattern, segments)
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::VecDeque,
+    ops::Deref,
+    ptr, slice,
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+};
+
+/// Emptied segments kept for reuse, more are given back to the allocator.
+const MAX_SPARE_SEGMENTS: usize = 8;
+
+/// A queue of byte messages for workloads like network proxies that move many small
+/// payloads. Pushed bytes are copied into shared segment buffers of `segment_size` bytes
+/// instead of a separate allocation per message, consumers get a [Bytes] guard that reads
+/// the message in place. A segment is reused once every message in it was popped and its
+/// guards are dropped, messages larger than a segment get a segment of their own.
+#[derive(Debug, Clone)]
+pub struct BytesQueue {
+    pub queue: Arc<InnerBytesQueue>,
+}
+
+#[derive(Debug)]
+pub struct InnerBytesQueue {
+    pub state: Mutex<Segments>,
+    pub not_empty: Condvar,
+    pub segment_size: usize,
+}
+
+#[derive(Debug)]
+pub struct Segments {
+    pub messages: VecDeque<Bytes>,
+    /// The segment being written to and the offset of its first unused byte.
+    current: Option<(Arc<Segment>, usize)>,
+    pub spare: Vec<Box<[u8]>>,
+}
+
+/// A buffer holding the bytes of several messages. Producers write unused parts under the
+/// queue lock, [Bytes] guards read the parts written before, so reads and writes never
+/// overlap. Its fields are private, writing through them would overlap reads.
+#[derive(Debug)]
+pub struct Segment {
+    data: *mut u8,
+    capacity: usize,
+    /// The queue that gets the buffer back when the segment is dropped.
+    pub queue: Weak<InnerBytesQueue>,
+}
+
+/// A message popped from a [BytesQueue], derefs to its bytes. The range of the message is
+/// private, the bytes around it may still be written.
+#[derive(Debug, Clone)]
+pub struct Bytes {
+    pub segment: Arc<Segment>,
+    start: usize,
+    len: usize,
+}
+
+unsafe impl Send for Segment {}
+unsafe impl Sync for Segment {}
+
+impl BytesQueue {
+    /// Creates a new empty queue that copies messages into segments of `segment_size` bytes.
+    pub fn new(segment_size: usize) -> Self {
+        assert!(segment_size > 0, "segment size must be greater than zero");
+        BytesQueue {
+            queue: InnerBytesQueue {
+                state: Mutex::new(Segments {
+                    messages: VecDeque::new(),
+                    current: None,
+                    spare: Vec::new(),
+                }),
+                not_empty: Condvar::new(),
+                segment_size,
+            }
+            .into(),
+        }
+    }
+
+    /// Copies `bytes` into the back of the queue.
+    pub fn push(&self, bytes: &[u8]) {
+        let mut state = self.state();
+        let mut retired = None;
+        let fits = state
+            .current
+            .as_ref()
+            .is_some_and(|(segment, used)| segment.capacity - used >= bytes.len());
+        if !fits {
+            let buffer = if bytes.len() > self.queue.segment_size {
+                vec![0; bytes.len()].into_boxed_slice()
+            } else {
+                state
+                    .spare
+                    .pop()
+                    .unwrap_or_else(|| vec![0; self.queue.segment_size].into_boxed_slice())
+            };
+            let segment = Segment::new(buffer, Arc::downgrade(&self.queue));
+            retired = state.current.replace((segment.into(), 0));
+        }
+        let (segment, used) = state.current.as_mut().expect("a segment was just added");
+        // the range after used is not handed out yet, so no guard reads it
+        unsafe { ptr::copy_nonoverlapping(bytes.as_ptr(), segment.data.add(*used), bytes.len()) };
+        let message = Bytes {
+            segment: segment.clone(),
+            start: *used,
+            len: bytes.len(),
+        };
+        *used += bytes.len();
+        state.messages.push_back(message);
+        drop(state);
+        self.queue.not_empty.notify_one();
+        // dropping the last reference recycles the segment, which takes the lock
+        drop(retired);
+    }
+
+    /// Takes the message at the front of the queue, or returns [None] if it is empty.
+    pub fn pop(&self) -> Option<Bytes> {
+        self.state().messages.pop_front()
+    }
+
+    /// Takes the message at the front of the queue, waiting for one to be pushed if it is
+    /// empty.
+    pub fn wait_and_pop(&self) -> Bytes {
+        let mut state = self.state();
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                return message;
+            }
+            state = self
+                .queue
+                .not_empty
+                .wait(state)
+                .expect("lock acquire failed");
+        }
+    }
+
+    /// Returns the number of messages in the queue.
+    pub fn len(&self) -> usize {
+        self.state().messages.len()
+    }
+
+    /// Returns true if the queue contains no messages.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn state(&self) -> MutexGuard<'_, Segments> {
+        self.queue.state.lock().expect("lock acquire failed")
+    }
+}
+
+impl Segment {
+    fn new(buffer: Box<[u8]>, queue: Weak<InnerBytesQueue>) -> Self {
+        let capacity = buffer.len();
+        Segment {
+            data: Box::into_raw(buffer).cast(),
+            capacity,
+            queue,
+        }
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        let buffer =
+            unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(self.data, self.capacity)) };
+        let Some(queue) = self.queue.upgrade() else {
+            return;
+        };
+        if buffer.len() != queue.segment_size {
+            return;
+        }
+        let mut state = queue.state.lock().expect("lock acquire failed");
+        if state.spare.len() < MAX_SPARE_SEGMENTS {
+            state.spare.push(buffer);
+        }
+    }
+}
+
+impl Deref for Bytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // written before the message was queued, producers only write after it
+        unsafe { slice::from_raw_parts(self.segment.data.add(self.start), self.len) }
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+};
+
+/// Callback run once when a token is cancelled.
+type OnCancel = Box<dyn FnOnce() + Send>;
+
+/// A cooperative cancellation flag shared by clones. Blocking operations that accept a token
+/// register a wake-up callback with it, so a [CancellationToken::cancel] from any thread
+/// releases them and they return [Cancelled]. Cancelling a token also cancels every token
+/// created from it with [CancellationToken::child_token], but not the other way around.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    pub inner: Arc<InnerToken>,
+}
+
+#[derive(Default)]
+pub struct InnerToken {
+    pub cancelled: AtomicBool,
+    pub next_id: AtomicU64,
+    pub callbacks: Mutex<Vec<(u64, OnCancel)>>,
+    pub children: Mutex<Vec<Weak<InnerToken>>>,
+}
+
+/// Keeps a callback registered with [CancellationToken::on_cancel], removes it when dropped.
+#[derive(Debug)]
+pub struct CancelGuard<'a> {
+    pub token: &'a CancellationToken,
+    pub id: u64,
+}
+
+/// Error returned by blocking operations that were cancelled through a [CancellationToken].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl CancellationToken {
+    /// Creates a new token that is not cancelled.
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Creates a token that is cancelled together with this one, but can also be cancelled
+    /// on its own.
+    pub fn child_token(&self) -> CancellationToken {
+        let child = CancellationToken::new();
+        let mut children = self.inner.children.lock().expect("lock acquire failed");
+        if self.is_cancelled() {
+            drop(children);
+            child.cancel();
+        } else {
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.inner));
+        }
+        child
+    }
+
+    /// Cancels the token and its children, running every registered callback.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        // callbacks registered after the swap see the flag and run themselves
+        let callbacks =
+            std::mem::take(&mut *self.inner.callbacks.lock().expect("lock acquire failed"));
+        for (_, callback) in callbacks {
+            callback();
+        }
+        let children =
+            std::mem::take(&mut *self.inner.children.lock().expect("lock acquire failed"));
+        for child in children.iter().filter_map(Weak::upgrade) {
+            CancellationToken { inner: child }.cancel();
+        }
+    }
+
+    /// Returns true if the token or one of its ancestors was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Returns [Cancelled] if the token was cancelled, for use with `?` in long running work.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Runs `callback` when the token is cancelled, or right away if it already is.
+    /// The callback is removed without running when the returned guard is dropped.
+    pub fn on_cancel<F: FnOnce() + Send + 'static>(&self, callback: F) -> CancelGuard<'_> {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut callbacks = self.inner.callbacks.lock().expect("lock acquire failed");
+        if self.is_cancelled() {
+            drop(callbacks);
+            callback();
+        } else {
+            callbacks.push((id, Box::new(callback)));
+        }
+        CancelGuard { token: self, id }
+    }
+}
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        self.token
+            .inner
+            .callbacks
+            .lock()
+            .expect("lock acquire failed")
+            .retain(|(id, _)| *id != self.id);
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
@@ -0,0 +1,56 @@
+#[cfg(feature = "chaos")]
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::Duration,
+};
+
+#[cfg(feature = "chaos")]
+static SEED: AtomicU64 = AtomicU64::new(0x9e37_79b9_7f4a_7c15);
+#[cfg(feature = "chaos")]
+static THREADS: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "chaos")]
+thread_local! {
+    static STATE: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Sets the seed of the yield injector, threads that hit their first yield point afterwards
+/// derive their random sequence from it. The OS still schedules threads, so a seed that made
+/// a test fail makes the same interleaving likely again but not certain.
+#[cfg(feature = "chaos")]
+pub fn set_seed(seed: u64) {
+    SEED.store(seed, Ordering::SeqCst);
+}
+
+/// Randomly yields or briefly sleeps, called at the points where the structures race with
+/// each other, e.g. between registering a waiter and parking. With the `chaos` feature tests
+/// explore interleavings plain threads rarely hit, like a push landing in the middle of a
+/// consumer going to sleep. Without it this compiles to nothing.
+#[cfg(feature = "chaos")]
+pub(crate) fn yield_point() {
+    let random = STATE.with(|state| {
+        let mut x = state.get();
+        if x == 0 {
+            let thread = THREADS.fetch_add(1, Ordering::Relaxed);
+            // spread consecutive thread numbers over the bits, xorshift never leaves zero
+            x = SEED.load(Ordering::SeqCst) ^ thread.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+        }
+        // xorshift64
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        state.set(x);
+        x
+    });
+    match random % 8 {
+        0 | 1 => thread::yield_now(),
+        2 => thread::sleep(Duration::from_micros(random >> 58)),
+        _ => {}
+    }
+}
+
+#[cfg(not(feature = "chaos"))]
+#[inline(always)]
+pub(crate) fn yield_point() {}
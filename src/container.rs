@@ -0,0 +1,146 @@
+use crate::boundq::Boundq;
+use crate::dequeus::Dequeus;
+use crate::inline_stackus::InlineStackus;
+use crate::lock::RawLock;
+use crate::multiq::Multiq;
+use crate::stackus::Stackus;
+
+/// A last in, first out collection any number of threads can use at once, for code and
+/// benchmarks written once for every stack in the crate. `len` is only a snapshot while other
+/// threads push or pop.
+pub trait ConcurrentStack<T> {
+    /// Inserts a value at the top of the stack.
+    fn push(&self, value: T);
+
+    /// Removes the value at the top of the stack, or returns [None] if it is empty.
+    fn try_pop(&self) -> Option<T>;
+
+    /// Returns the number of values in the stack.
+    fn len(&self) -> usize;
+
+    /// Returns true if the stack contains no values.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A first in, first out collection any number of threads can use at once, the queue
+/// counterpart of [ConcurrentStack]. A bounded queue waits in `push` until there is room.
+pub trait ConcurrentQueue<T> {
+    /// Inserts a value at the back of the queue.
+    fn push(&self, value: T);
+
+    /// Removes the value at the front of the queue, or returns [None] if it is empty.
+    fn try_pop(&self) -> Option<T>;
+
+    /// Returns the number of values in the queue.
+    fn len(&self) -> usize;
+
+    /// Returns true if the queue contains no values.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Asserts that a container holds exactly the expected values, in the order its `to_vec`
+/// returns them: front to back for queues, top to bottom for stacks. The values are copied
+/// out, so the container is left as it was.
+#[macro_export]
+macro_rules! assert_contents_eq {
+    ($container:expr, $expected:expr $(,)?) => {
+        assert_eq!($container.to_vec(), $expected, "contents of {}", stringify!($container))
+    };
+    ($container:expr, $expected:expr, $($arg:tt)+) => {
+        assert_eq!($container.to_vec(), $expected, $($arg)+)
+    };
+}
+
+impl<T> ConcurrentStack<T> for Stackus<T> {
+    fn push(&self, value: T) {
+        Stackus::push(self, value)
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn len(&self) -> usize {
+        Stackus::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Stackus::is_empty(self)
+    }
+}
+
+impl<T, const N: usize> ConcurrentStack<T> for InlineStackus<T, N> {
+    fn push(&self, value: T) {
+        InlineStackus::push(self, value)
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        self.pop()
+    }
+
+    fn len(&self) -> usize {
+        self.inline_len() + self.spill.get().map_or(0, Stackus::len)
+    }
+
+    fn is_empty(&self) -> bool {
+        InlineStackus::is_empty(self)
+    }
+}
+
+impl<T, L: RawLock> ConcurrentQueue<T> for Multiq<T, L> {
+    fn push(&self, value: T) {
+        Multiq::push(self, value)
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        Multiq::pop(self)
+    }
+
+    fn len(&self) -> usize {
+        self.load_stats().depth
+    }
+
+    fn is_empty(&self) -> bool {
+        Multiq::is_empty(self)
+    }
+}
+
+impl<T> ConcurrentQueue<T> for Boundq<T> {
+    fn push(&self, value: T) {
+        Boundq::push(self, value)
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        Boundq::try_pop(self)
+    }
+
+    fn len(&self) -> usize {
+        Boundq::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Boundq::is_empty(self)
+    }
+}
+
+impl<T, L: RawLock> ConcurrentQueue<T> for Dequeus<T, L> {
+    fn push(&self, value: T) {
+        self.push_back(value)
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        self.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        Dequeus::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Dequeus::is_empty(self)
+    }
+}
@@ -0,0 +1,83 @@
+use crate::mapus::Mapus;
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Counts events per key from many threads, e.g. requests per endpoint. Built on [Mapus]
+/// with an atomic counter per key: counting a key that is already known only takes the read
+/// lock of its shard, so threads counting the same keys don't serialize on a write lock.
+/// Only the first event of a key locks its shard for writing.
+#[derive(Debug)]
+pub struct CounterMap<K> {
+    pub counts: Mapus<K, AtomicU64>,
+}
+
+impl<K: Hash + Eq> CounterMap<K> {
+    /// Creates a new map with no counts.
+    pub fn new() -> Self {
+        CounterMap {
+            counts: Mapus::new(),
+        }
+    }
+
+    /// Adds one to the count of `key`.
+    pub fn increment(&self, key: K) {
+        self.add(key, 1);
+    }
+
+    /// Adds `n` to the count of `key`.
+    pub fn add(&self, key: K, n: u64) {
+        let counted = self
+            .counts
+            .with(&key, |count| count.fetch_add(n, Ordering::Relaxed));
+        if counted.is_none() {
+            self.counts
+                .entry(key)
+                .or_insert_with(|| AtomicU64::new(0))
+                .fetch_add(n, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the count of `key`, zero if it was never counted.
+    pub fn get(&self, key: &K) -> u64 {
+        self.counts
+            .with(key, |count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    /// Collects the counts of all keys, one shard at a time. Increments that run meanwhile may
+    /// or may not be included.
+    pub fn snapshot(&self) -> HashMap<K, u64>
+    where
+        K: Clone,
+    {
+        let mut snapshot = HashMap::new();
+        for shard in &self.counts.shards {
+            let shard = shard.read().expect("lock acquire failed");
+            snapshot.extend(
+                shard
+                    .iter()
+                    .map(|(key, count)| (key.clone(), count.load(Ordering::Relaxed))),
+            );
+        }
+        snapshot
+    }
+
+    /// Returns the number of keys counted so far.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Returns true if nothing was counted yet.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+impl<K: Hash + Eq> Default for CounterMap<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,85 @@
+use crate::lock::{DefaultLock, Lock, RawLock};
+use std::{collections::VecDeque, sync::Arc};
+
+/// A lock-based double-ended queue that any number of threads can push to and pop from at
+/// both ends, e.g. FIFO for regular work with urgent items pushed to the front, or LIFO for
+/// cache-warm processing. A single lock of type `L` guards a ring buffer, so each operation
+/// is a few instructions under the lock.
+#[derive(Debug)]
+pub struct Dequeus<T, L: RawLock = DefaultLock> {
+    pub deque: Arc<Lock<VecDeque<T>, L>>,
+}
+
+impl<T, L: RawLock> Clone for Dequeus<T, L> {
+    fn clone(&self) -> Self {
+        Dequeus {
+            deque: self.deque.clone(),
+        }
+    }
+}
+
+impl<T> Dequeus<T> {
+    /// Creates a new empty deque.
+    pub fn new() -> Dequeus<T> {
+        Self::with_lock()
+    }
+}
+
+impl<T> Default for Dequeus<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, L: RawLock> Dequeus<T, L> {
+    /// Creates a new empty deque guarded by a lock of type `L`.
+    pub fn with_lock() -> Dequeus<T, L> {
+        Dequeus {
+            deque: Lock::new(VecDeque::new()).into(),
+        }
+    }
+
+    /// Inserts a value at the front.
+    pub fn push_front(&self, value: T) {
+        self.deque
+            .lock()
+            .expect("lock acquire failed")
+            .push_front(value);
+    }
+
+    /// Inserts a value at the back.
+    pub fn push_back(&self, value: T) {
+        self.deque
+            .lock()
+            .expect("lock acquire failed")
+            .push_back(value);
+    }
+
+    /// Removes the value at the front, or returns [None] if the deque is empty.
+    pub fn pop_front(&self) -> Option<T> {
+        self.deque.lock().expect("lock acquire failed").pop_front()
+    }
+
+    /// Removes the value at the back, or returns [None] if the deque is empty.
+    pub fn pop_back(&self) -> Option<T> {
+        self.deque.lock().expect("lock acquire failed").pop_back()
+    }
+
+    /// Removes the front half of the values, rounded up, and returns them front to back. Lets
+    /// an idle thread take over part of the work of another in a single lock acquisition.
+    pub fn steal_half(&self) -> Vec<T> {
+        let mut deque = self.deque.lock().expect("lock acquire failed");
+        let half = deque.len().div_ceil(2);
+        deque.drain(..half).collect()
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.deque.lock().expect("lock acquire failed").len()
+    }
+
+    /// Returns true if the deque contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.deque.lock().expect("lock acquire failed").is_empty()
+    }
+}
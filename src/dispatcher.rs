@@ -0,0 +1,153 @@
+use crate::multiq::Multiq;
+use std::{
+    hash::{BuildHasher, Hash, RandomState},
+    sync::{RwLock, RwLockReadGuard},
+};
+
+/// The queue behind one shard of a [Dispatcher], holds [Option]s so it can start out empty,
+/// only [Some] is pushed.
+pub type Shard<K, T> = Multiq<Option<(K, T)>>;
+
+/// Routes keyed values to one of several [Multiq]s, so each queue can have its own consumer
+/// and all values of a key are handled by the same one, in push order. Keys are spread with
+/// jump consistent hashing, so [Dispatcher::resize] only moves the keys that have to move:
+/// growing from n to n + 1 queues moves about 1 / (n + 1) of them to the new queue. Each queue
+/// is meant to have a single [DispatchConsumer].
+#[derive(Debug)]
+pub struct Dispatcher<K, T> {
+    pub queues: RwLock<Vec<Shard<K, T>>>,
+    pub hasher: RandomState,
+}
+
+/// Pops the values routed to one queue of a [Dispatcher].
+#[derive(Debug)]
+pub struct DispatchConsumer<'a, K, T> {
+    pub dispatcher: &'a Dispatcher<K, T>,
+    pub index: usize,
+}
+
+impl<K: Hash, T> Dispatcher<K, T> {
+    /// Creates a new dispatcher with `queues` empty queues.
+    pub fn new(queues: usize) -> Self {
+        assert!(queues > 0, "queues must be greater than zero");
+        Dispatcher {
+            queues: RwLock::new((0..queues).map(|_| Self::empty_queue()).collect()),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Pushes `value` to the back of the queue that `key` maps to.
+    pub fn push(&self, key: K, value: T) {
+        let queues = self.read_queues();
+        queues[self.route(&key, queues.len())].push(Some((key, value)));
+    }
+
+    /// Returns the index of the queue that `key` maps to with the current number of queues.
+    pub fn queue_of(&self, key: &K) -> usize {
+        self.route(key, self.queues())
+    }
+
+    /// Returns a consumer of the queue at `index`.
+    pub fn consumer(&self, index: usize) -> DispatchConsumer<'_, K, T> {
+        assert!(index < self.queues(), "no queue at index {index}");
+        DispatchConsumer {
+            dispatcher: self,
+            index,
+        }
+    }
+
+    /// Changes the number of queues to `queues` and moves the queued values whose key now
+    /// maps to another queue, keeping the values of each key in order. Pushes wait until the
+    /// values are moved. Removed queues are closed and their consumers get [None] from then on.
+    pub fn resize(&self, queues: usize) {
+        assert!(queues > 0, "queues must be greater than zero");
+        let mut current = self.queues.write().expect("lock acquire failed");
+        let mut moved = Vec::new();
+        for (index, queue) in current.iter().enumerate() {
+            if index >= queues {
+                moved.extend(queue.drain().flatten());
+                // wakes a waiting consumer to look its queue up again
+                queue.close();
+            } else {
+                // everything is taken out and the values that stay put back, so they keep
+                // their order and don't end up behind newer values of the same key
+                let (stay, go): (Vec<_>, Vec<_>) = queue
+                    .drain()
+                    .flatten()
+                    .partition(|(key, _)| self.route(key, queues) == index);
+                queue.push_all(stay.into_iter().map(Some));
+                moved.extend(go);
+            }
+        }
+        current.resize_with(queues, Self::empty_queue);
+        for (key, value) in moved {
+            let index = self.route(&key, queues);
+            current[index].push(Some((key, value)));
+        }
+    }
+
+    /// Returns the number of queues.
+    pub fn queues(&self) -> usize {
+        self.read_queues().len()
+    }
+
+    /// Returns true if no queue holds a value.
+    pub fn is_empty(&self) -> bool {
+        self.read_queues().iter().all(Multiq::is_empty)
+    }
+
+    fn route(&self, key: &K, queues: usize) -> usize {
+        jump_hash(self.hasher.hash_one(key), queues)
+    }
+
+    fn read_queues(&self) -> RwLockReadGuard<'_, Vec<Shard<K, T>>> {
+        self.queues.read().expect("lock acquire failed")
+    }
+
+    fn empty_queue() -> Shard<K, T> {
+        // a Multiq starts with a value, take it right away
+        let queue = Multiq::new(None);
+        queue.pop();
+        queue
+    }
+}
+
+impl<K: Hash, T> DispatchConsumer<'_, K, T> {
+    /// Removes the value at the front of the queue and returns it, or [None] if the queue is
+    /// empty or was removed by a resize.
+    pub fn pop(&self) -> Option<T> {
+        let (_, value) = self.queue()?.pop().flatten()?;
+        Some(value)
+    }
+
+    /// Waits until a value is routed to the queue and returns it, or [None] once the queue
+    /// is removed by a resize.
+    pub fn wait_and_pop(&self) -> Option<T> {
+        loop {
+            if let Ok(Some((_, value))) = self.queue()?.wait_and_pop() {
+                return Some(value);
+            }
+            // closed by a resize that removed the queue
+        }
+    }
+
+    /// Returns true once a resize removed the queue.
+    pub fn is_removed(&self) -> bool {
+        self.index >= self.dispatcher.queues()
+    }
+
+    fn queue(&self) -> Option<Shard<K, T>> {
+        self.dispatcher.read_queues().get(self.index).cloned()
+    }
+}
+
+/// Maps `key` to one of `buckets` buckets with the jump consistent hash of Lamping and Veach.
+fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let (mut bucket, mut next) = (0, 0);
+    while next < buckets {
+        bucket = next;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as usize;
+    }
+    bucket
+}
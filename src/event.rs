@@ -0,0 +1,91 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+/// A manual reset event. Once [Event::set] is called every waiting thread is released and
+/// every following wait returns immediately until the event is [Event::reset].
+/// The fast path is a single atomic load, threads that have to wait register themselves
+/// and park until the setter unparks them.
+#[derive(Debug, Default)]
+pub struct Event {
+    pub flag: AtomicBool,
+    pub waiters: Mutex<Vec<Thread>>,
+}
+
+impl Event {
+    /// Creates a new event which is not set.
+    pub fn new() -> Self {
+        Event::default()
+    }
+
+    /// Sets the event and wakes up all waiting threads.
+    pub fn set(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        // taking the lock after the store makes sure every thread that registered
+        // before will be unparked and every thread that registers after will see the flag
+        let waiters = std::mem::take(&mut *self.waiters.lock().expect("lock acquire failed"));
+        for waiter in waiters {
+            waiter.unpark();
+        }
+    }
+
+    /// Clears the event so following waits block again.
+    pub fn reset(&self) {
+        self.flag.store(false, Ordering::SeqCst);
+    }
+
+    /// Returns true if the event is set.
+    pub fn is_set(&self) -> bool {
+        self.flag.load(Ordering::SeqCst)
+    }
+
+    /// Blocks the current thread until the event is set.
+    pub fn wait(&self) {
+        while !self.register() {
+            thread::park();
+        }
+    }
+
+    /// Blocks the current thread until the event is set or `timeout` passes.
+    /// Returns true if the event was set.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.register() {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                let current = thread::current().id();
+                self.waiters
+                    .lock()
+                    .expect("lock acquire failed")
+                    .retain(|waiter| waiter.id() != current);
+                return self.is_set();
+            }
+            thread::park_timeout(deadline - now);
+        }
+    }
+
+    /// Adds the current thread to the waiters unless the event is already set,
+    /// returns true if it is set and there is no need to park.
+    fn register(&self) -> bool {
+        if self.is_set() {
+            return true;
+        }
+        let mut waiters = self.waiters.lock().expect("lock acquire failed");
+        if self.is_set() {
+            return true;
+        }
+        let current = thread::current();
+        if !waiters.iter().any(|waiter| waiter.id() == current.id()) {
+            waiters.push(current);
+        }
+        false
+    }
+}
@@ -0,0 +1,38 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// An exponentially weighted moving average that any thread can update without a lock.
+/// Each sample moves the average `alpha` of the way towards it, so older samples fade out
+/// geometrically and the average follows load changes without keeping a window of samples.
+/// The value is an f64 stored as bits in an [AtomicU64].
+#[derive(Debug)]
+pub struct Ewma {
+    pub bits: AtomicU64,
+    pub alpha: f64,
+}
+
+impl Ewma {
+    /// Creates an average starting at zero, `alpha` must be in (0, 1].
+    pub fn new(alpha: f64) -> Self {
+        assert!(alpha > 0.0 && alpha <= 1.0, "alpha must be in (0, 1]");
+        Ewma {
+            bits: AtomicU64::new(0.0f64.to_bits()),
+            alpha,
+        }
+    }
+
+    /// Folds `sample` into the average.
+    pub fn update(&self, sample: f64) {
+        // fetch_update retries on contention, so no concurrent sample is lost
+        let _ = self
+            .bits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                let average = f64::from_bits(bits);
+                Some((average + self.alpha * (sample - average)).to_bits())
+            });
+    }
+
+    /// Returns the current average.
+    pub fn get(&self) -> f64 {
+        f64::from_bits(self.bits.load(Ordering::Relaxed))
+    }
+}
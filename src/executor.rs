@@ -0,0 +1,367 @@
+use crate::parker::{Parker, Unparker};
+use crate::promise::JobError;
+use crate::scheduler::Scheduler;
+use std::{
+    cell::RefCell,
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::{pin, Pin},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, Weak,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread,
+};
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+thread_local! {
+    /// The executor the current thread works for and the tasks woken while it polled one,
+    /// they go to the worker's local deque instead of the injector.
+    static LOCAL: RefCell<(usize, Vec<Runnable>)> = const { RefCell::new((0, Vec::new())) };
+}
+
+/// A multithreaded executor for futures on top of a [Scheduler]: every thread is a worker with
+/// its own deque, spawned tasks go to the injector, and a task woken by a worker thread stays
+/// on that worker unless an idle one steals it. Idle workers park until a task is scheduled.
+/// Dropping the executor stops the workers, tasks that didn't finish are dropped.
+#[derive(Debug)]
+pub struct Executor {
+    pub shared: Arc<ExecutorShared>,
+    pub threads: Vec<thread::JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+pub struct ExecutorShared {
+    pub scheduler: Scheduler<Runnable>,
+    pub unparkers: Vec<Unparker>,
+    /// Indices of the workers that are about to park or parked.
+    pub idle: Mutex<Vec<usize>>,
+    pub shutdown: AtomicBool,
+}
+
+/// Spawns tasks onto an [Executor] from anywhere, including its own tasks.
+#[derive(Debug, Clone)]
+pub struct Spawner {
+    pub shared: Weak<ExecutorShared>,
+}
+
+/// A spawned future, polled by whichever worker pops it.
+pub struct Task {
+    /// [None] once the future completed.
+    pub future: Mutex<Option<BoxFuture>>,
+    /// Set while the task waits in the scheduler, so a task is queued once however often
+    /// it is woken.
+    pub scheduled: AtomicBool,
+    pub executor: Weak<ExecutorShared>,
+}
+
+/// A [Task] queued in the scheduler.
+pub struct Runnable(pub Arc<Task>);
+
+/// Awaits or blocks on the output of a spawned task.
+#[derive(Debug)]
+pub struct TaskHandle<T> {
+    pub state: Arc<Mutex<TaskState<T>>>,
+}
+
+#[derive(Debug)]
+pub struct TaskState<T> {
+    pub result: Option<Result<T, JobError>>,
+    /// The waker of whoever awaits the handle.
+    pub waker: Option<Waker>,
+}
+
+/// Completes a [TaskHandle], with [JobError::Abandoned] if it's dropped without a value.
+struct Completion<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+}
+
+/// Turns a panic of the inner future into [JobError::Panicked].
+struct CatchUnwind<F> {
+    future: Pin<Box<F>>,
+}
+
+/// Unparks the thread in [block_on].
+struct ThreadWaker(Unparker);
+
+impl Executor {
+    /// Starts an executor with one worker thread per available CPU.
+    pub fn new() -> Self {
+        Self::with_threads(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+
+    /// Starts an executor with `threads` worker threads.
+    pub fn with_threads(threads: usize) -> Self {
+        let parkers: Vec<_> = (0..threads).map(|_| Parker::new()).collect();
+        let shared = Arc::new(ExecutorShared {
+            scheduler: Scheduler::new(threads),
+            unparkers: parkers.iter().map(Parker::unparker).collect(),
+            idle: Mutex::new(Vec::new()),
+            shutdown: AtomicBool::new(false),
+        });
+        let threads = parkers
+            .into_iter()
+            .enumerate()
+            .map(|(index, parker)| {
+                let shared = shared.clone();
+                thread::spawn(move || shared.work(index, parker))
+            })
+            .collect();
+        Executor { shared, threads }
+    }
+
+    /// Runs `future` on the executor and returns a handle to its output.
+    pub fn spawn<F>(&self, future: F) -> TaskHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.spawner().spawn(future)
+    }
+
+    /// Returns a spawner for this executor.
+    pub fn spawner(&self) -> Spawner {
+        Spawner {
+            shared: Arc::downgrade(&self.shared),
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+        for unparker in &self.shared.unparkers {
+            unparker.unpark();
+        }
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl ExecutorShared {
+    fn work(&self, index: usize, parker: Parker) {
+        LOCAL.with(|local| local.borrow_mut().0 = self as *const _ as usize);
+        let mut worker = self.scheduler.worker(index);
+        while !self.shutdown.load(Ordering::Acquire) {
+            if let Some(Runnable(task)) = worker.pop() {
+                task.run();
+                let woken = LOCAL.with(|local| std::mem::take(&mut local.borrow_mut().1));
+                if !woken.is_empty() {
+                    for runnable in woken {
+                        worker.push(runnable);
+                    }
+                    // an idle worker can steal some of them
+                    self.notify_one();
+                }
+                continue;
+            }
+            {
+                let mut idle = self.idle.lock().expect("lock acquire failed");
+                if !idle.contains(&index) {
+                    idle.push(index);
+                }
+            }
+            // a task scheduled before this worker was listed as idle didn't unpark it
+            if self.scheduler.is_empty() {
+                parker.park();
+            }
+        }
+    }
+
+    fn schedule(&self, runnable: Runnable) {
+        let runnable = LOCAL.with(|local| {
+            let mut local = local.borrow_mut();
+            if local.0 == self as *const _ as usize {
+                local.1.push(runnable);
+                return None;
+            }
+            Some(runnable)
+        });
+        if let Some(runnable) = runnable {
+            self.scheduler.inject(runnable);
+            self.notify_one();
+        }
+    }
+
+    fn notify_one(&self) {
+        let index = self.idle.lock().expect("lock acquire failed").pop();
+        if let Some(index) = index {
+            self.unparkers[index].unpark();
+        }
+    }
+}
+
+impl Spawner {
+    /// Runs `future` on the executor and returns a handle to its output. If the executor was
+    /// dropped, the handle completes with [JobError::Abandoned].
+    pub fn spawn<F>(&self, future: F) -> TaskHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }));
+        let completion = Completion {
+            state: state.clone(),
+        };
+        let future = async move {
+            let result = CatchUnwind {
+                future: Box::pin(future),
+            }
+            .await;
+            completion.complete(result);
+        };
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(Box::pin(future))),
+            scheduled: AtomicBool::new(true),
+            executor: self.shared.clone(),
+        });
+        if let Some(shared) = self.shared.upgrade() {
+            shared.schedule(Runnable(task));
+        }
+        TaskHandle { state }
+    }
+}
+
+impl Task {
+    fn run(self: Arc<Self>) {
+        // a wake during the poll queues the task again
+        self.scheduled.store(false, Ordering::Release);
+        let mut future = self.future.lock().expect("lock acquire failed");
+        let Some(pending) = future.as_mut() else {
+            return;
+        };
+        let waker = Waker::from(self.clone());
+        if pending
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready()
+        {
+            *future = None;
+        }
+    }
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if let Some(shared) = self.executor.upgrade() {
+            shared.schedule(Runnable(self.clone()));
+        }
+    }
+}
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Task")
+            .field("scheduled", &self.scheduled)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for Runnable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Runnable")
+    }
+}
+
+impl<T> TaskHandle<T> {
+    /// Blocks the current thread until the task completed and returns its output.
+    pub fn join(self) -> Result<T, JobError> {
+        block_on(self)
+    }
+
+    /// Returns true once the task completed.
+    pub fn is_finished(&self) -> bool {
+        self.state
+            .lock()
+            .expect("lock acquire failed")
+            .result
+            .is_some()
+    }
+}
+
+impl<T> Future for TaskHandle<T> {
+    type Output = Result<T, JobError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().expect("lock acquire failed");
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Completion<T> {
+    fn complete(&self, result: Result<T, JobError>) {
+        let mut state = self.state.lock().expect("lock acquire failed");
+        if state.result.is_none() {
+            state.result = Some(result);
+        }
+        let waker = state.waker.take();
+        drop(state);
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        self.complete(Err(JobError::Abandoned));
+    }
+}
+
+impl<F: Future> Future for CatchUnwind<F> {
+    type Output = Result<F::Output, JobError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.future.as_mut().poll(cx))) {
+            Ok(Poll::Ready(value)) => Poll::Ready(Ok(value)),
+            Ok(Poll::Pending) => Poll::Pending,
+            Err(_) => Poll::Ready(Err(JobError::Panicked)),
+        }
+    }
+}
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Runs `future` to completion on the current thread, parking it while the future waits.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let parker = Parker::new();
+    let waker = Waker::from(Arc::new(ThreadWaker(parker.unparker())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(value) = future.as_mut().poll(&mut cx) {
+            return value;
+        }
+        parker.park();
+    }
+}
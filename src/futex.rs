@@ -0,0 +1,47 @@
+use std::{
+    ptr::null,
+    sync::atomic::AtomicU32,
+    time::{Duration, Instant},
+};
+
+/// Blocks while `futex` holds `expected`, until woken, `deadline` passes or a spurious wakeup.
+/// Returns false only if the deadline passed.
+pub(crate) fn wait(futex: &AtomicU32, expected: u32, deadline: Option<Instant>) -> bool {
+    let timeout = match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::ZERO {
+                return false;
+            }
+            Some(libc::timespec {
+                tv_sec: remaining.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+                tv_nsec: remaining.subsec_nanos() as _,
+            })
+        }
+        None => None,
+    };
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex.as_ptr(),
+            libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+            expected,
+            timeout
+                .as_ref()
+                .map_or(null(), |t| t as *const libc::timespec),
+        )
+    };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() != Some(libc::ETIMEDOUT)
+}
+
+/// Wakes up to `count` threads blocked in [wait] on `futex`.
+pub(crate) fn wake(futex: &AtomicU32, count: i32) {
+    unsafe {
+        libc::syscall(
+            libc::SYS_futex,
+            futex.as_ptr(),
+            libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG,
+            count,
+        );
+    }
+}
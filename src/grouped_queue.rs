@@ -0,0 +1,145 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    hash::Hash,
+    ops::{Deref, DerefMut},
+    sync::{Condvar, Mutex, PoisonError},
+};
+
+/// A queue of keyed values where values with the same key are handed out one at a time, in
+/// push order, while values with different keys are processed in parallel. A consumer claims
+/// a key together with its oldest value and no other consumer gets that key until the
+/// [GroupClaim] is dropped, e.g. messages of one account or session are processed in order
+/// without a lock per key. Keys are served round robin, so a busy key doesn't starve the rest.
+#[derive(Debug)]
+pub struct GroupedQueue<K, T> {
+    pub cvar: Condvar,
+    pub groups: Mutex<Groups<K, T>>,
+}
+
+#[derive(Debug)]
+pub struct Groups<K, T> {
+    pub values: HashMap<K, VecDeque<T>>,
+    /// Keys with queued values that no consumer holds, in the order they became ready.
+    pub ready: VecDeque<K>,
+    /// Keys claimed by a consumer.
+    pub busy: HashSet<K>,
+    pub len: usize,
+}
+
+/// A value taken from a [GroupedQueue], its key stays claimed until this is dropped.
+#[derive(Debug)]
+pub struct GroupClaim<'a, K: Hash + Eq + Clone, T> {
+    pub queue: &'a GroupedQueue<K, T>,
+    pub key: K,
+    pub value: T,
+}
+
+impl<K: Hash + Eq + Clone, T> GroupedQueue<K, T> {
+    /// Creates a new empty queue.
+    pub fn new() -> Self {
+        GroupedQueue {
+            cvar: Condvar::new(),
+            groups: Mutex::new(Groups {
+                values: HashMap::new(),
+                ready: VecDeque::new(),
+                busy: HashSet::new(),
+                len: 0,
+            }),
+        }
+    }
+
+    /// Pushes a value to the back of the group of `key`.
+    pub fn push(&self, key: K, value: T) {
+        let mut groups = self.groups.lock().expect("lock acquire failed");
+        groups.len += 1;
+        let values = groups.values.entry(key.clone()).or_default();
+        values.push_back(value);
+        // a key with older values is already ready or busy
+        if values.len() == 1 && !groups.busy.contains(&key) {
+            groups.ready.push_back(key);
+            drop(groups);
+            self.cvar.notify_one();
+        }
+    }
+
+    /// Claims the next ready key and takes its oldest value, or returns [None] if every key
+    /// with values is claimed by another consumer.
+    pub fn try_pop(&self) -> Option<GroupClaim<'_, K, T>> {
+        let mut groups = self.groups.lock().expect("lock acquire failed");
+        self.claim(&mut groups)
+    }
+
+    /// Claims the next ready key and takes its oldest value, waiting until one is available.
+    pub fn wait_pop(&self) -> GroupClaim<'_, K, T> {
+        let mut groups = self.groups.lock().expect("lock acquire failed");
+        loop {
+            if let Some(claim) = self.claim(&mut groups) {
+                return claim;
+            }
+            groups = self.cvar.wait(groups).expect("lock acquire failed");
+        }
+    }
+
+    /// Returns the number of queued values.
+    pub fn len(&self) -> usize {
+        self.groups.lock().expect("lock acquire failed").len
+    }
+
+    /// Returns true if no values are queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn claim(&self, groups: &mut Groups<K, T>) -> Option<GroupClaim<'_, K, T>> {
+        let key = groups.ready.pop_front()?;
+        let values = groups.values.get_mut(&key).expect("ready keys have values");
+        let value = values.pop_front().expect("ready keys have values");
+        if values.is_empty() {
+            groups.values.remove(&key);
+        }
+        groups.len -= 1;
+        groups.busy.insert(key.clone());
+        Some(GroupClaim {
+            queue: self,
+            key,
+            value,
+        })
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> Default for GroupedQueue<K, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> Deref for GroupClaim<'_, K, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> DerefMut for GroupClaim<'_, K, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<K: Hash + Eq + Clone, T> Drop for GroupClaim<'_, K, T> {
+    fn drop(&mut self) {
+        // may run while unwinding out of the consumer, a second panic would abort
+        let mut groups = self
+            .queue
+            .groups
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        groups.busy.remove(&self.key);
+        if groups.values.contains_key(&self.key) {
+            groups.ready.push_back(self.key.clone());
+            drop(groups);
+            self.queue.cvar.notify_one();
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Number of bits of a value kept exactly within its power of two, 8 sub-buckets per power of
+/// two bound the error of a recorded value to 12.5%.
+const SUB_BUCKET_BITS: u32 = 3;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// Values below SUB_BUCKETS get a bucket each, every power of two above SUB_BUCKETS more.
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// A histogram of durations with logarithmic buckets in the style of HdrHistogram: every
+/// power of two of nanoseconds is split into 8 linear sub-buckets, so it covers nanoseconds to
+/// centuries with a relative error of at most 12.5% in a fixed array of counters. Recording is
+/// a few relaxed atomic adds, so any number of threads record at once without locking.
+#[derive(Debug)]
+pub struct Histogram {
+    pub counts: Box<[AtomicU64]>,
+    pub count: AtomicU64,
+    /// Sum of the recorded values in nanoseconds, for the mean.
+    pub sum: AtomicU64,
+    pub max: AtomicU64,
+}
+
+/// A copy of the counters of a [Histogram] to compute statistics from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    pub counts: Vec<u64>,
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+}
+
+impl Histogram {
+    /// Creates a new empty histogram.
+    pub fn new() -> Self {
+        Histogram {
+            counts: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    /// Records one duration, durations beyond 584 years are recorded as that.
+    pub fn record(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.counts[bucket_of(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(nanos, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Copies the counters, records that happen meanwhile may or may not be included.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        HistogramSnapshot {
+            counts: self
+                .counts
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .collect(),
+            count: self.count.load(Ordering::Relaxed),
+            sum: self.sum.load(Ordering::Relaxed),
+            max: self.max.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HistogramSnapshot {
+    /// Returns the number of recorded durations.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the longest recorded duration.
+    pub fn max(&self) -> Duration {
+        Duration::from_nanos(self.max)
+    }
+
+    /// Returns the mean of the recorded durations, zero if there are none.
+    pub fn mean(&self) -> Duration {
+        Duration::from_nanos(self.sum.checked_div(self.count).unwrap_or(0))
+    }
+
+    /// Returns the duration that `quantile` of the recorded durations don't exceed, e.g. 0.99
+    /// for the 99th percentile, rounded up to the end of its bucket. Zero if there are none.
+    pub fn quantile(&self, quantile: f64) -> Duration {
+        let total: u64 = self.counts.iter().sum();
+        if total == 0 {
+            return Duration::ZERO;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * total as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let end = bucket_start(bucket + 1).saturating_sub(1);
+                return Duration::from_nanos(end.min(self.max));
+            }
+        }
+        self.max()
+    }
+}
+
+fn bucket_of(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let exponent = 63 - nanos.leading_zeros();
+    let sub_bucket = (nanos >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    ((exponent - SUB_BUCKET_BITS + 1) as usize) * SUB_BUCKETS + sub_bucket
+}
+
+/// Returns the smallest value of `bucket`, saturating past the last one.
+fn bucket_start(bucket: usize) -> u64 {
+    if bucket < SUB_BUCKETS {
+        return bucket as u64;
+    }
+    if bucket >= BUCKETS {
+        return u64::MAX;
+    }
+    let exponent = (bucket / SUB_BUCKETS) as u32 + SUB_BUCKET_BITS - 1;
+    ((SUB_BUCKETS + bucket % SUB_BUCKETS) as u64) << (exponent - SUB_BUCKET_BITS)
+}
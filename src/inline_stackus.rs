@@ -0,0 +1,149 @@
+use crate::spin::Backoff;
+use crate::stackus::Stackus;
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicU8, AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+const EMPTY: u8 = 0;
+const WRITING: u8 = 1;
+const FULL: u8 = 2;
+const READING: u8 = 3;
+
+/// A stack that keeps up to `N` values inline in a fixed array and only spills to a linked
+/// [Stackus] beyond that, so shallow stacks never allocate. Pushes and pops claim a slot by
+/// moving the shared top index with a compare-exchange, then move the value in or out. A push
+/// and a pop that claimed the same slot at once wait for each other through the slot's state,
+/// so a thread only ever waits for one that is already in the middle of its operation.
+#[derive(Debug)]
+pub struct InlineStackus<T, const N: usize> {
+    /// Number of claimed slots.
+    top: AtomicUsize,
+    slots: [Slot<T>; N],
+    /// Values pushed while every slot is taken, created on the first spill.
+    pub spill: OnceLock<Stackus<T>>,
+}
+
+/// A value place of [InlineStackus], its state tells whether the value is initialized.
+#[derive(Debug)]
+struct Slot<T> {
+    state: AtomicU8,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for InlineStackus<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for InlineStackus<T, N> {}
+
+impl<T, const N: usize> InlineStackus<T, N> {
+    /// Creates a new empty stack.
+    pub fn new() -> Self {
+        InlineStackus {
+            top: AtomicUsize::new(0),
+            slots: std::array::from_fn(|_| Slot {
+                state: AtomicU8::new(EMPTY),
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+            }),
+            spill: OnceLock::new(),
+        }
+    }
+
+    /// Inserts a value at the top of the stack.
+    pub fn push(&self, value: T) {
+        let claimed = self
+            .top
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |top| {
+                (top < N).then_some(top + 1)
+            });
+        let Ok(index) = claimed else {
+            return self.push_spill(value);
+        };
+        let slot = &self.slots[index];
+        // a pop that claimed this slot earlier may still be moving its value out
+        Self::transition(slot, EMPTY, WRITING);
+        unsafe { (*slot.value.get()).write(value) };
+        slot.state.store(FULL, Ordering::Release);
+    }
+
+    /// Removes the element from the top of the stack and returns it, or [None] if it is empty.
+    pub fn pop(&self) -> Option<T> {
+        // spilled values were pushed after the array filled up, so they are on top
+        if let Some(value) = self.spill.get().and_then(Stackus::pop) {
+            return Some(value);
+        }
+        let index = self
+            .top
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |top| {
+                top.checked_sub(1)
+            })
+            .ok()?
+            - 1;
+        let slot = &self.slots[index];
+        // the push that claimed this slot may still be moving its value in
+        Self::transition(slot, FULL, READING);
+        let value = unsafe { (*slot.value.get()).assume_init_read() };
+        slot.state.store(EMPTY, Ordering::Release);
+        Some(value)
+    }
+
+    /// Returns the number of values held inline, values in the spill stack are not counted.
+    pub fn inline_len(&self) -> usize {
+        self.top.load(Ordering::Acquire)
+    }
+
+    /// Returns true if values had to be moved to the heap at some point.
+    pub fn has_spilled(&self) -> bool {
+        self.spill.get().is_some()
+    }
+
+    /// Returns the approximate number of bytes the stack allocated on the heap for values that
+    /// spilled, the inline slots are part of the stack itself and not counted.
+    pub fn approx_memory_usage(&self) -> usize {
+        self.spill.get().map_or(0, Stackus::approx_memory_usage)
+    }
+
+    /// Returns true if the stack contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.inline_len() == 0 && self.spill.get().is_none_or(Stackus::is_empty)
+    }
+
+    fn push_spill(&self, value: T) {
+        let mut value = Some(value);
+        let spill = self
+            .spill
+            .get_or_init(|| Stackus::new(value.take().expect("value is taken once")));
+        if let Some(value) = value {
+            spill.push(value);
+        }
+    }
+
+    fn transition(slot: &Slot<T>, from: u8, to: u8) {
+        let mut backoff = Backoff::new();
+        while slot
+            .state
+            .compare_exchange_weak(from, to, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T, const N: usize> Default for InlineStackus<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for InlineStackus<T, N> {
+    fn drop(&mut self) {
+        for slot in &mut self.slots {
+            if *slot.state.get_mut() == FULL {
+                unsafe { slot.value.get_mut().assume_init_drop() };
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use crate::spin::Backoff;
+use std::{
+    marker::PhantomData,
+    ptr::{self, null_mut},
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+/// The link a type embeds to be pushed onto an [IntrusiveStackus]. Its fields are private,
+/// only the stack may change them.
+#[derive(Debug)]
+pub struct Link<T> {
+    next: AtomicPtr<T>,
+    /// Set while the node is in a stack, so it can't be pushed twice.
+    linked: AtomicBool,
+}
+
+/// Implemented by types that embed a [Link], like the nodes of an intrusive list.
+///
+/// # Safety
+/// `link` must return the same link every time it is called on the same value, and no other
+/// value may return that link.
+pub unsafe trait Linked {
+    fn link(&self) -> &Link<Self>
+    where
+        Self: Sized;
+}
+
+/// A stack of borrowed values that carry their own [Link], so pushing and popping never
+/// allocate, e.g. to hand out preallocated buffers in a real-time thread. Values are borrowed
+/// for `'a`, which keeps them in place while they are linked. Pushes are lock-free like in
+/// [crate::stackus::Stackus]. Popped values are reused right away instead of being reclaimed
+/// later, so a pop racing another pop could see a value popped and pushed again in between and
+/// link the stack to a stale next pointer, the ABA problem. Pops therefore take turns through
+/// a flag, which a single popping thread never waits on.
+#[derive(Debug)]
+pub struct IntrusiveStackus<'a, T: Linked> {
+    head: AtomicPtr<T>,
+    /// Set while a thread pops.
+    popping: AtomicBool,
+    pub values: PhantomData<&'a T>,
+}
+
+/// Iterator over the values taken by [IntrusiveStackus::pop_all], top first. Values left when
+/// it is dropped are unlinked so they can be pushed again.
+#[derive(Debug)]
+pub struct Drain<'a, T: Linked> {
+    node: *mut T,
+    pub values: PhantomData<&'a T>,
+}
+
+unsafe impl<T: Linked + Sync> Send for IntrusiveStackus<'_, T> {}
+unsafe impl<T: Linked + Sync> Sync for IntrusiveStackus<'_, T> {}
+unsafe impl<T: Linked + Sync> Send for Drain<'_, T> {}
+
+impl<T> Link<T> {
+    /// Creates an unlinked link.
+    pub const fn new() -> Self {
+        Link {
+            next: AtomicPtr::new(null_mut()),
+            linked: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns true if the value that embeds this link is in a stack.
+    pub fn is_linked(&self) -> bool {
+        self.linked.load(Ordering::Acquire)
+    }
+}
+
+impl<T> Default for Link<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T: Linked> IntrusiveStackus<'a, T> {
+    /// Creates a new empty stack.
+    pub const fn new() -> Self {
+        IntrusiveStackus {
+            head: AtomicPtr::new(null_mut()),
+            popping: AtomicBool::new(false),
+            values: PhantomData,
+        }
+    }
+
+    /// Inserts `value` at the top of the stack without allocating.
+    /// Panics if `value` is already in a stack.
+    pub fn push(&self, value: &'a T) {
+        let link = value.link();
+        assert!(
+            !link.linked.swap(true, Ordering::AcqRel),
+            "value is already in a stack"
+        );
+        let node = ptr::from_ref(value).cast_mut();
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            link.next.store(head, Ordering::Relaxed);
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Removes the value from the top of the stack and returns it, or [None] if it is empty.
+    pub fn pop(&self) -> Option<&'a T> {
+        self.lock_pop();
+        let mut head = self.head.load(Ordering::Acquire);
+        while !head.is_null() {
+            // only pushes run concurrently and they never unlink head, so next is current
+            let next = unsafe { (*head).link().next.load(Ordering::Relaxed) };
+            match self
+                .head
+                .compare_exchange_weak(head, next, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(current) => head = current,
+            }
+        }
+        self.popping.store(false, Ordering::Release);
+        let value = unsafe { head.as_ref()? };
+        value.link().linked.store(false, Ordering::Release);
+        Some(value)
+    }
+
+    /// Takes every value off the stack at once.
+    pub fn pop_all(&self) -> Drain<'a, T> {
+        // a pop in progress must not see the values pushed again after they are drained
+        self.lock_pop();
+        let node = self.head.swap(null_mut(), Ordering::Acquire);
+        self.popping.store(false, Ordering::Release);
+        Drain {
+            node,
+            values: PhantomData,
+        }
+    }
+
+    /// Returns true if the stack contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).is_null()
+    }
+
+    fn lock_pop(&self) {
+        let mut backoff = Backoff::new();
+        while self
+            .popping
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            backoff.snooze();
+        }
+    }
+}
+
+impl<T: Linked> Default for IntrusiveStackus<'_, T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, T: Linked> Iterator for Drain<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let value = unsafe { self.node.as_ref()? };
+        let link = value.link();
+        self.node = link.next.load(Ordering::Relaxed);
+        link.linked.store(false, Ordering::Release);
+        Some(value)
+    }
+}
+
+impl<T: Linked> Drop for Drain<'_, T> {
+    fn drop(&mut self) {
+        self.for_each(drop);
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hash},
+    marker::PhantomData,
+    sync::{Mutex, MutexGuard},
+};
+
+const DEFAULT_STRIPES: usize = 64;
+
+/// A striped lock which serializes work per key. The key is hashed to pick one of a fixed
+/// number of mutexes, so operations on the same key never run at the same time while
+/// operations on different keys usually run in parallel. Two different keys may share a
+/// stripe, so holding two guards at once can deadlock.
+#[derive(Debug)]
+pub struct KeyedMutex<K: Hash> {
+    pub stripes: Vec<Mutex<()>>,
+    pub hasher: RandomState,
+    pub key: PhantomData<fn(&K)>,
+}
+
+/// Guard returned by [KeyedMutex::lock], the stripe is unlocked when it is dropped.
+#[derive(Debug)]
+pub struct KeyedGuard<'a> {
+    pub guard: MutexGuard<'a, ()>,
+    pub stripe: usize,
+}
+
+impl<K: Hash> KeyedMutex<K> {
+    /// Creates a new keyed mutex with the default number of stripes.
+    pub fn new() -> Self {
+        Self::with_stripes(DEFAULT_STRIPES)
+    }
+
+    /// Creates a new keyed mutex with `stripes` independent locks.
+    pub fn with_stripes(stripes: usize) -> Self {
+        assert!(stripes > 0, "stripes must be greater than zero");
+        KeyedMutex {
+            stripes: (0..stripes).map(|_| Mutex::new(())).collect(),
+            hasher: RandomState::new(),
+            key: PhantomData,
+        }
+    }
+
+    /// Locks the stripe `key` belongs to, blocking until it is available.
+    pub fn lock(&self, key: &K) -> KeyedGuard<'_> {
+        let stripe = self.stripe_of(key);
+        KeyedGuard {
+            // the mutex protects no data, so a poisoned one is still usable
+            guard: self.stripes[stripe]
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+            stripe,
+        }
+    }
+
+    /// Locks the stripe `key` belongs to if it is not held by another thread.
+    pub fn try_lock(&self, key: &K) -> Option<KeyedGuard<'_>> {
+        let stripe = self.stripe_of(key);
+        match self.stripes[stripe].try_lock() {
+            Ok(guard) => Some(KeyedGuard { guard, stripe }),
+            Err(std::sync::TryLockError::Poisoned(poisoned)) => Some(KeyedGuard {
+                guard: poisoned.into_inner(),
+                stripe,
+            }),
+            Err(std::sync::TryLockError::WouldBlock) => None,
+        }
+    }
+
+    /// Returns the index of the stripe `key` is mapped to.
+    pub fn stripe_of(&self, key: &K) -> usize {
+        (self.hasher.hash_one(key) % self.stripes.len() as u64) as usize
+    }
+}
+
+impl<K: Hash> Default for KeyedMutex<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,91 @@
+use crate::spin::Backoff;
+use std::{
+    cell::UnsafeCell,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+/// A left-right concurrency primitive, based on the paper "Left-Right: A Concurrency Control
+/// Technique with Wait-Free Population Oblivious Reads" by Pedro Ramalhete and Andreia Correia.
+/// Keeps two copies of the data, readers always read the copy the writer is not touching, so
+/// they never wait and never take a lock. The writer applies each change to the idle copy,
+/// switches readers over to it, waits until no reader is left on the other copy and applies
+/// the same change there. This makes writes twice as expensive, so it suits read-mostly data.
+#[derive(Debug)]
+pub struct LeftRight<T> {
+    pub instances: [UnsafeCell<T>; 2],
+    /// Index of the instance readers should use.
+    pub left_right: AtomicUsize,
+    /// Index of the read indicator new readers announce themselves on.
+    pub version_index: AtomicUsize,
+    pub read_indicators: [AtomicUsize; 2],
+    pub writer: Mutex<()>,
+}
+
+/// A reader announced on a read indicator, leaves it when dropped so a panicking read doesn't
+/// keep writers waiting forever.
+struct Reading<'a>(&'a AtomicUsize);
+
+unsafe impl<T: Send + Sync> Sync for LeftRight<T> {}
+
+impl<T> LeftRight<T> {
+    /// Creates a new left-right instance holding two copies of `value`.
+    pub fn new(value: T) -> Self
+    where
+        T: Clone,
+    {
+        LeftRight {
+            instances: [UnsafeCell::new(value.clone()), UnsafeCell::new(value)],
+            left_right: AtomicUsize::new(0),
+            version_index: AtomicUsize::new(0),
+            read_indicators: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Calls `f` with a reference to the current data, never blocks.
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let version = self.version_index.load(Ordering::SeqCst);
+        self.read_indicators[version].fetch_add(1, Ordering::SeqCst);
+        let _reading = Reading(&self.read_indicators[version]);
+        let instance = self.left_right.load(Ordering::SeqCst);
+        // the writer doesn't touch this instance until our indicator is back to zero
+        f(unsafe { &*self.instances[instance].get() })
+    }
+
+    /// Applies `f` to the data, writers are serialized with each other. `f` runs once on
+    /// each copy, so it has to make the same change both times.
+    pub fn write(&self, f: impl Fn(&mut T)) {
+        let _writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let readers_on = self.left_right.load(Ordering::SeqCst);
+        let idle = 1 - readers_on;
+        f(unsafe { &mut *self.instances[idle].get() });
+        self.left_right.store(idle, Ordering::SeqCst);
+        // wait out readers that may still use the old instance, toggling the version so
+        // new readers don't keep the old indicator busy forever
+        let previous_version = self.version_index.load(Ordering::SeqCst);
+        let next_version = 1 - previous_version;
+        self.wait_for_readers(next_version);
+        self.version_index.store(next_version, Ordering::SeqCst);
+        self.wait_for_readers(previous_version);
+        f(unsafe { &mut *self.instances[readers_on].get() });
+    }
+
+    fn wait_for_readers(&self, version: usize) {
+        let mut backoff = Backoff::new();
+        while self.read_indicators[version].load(Ordering::SeqCst) != 0 {
+            backoff.snooze();
+        }
+    }
+}
+
+impl Drop for Reading<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
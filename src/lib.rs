@@ -1,4 +1,60 @@
+pub mod accumulator;
+mod affinity;
+pub mod appendus;
+pub mod bitus;
+pub mod boundq;
+pub mod broadcastus;
+pub mod bus;
+pub mod bytes_queue;
+pub mod cancellation;
+pub mod chaos;
+pub mod container;
+pub mod counter_map;
+pub mod dequeus;
+pub mod dispatcher;
+pub mod event;
+pub mod ewma;
+#[cfg(feature = "executor")]
+pub mod executor;
+#[cfg(target_os = "linux")]
+mod futex;
+pub mod grouped_queue;
+pub mod histogram;
+pub mod inline_stackus;
+pub mod intrusive_stackus;
+pub mod keyed_mutex;
+pub mod left_right;
+pub mod lock;
+#[cfg(feature = "debug-locks")]
+pub mod lock_order;
+pub mod mapus;
+pub mod mcs_lock;
 pub mod multiq;
+pub mod os_lock;
+pub mod parallel;
+pub mod parker;
+pub mod promise;
+pub mod rate_limiter;
+#[cfg(target_os = "linux")]
+pub mod raw_mutex;
+pub mod rcu;
+pub mod reentrant_mutex;
+pub mod registry;
+mod sanitize;
+pub mod scheduler;
+pub mod semaphore;
+pub mod seqlock;
+pub mod sequencer;
+pub mod single_thread;
+pub mod slabus;
+pub mod sp_stackus;
+pub mod spin;
 pub mod stackus;
+pub mod static_queue;
+pub mod task_queue;
 #[cfg(test)]
 mod tests;
+pub mod thread_pool;
+pub mod ticket_lock;
+pub mod timer_driver;
+pub mod watch;
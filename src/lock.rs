@@ -0,0 +1,259 @@
+use std::{
+    cell::UnsafeCell,
+    fmt::{self, Debug},
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Condvar, LockResult, Mutex, PoisonError, TryLockError, TryLockResult,
+    },
+    thread,
+};
+
+/// Lock used by the containers when no other is chosen, the futex feature switches it to
+/// [crate::raw_mutex::RawMutex] on Linux.
+#[cfg(all(feature = "futex", target_os = "linux"))]
+pub type DefaultLock = crate::raw_mutex::RawMutex;
+/// Lock used by the containers when no other is chosen, the os-lock feature switches it to
+/// [crate::os_lock::SrwLock] on Windows.
+#[cfg(all(feature = "os-lock", windows))]
+pub type DefaultLock = crate::os_lock::SrwLock;
+/// Lock used by the containers when no other is chosen, the os-lock feature switches it to
+/// [crate::os_lock::UnfairLock] on Apple platforms.
+#[cfg(all(feature = "os-lock", target_vendor = "apple"))]
+pub type DefaultLock = crate::os_lock::UnfairLock;
+/// Lock used by the containers when no other is chosen, the futex and os-lock features switch
+/// it to the lock of the platform.
+#[cfg(not(any(
+    all(feature = "futex", target_os = "linux"),
+    all(feature = "os-lock", windows),
+    all(feature = "os-lock", target_vendor = "apple")
+)))]
+pub type DefaultLock = StdLock;
+
+/// A mutual exclusion primitive that protects no data by itself, [Lock] pairs it with a value.
+/// Allows containers such as [crate::multiq::Multiq] to be instantiated over different
+/// locking strategies (fair, spinning, futex based) without changing their code.
+///
+/// # Safety
+/// Between a successful `lock` or `try_lock` and the matching `unlock` no other thread may
+/// successfully lock.
+pub unsafe trait RawLock: Default + Debug + Send + Sync {
+    /// Acquires the lock, blocking the current thread until it is available.
+    fn lock(&self);
+
+    /// Acquires the lock if it is available right now, returns true on success.
+    fn try_lock(&self) -> bool;
+
+    /// Releases the lock.
+    ///
+    /// # Safety
+    /// The lock must be held by the current thread.
+    unsafe fn unlock(&self);
+}
+
+/// A mutex over any [RawLock] with the same API and poisoning behavior as [std::sync::Mutex],
+/// so code written against std works unchanged with every lock in the crate.
+/// With the debug-locks feature every acquisition is checked against the order in which
+/// locks were taken before, see [crate::lock_order].
+pub struct Lock<T, R: RawLock = DefaultLock> {
+    pub raw: R,
+    pub poisoned: AtomicBool,
+    pub data: UnsafeCell<T>,
+    /// Identifies the lock in the acquisition order graph.
+    #[cfg(feature = "debug-locks")]
+    pub order_id: usize,
+}
+
+unsafe impl<T: Send, R: RawLock> Send for Lock<T, R> {}
+unsafe impl<T: Send, R: RawLock> Sync for Lock<T, R> {}
+
+/// Guard returned by [Lock::lock], releases the lock when dropped.
+pub struct LockGuard<'a, T, R: RawLock = DefaultLock> {
+    pub lock: &'a Lock<T, R>,
+    /// Whether the thread was already panicking when the lock was taken.
+    pub panicking: bool,
+    /// Keeps the guard on the locking thread, some platform locks such as
+    /// [crate::os_lock::UnfairLock] abort when another thread unlocks them.
+    pub unsend: PhantomData<*const ()>,
+}
+
+/// A [RawLock] built from std's Mutex and Condvar, the default when no feature picks another.
+/// Locking and unlocking are a single atomic operation while nobody waits, only blocked
+/// threads take the std Mutex to sleep on the Condvar, so an uncontended lock costs about as
+/// much as a [std::sync::Mutex].
+#[derive(Debug, Default)]
+pub struct StdLock {
+    pub locked: AtomicBool,
+    /// Threads sleeping in lock, unlock only wakes one while there are any.
+    pub waiters: AtomicUsize,
+    pub sleep: Mutex<()>,
+    pub cvar: Condvar,
+}
+
+impl<T, R: RawLock> Lock<T, R> {
+    /// Creates a new unlocked lock holding `value`.
+    pub fn new(value: T) -> Self {
+        Lock {
+            raw: R::default(),
+            poisoned: AtomicBool::new(false),
+            data: UnsafeCell::new(value),
+            #[cfg(feature = "debug-locks")]
+            order_id: crate::lock_order::next_id(),
+        }
+    }
+
+    /// Acquires the lock, blocking until it is available. Returns an error holding the guard
+    /// if another thread panicked while holding it.
+    pub fn lock(&self) -> LockResult<LockGuard<'_, T, R>> {
+        // only blocking acquisitions can deadlock, try_lock is tracked as held but not checked
+        #[cfg(feature = "debug-locks")]
+        crate::lock_order::before_lock(self.order_id);
+        self.raw.lock();
+        self.guard()
+    }
+
+    /// Acquires the lock if it is available right now.
+    pub fn try_lock(&self) -> TryLockResult<LockGuard<'_, T, R>> {
+        if !self.raw.try_lock() {
+            return Err(TryLockError::WouldBlock);
+        }
+        Ok(self.guard()?)
+    }
+
+    /// Returns true if a thread panicked while holding the lock.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Clears the poisoned state, for callers that repaired the data.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+
+    /// Consumes the lock and returns the value.
+    pub fn into_inner(self) -> LockResult<T> {
+        let poisoned = self.is_poisoned();
+        let value = self.data.into_inner();
+        if poisoned {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    /// Returns a mutable reference to the value, no locking needed since the borrow is unique.
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        let poisoned = self.is_poisoned();
+        let value = self.data.get_mut();
+        if poisoned {
+            Err(PoisonError::new(value))
+        } else {
+            Ok(value)
+        }
+    }
+
+    fn guard(&self) -> LockResult<LockGuard<'_, T, R>> {
+        #[cfg(feature = "debug-locks")]
+        crate::lock_order::after_lock(self.order_id);
+        let guard = LockGuard {
+            lock: self,
+            panicking: thread::panicking(),
+            unsend: PhantomData,
+        };
+        if self.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+}
+
+impl<T: Default, R: RawLock> Default for Lock<T, R> {
+    fn default() -> Self {
+        Lock::new(T::default())
+    }
+}
+
+impl<T: Debug, R: RawLock> Debug for Lock<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Lock");
+        match self.try_lock() {
+            Ok(guard) => debug.field("data", &&*guard),
+            Err(TryLockError::Poisoned(poisoned)) => debug.field("data", &&*poisoned.into_inner()),
+            Err(TryLockError::WouldBlock) => debug.field("data", &"<locked>"),
+        };
+        debug.field("poisoned", &self.is_poisoned()).finish()
+    }
+}
+
+impl<T, R: RawLock> Deref for LockGuard<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T, R: RawLock> DerefMut for LockGuard<'_, T, R> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T, R: RawLock> Drop for LockGuard<'_, T, R> {
+    fn drop(&mut self) {
+        // same rule as std: a panic that started while the guard was held poisons the lock
+        if !self.panicking && thread::panicking() {
+            self.lock.poisoned.store(true, Ordering::Relaxed);
+        }
+        unsafe { self.lock.raw.unlock() };
+        #[cfg(feature = "debug-locks")]
+        crate::lock_order::after_unlock(self.lock.order_id);
+    }
+}
+
+impl<T: Debug, R: RawLock> Debug for LockGuard<'_, T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+unsafe impl RawLock for StdLock {
+    fn lock(&self) {
+        if self.try_lock() {
+            return;
+        }
+        // the inner mutex is only held for a few instructions, a panic can't poison it
+        let mut sleep = self.sleep.lock().unwrap_or_else(PoisonError::into_inner);
+        // counted before trying again, so an unlock that the try misses sees this thread and
+        // notifies it once it sleeps, which releases the inner mutex
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        while self
+            .locked
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::Relaxed)
+            .is_err()
+        {
+            sleep = self
+                .cvar
+                .wait(sleep)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        self.waiters.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn try_lock(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        // SeqCst pairs with the count of a sleeping thread, one of the two sees the other
+        self.locked.store(false, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let _sleep = self.sleep.lock().unwrap_or_else(PoisonError::into_inner);
+            self.cvar.notify_one();
+        }
+    }
+}
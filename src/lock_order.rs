@@ -0,0 +1,87 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+/// Order in which locks were seen taken while another lock was held: an edge a -> b means
+/// some thread acquired b while holding a. A cycle in this graph means two threads can
+/// deadlock by taking the same locks in different order, even if they never did so far.
+static GRAPH: OnceLock<Mutex<HashMap<usize, HashSet<usize>>>> = OnceLock::new();
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+thread_local! {
+    /// Ids of the locks held by the current thread, in acquisition order.
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns a new id for a lock, ids are never reused so freed locks can't create false edges.
+pub fn next_id() -> usize {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Records that the current thread is about to block on lock `id` and panics with the
+/// cycle if that acquisition order contradicts an order seen before.
+pub fn before_lock(id: usize) {
+    let held = HELD.with(|held| held.borrow().clone());
+    if held.contains(&id) {
+        panic!("lock order cycle: lock {id} acquired again by the thread holding it");
+    }
+    let mut graph = GRAPH
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for &from in &held {
+        if let Some(path) = find_path(&graph, id, from) {
+            let cycle = path
+                .iter()
+                .chain(std::iter::once(&id))
+                .map(|lock| format!("lock {lock}"))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            drop(graph);
+            panic!("lock order cycle: acquiring lock {id} while holding lock {from}, but {cycle} was seen before");
+        }
+        graph.entry(from).or_default().insert(id);
+    }
+}
+
+/// Records that the current thread now holds lock `id`.
+pub fn after_lock(id: usize) {
+    HELD.with(|held| held.borrow_mut().push(id));
+}
+
+/// Records that the current thread released lock `id`.
+pub fn after_unlock(id: usize) {
+    // guards may be dropped in any order, and the slot may be gone during thread teardown
+    let _ = HELD.try_with(|held| {
+        let mut held = held.borrow_mut();
+        if let Some(position) = held.iter().rposition(|&lock| lock == id) {
+            held.remove(position);
+        }
+    });
+}
+
+/// Returns the locks on a path from `from` to `to` in the order graph, if there is one.
+fn find_path(graph: &HashMap<usize, HashSet<usize>>, from: usize, to: usize) -> Option<Vec<usize>> {
+    let mut stack = vec![vec![from]];
+    let mut visited = HashSet::new();
+    while let Some(path) = stack.pop() {
+        let last = *path.last().expect("paths are never empty");
+        if last == to {
+            return Some(path);
+        }
+        if !visited.insert(last) {
+            continue;
+        }
+        for &next in graph.get(&last).into_iter().flatten() {
+            let mut longer = path.clone();
+            longer.push(next);
+            stack.push(longer);
+        }
+    }
+    None
+}
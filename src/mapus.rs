@@ -0,0 +1,235 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hash},
+    ops::{Deref, DerefMut},
+    sync::{RwLock, RwLockReadGuard, RwLockWriteGuard},
+    vec,
+};
+
+const DEFAULT_SHARDS: usize = 64;
+
+/// A concurrent hash map split into shards, each a [HashMap] behind its own [RwLock]. A key
+/// is hashed to pick its shard like in [crate::keyed_mutex::KeyedMutex], so operations on
+/// keys in different shards run in parallel and lookups in the same shard share the lock.
+#[derive(Debug)]
+pub struct Mapus<K, V> {
+    pub shards: Vec<RwLock<HashMap<K, V>>>,
+    pub hasher: RandomState,
+}
+
+/// Iterator over the entries of a [Mapus], returned by [Mapus::iter_snapshot].
+#[derive(Debug)]
+pub struct Snapshot<'a, K, V> {
+    pub map: &'a Mapus<K, V>,
+    /// The next shard to copy.
+    pub shard: usize,
+    pub entries: vec::IntoIter<(K, V)>,
+}
+
+/// A key's place in a [Mapus], returned by [Mapus::entry]. Holds the write lock of the
+/// key's shard, so the lookup and the following insert or update are one atomic step.
+#[derive(Debug)]
+pub struct Entry<'a, K, V> {
+    pub shard: RwLockWriteGuard<'a, HashMap<K, V>>,
+    pub key: K,
+}
+
+/// Mutable access to a value of a [Mapus], the shard stays write locked until it is dropped.
+/// Its fields are private, changing the shard through them would move the value.
+#[derive(Debug)]
+pub struct RefMut<'a, K, V> {
+    _shard: RwLockWriteGuard<'a, HashMap<K, V>>,
+    /// Points into the locked shard, which is not changed while the guard is held.
+    value: *mut V,
+}
+
+impl<K: Hash + Eq, V> Mapus<K, V> {
+    /// Creates a new empty map with the default number of shards.
+    pub fn new() -> Self {
+        Self::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Creates a new empty map with `shards` independently locked shards.
+    pub fn with_shards(shards: usize) -> Self {
+        assert!(shards > 0, "shards must be greater than zero");
+        Mapus {
+            shards: (0..shards).map(|_| RwLock::new(HashMap::new())).collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Inserts a value for `key` and returns the value it replaced.
+    pub fn insert(&self, key: K, value: V) -> Option<V> {
+        self.write_shard(&key).insert(key, value)
+    }
+
+    /// Locks the shard of `key` for a read-modify-write of its value, e.g.
+    /// `map.entry(key).and_modify(|count| *count += 1).or_insert(1)`. Other keys of the same
+    /// shard wait until the entry or the [RefMut] made from it is dropped.
+    pub fn entry(&self, key: K) -> Entry<'_, K, V> {
+        Entry {
+            shard: self.write_shard(&key),
+            key,
+        }
+    }
+
+    /// Returns a copy of the value for `key`.
+    pub fn get(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.read_shard(key).get(key).cloned()
+    }
+
+    /// Calls `f` on the value for `key` under the shard's read lock and returns its result,
+    /// for values that are expensive to clone.
+    pub fn with<R>(&self, key: &K, f: impl FnOnce(&V) -> R) -> Option<R> {
+        self.read_shard(key).get(key).map(f)
+    }
+
+    /// Removes the value for `key` and returns it.
+    pub fn remove(&self, key: &K) -> Option<V> {
+        self.write_shard(key).remove(key)
+    }
+
+    /// Returns true if the map holds a value for `key`.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.read_shard(key).contains_key(key)
+    }
+
+    /// Keeps only the entries for which `f` returns true. Shards are locked for writing one
+    /// at a time, so operations on the other shards go on meanwhile.
+    pub fn retain(&self, mut f: impl FnMut(&K, &mut V) -> bool) {
+        for shard in &self.shards {
+            shard.write().expect("lock acquire failed").retain(&mut f);
+        }
+    }
+
+    /// Iterates over copies of the entries. Each shard is copied at once under its read lock
+    /// when the iterator reaches it, so only one shard is locked at a time and briefly. Entries
+    /// of the same shard are seen as of one moment, a key that stays in the map during the
+    /// whole iteration is seen exactly once, keys added or removed meanwhile may be missed.
+    pub fn iter_snapshot(&self) -> Snapshot<'_, K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        Snapshot {
+            map: self,
+            shard: 0,
+            entries: Vec::new().into_iter(),
+        }
+    }
+
+    /// Returns the number of entries, may already be stale when other threads insert or
+    /// remove.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.read().expect("lock acquire failed").len())
+            .sum()
+    }
+
+    /// Returns true if the map contains no entries.
+    pub fn is_empty(&self) -> bool {
+        self.shards
+            .iter()
+            .all(|shard| shard.read().expect("lock acquire failed").is_empty())
+    }
+
+    /// Returns the index of the shard `key` is mapped to.
+    pub fn shard_of(&self, key: &K) -> usize {
+        (self.hasher.hash_one(key) % self.shards.len() as u64) as usize
+    }
+
+    fn read_shard(&self, key: &K) -> RwLockReadGuard<'_, HashMap<K, V>> {
+        self.shards[self.shard_of(key)]
+            .read()
+            .expect("lock acquire failed")
+    }
+
+    fn write_shard(&self, key: &K) -> RwLockWriteGuard<'_, HashMap<K, V>> {
+        self.shards[self.shard_of(key)]
+            .write()
+            .expect("lock acquire failed")
+    }
+}
+
+impl<K: Hash + Eq, V> Default for Mapus<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, K: Hash + Eq, V> Entry<'a, K, V> {
+    /// Returns the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Calls `f` on the value if the key has one.
+    pub fn and_modify(mut self, f: impl FnOnce(&mut V)) -> Self {
+        if let Some(value) = self.shard.get_mut(&self.key) {
+            f(value);
+        }
+        self
+    }
+
+    /// Inserts the value returned by `f` if the key has none, `f` is not called otherwise.
+    pub fn or_insert_with(self, f: impl FnOnce() -> V) -> RefMut<'a, K, V> {
+        let Entry { mut shard, key } = self;
+        let value: *mut V = shard.entry(key).or_insert_with(f);
+        RefMut {
+            _shard: shard,
+            value,
+        }
+    }
+
+    /// Inserts `value` if the key has none.
+    pub fn or_insert(self, value: V) -> RefMut<'a, K, V> {
+        self.or_insert_with(|| value)
+    }
+
+    /// Inserts the default value if the key has none.
+    pub fn or_default(self) -> RefMut<'a, K, V>
+    where
+        V: Default,
+    {
+        self.or_insert_with(V::default)
+    }
+}
+
+impl<K, V> Deref for RefMut<'_, K, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        unsafe { &*self.value }
+    }
+}
+
+impl<K, V> DerefMut for RefMut<'_, K, V> {
+    fn deref_mut(&mut self) -> &mut V {
+        unsafe { &mut *self.value }
+    }
+}
+
+impl<K: Clone, V: Clone> Iterator for Snapshot<'_, K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<(K, V)> {
+        loop {
+            if let Some(entry) = self.entries.next() {
+                return Some(entry);
+            }
+            let shard = self.map.shards.get(self.shard)?;
+            self.shard += 1;
+            let entries: Vec<_> = shard
+                .read()
+                .expect("lock acquire failed")
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            self.entries = entries.into_iter();
+        }
+    }
+}
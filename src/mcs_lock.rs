@@ -0,0 +1,122 @@
+use crate::lock::RawLock;
+use crate::spin::Backoff;
+use std::{
+    cell::RefCell,
+    ptr::null_mut,
+    sync::atomic::{AtomicBool, AtomicPtr, Ordering},
+};
+
+/// A queue lock, based on "Algorithms for Scalable Synchronization on Shared-Memory
+/// Multiprocessors" by John Mellor-Crummey and Michael Scott. Waiting threads form a linked
+/// queue and each one spins on a flag in its own node instead of on the shared lock word,
+/// so a release touches only the cache line of the next waiter. This keeps handover cost flat
+/// as the number of cores grows, and the lock is FIFO-fair like [crate::ticket_lock::TicketLock].
+/// Nodes are taken from a per-thread cache, so locking doesn't allocate after warmup.
+#[derive(Debug, Default)]
+pub struct McsLock {
+    /// Last node in the queue, null if the lock is free.
+    pub tail: AtomicPtr<McsNode>,
+    /// Node of the thread holding the lock, only touched by the holder.
+    pub holder: AtomicPtr<McsNode>,
+}
+
+/// Spin node of a thread holding or waiting for an [McsLock].
+#[derive(Debug, Default)]
+pub struct McsNode {
+    pub next: AtomicPtr<McsNode>,
+    pub locked: AtomicBool,
+}
+
+thread_local! {
+    // boxed so a node keeps its address while it is linked into a queue
+    #[allow(clippy::vec_box)]
+    static NODES: RefCell<Vec<Box<McsNode>>> = const { RefCell::new(Vec::new()) };
+}
+
+impl McsLock {
+    /// Creates a new unlocked lock.
+    pub const fn new() -> Self {
+        McsLock {
+            tail: AtomicPtr::new(null_mut()),
+            holder: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    /// Returns true if some thread holds the lock.
+    pub fn is_locked(&self) -> bool {
+        !self.tail.load(Ordering::Relaxed).is_null()
+    }
+
+    fn take_node() -> *mut McsNode {
+        let node = NODES
+            .with(|nodes| nodes.borrow_mut().pop())
+            .unwrap_or_default();
+        node.next.store(null_mut(), Ordering::Relaxed);
+        node.locked.store(true, Ordering::Relaxed);
+        Box::into_raw(node)
+    }
+
+    fn return_node(node: *mut McsNode) {
+        let node = unsafe { Box::from_raw(node) };
+        // during thread teardown the cache may be gone already, then the node is just freed
+        let _ = NODES.try_with(|nodes| nodes.borrow_mut().push(node));
+    }
+}
+
+unsafe impl RawLock for McsLock {
+    fn lock(&self) {
+        let node = Self::take_node();
+        let predecessor = self.tail.swap(node, Ordering::AcqRel);
+        if !predecessor.is_null() {
+            // link behind the predecessor and wait until it hands the lock over
+            unsafe { (*predecessor).next.store(node, Ordering::Release) };
+            let mut backoff = Backoff::new();
+            while unsafe { (*node).locked.load(Ordering::Acquire) } {
+                backoff.snooze();
+            }
+        }
+        self.holder.store(node, Ordering::Relaxed);
+    }
+
+    fn try_lock(&self) -> bool {
+        let node = Self::take_node();
+        match self
+            .tail
+            .compare_exchange(null_mut(), node, Ordering::AcqRel, Ordering::Relaxed)
+        {
+            Ok(_) => {
+                self.holder.store(node, Ordering::Relaxed);
+                true
+            }
+            Err(_) => {
+                Self::return_node(node);
+                false
+            }
+        }
+    }
+
+    unsafe fn unlock(&self) {
+        let node = self.holder.load(Ordering::Relaxed);
+        let mut next = (*node).next.load(Ordering::Acquire);
+        if next.is_null() {
+            // no known successor, try to mark the lock free
+            if self
+                .tail
+                .compare_exchange(node, null_mut(), Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                Self::return_node(node);
+                return;
+            }
+            // a successor swapped itself in but didn't link yet
+            let mut backoff = Backoff::new();
+            while next.is_null() {
+                backoff.snooze();
+                next = (*node).next.load(Ordering::Acquire);
+            }
+        }
+        (*next).locked.store(false, Ordering::Release);
+        // the successor is done with this node once it is linked
+        Self::return_node(node);
+    }
+}
@@ -1,190 +1,1236 @@
-use std::sync::{Arc, Condvar, Mutex};
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::chaos;
+use crate::ewma::Ewma;
+use crate::histogram::{Histogram, HistogramSnapshot};
+use crate::lock::{DefaultLock, Lock, LockGuard, RawLock};
+use crate::parker::{Parker, Unparker};
+use crate::semaphore::Semaphore;
+use std::{
+    collections::VecDeque,
+    fmt, mem,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, LockResult, Mutex, MutexGuard, PoisonError, TryLockError, Weak,
+    },
+    time::{Duration, Instant},
+};
 /// A lock-based general purpose queue. Implenemented based on the book
 /// "C++ Concurrency in Action: Practical Multithreading" by Anthony Williams.
 /// This queue uses 1 lock for head and 1 for tail, push() works on 1 lock and pop() uses 2 locks
-/// if there is no data in head and it has to try to look in a tail.
-#[derive(Debug, Clone)]
-pub struct Multiq<T: Clone> {
-    pub queue: Arc<InnerMultiq<T>>,
+/// if there is no data in head and it has to try to look in a tail. Each side is a [VecDeque],
+/// push appends to the tail and a pop that finds the head empty swaps the whole tail into it,
+/// so both are O(1) however long the queue is.
+/// Both locks are of type `L`, see [crate::lock::RawLock] for the available strategies.
+pub struct Multiq<T, L: RawLock = DefaultLock> {
+    pub queue: Arc<InnerMultiq<T, L>>,
 }
 
+/// A handle of a [Multiq] that doesn't keep the queue alive, returned by [Multiq::downgrade].
+/// Values stored in the queue can hold one to reach their own queue, e.g. a job that requeues
+/// itself, without a reference cycle that would leak the queue and everything in it.
 #[derive(Debug)]
-pub struct InnerMultiq<T: Clone> {
-    pub cvar: Condvar,
-    pub head: Mutex<Data<T>>,
-    pub tail: Mutex<Data<T>>,
+pub struct WeakMultiq<T, L: RawLock = DefaultLock> {
+    pub queue: Weak<InnerMultiq<T, L>>,
 }
 
-#[derive(Debug, Clone)]
-pub struct Data<T: Clone> {
-    pub contents: (Option<T>, Option<Box<Data<T>>>),
+#[derive(Debug)]
+pub struct InnerMultiq<T, L: RawLock = DefaultLock> {
+    /// Consumers blocked in wait_and_pop, woken one per push in arrival order.
+    pub waiters: Mutex<VecDeque<Unparker>>,
+    /// Length of waiters, read without the lock so a push with nobody waiting skips it.
+    pub waiting: AtomicUsize,
+    /// Consumers blocked in wait_and_peek, all woken by every push since a peek takes nothing
+    /// and must not use up the wakeup of a blocked pop.
+    pub peekers: Mutex<VecDeque<Unparker>>,
+    /// Length of peekers, like waiting.
+    pub peeking: AtomicUsize,
+    /// Threads inside wait_and_pop, see [Multiq::waiting_consumers].
+    pub consumers: AtomicUsize,
+    pub head: Lock<VecDeque<T>, L>,
+    pub tail: Lock<VecDeque<T>, L>,
+    pub budget: Option<ByteBudget<T>>,
+    pub stats: QueueStats,
+    pub poison_policy: PoisonPolicy,
+    /// Whether wait_and_pop callers take turns, see [Multiq::set_round_robin].
+    pub round_robin: AtomicBool,
+    pub turnstile: Turnstile,
+    /// Set by [Multiq::with_latency_histogram].
+    pub dwell: Option<DwellTimes>,
+    /// Set by [Multiq::close].
+    pub closed: AtomicBool,
+    /// Set by [Multiq::forget_on_drop].
+    pub forget_on_drop: AtomicBool,
+}
+
+/// Push times of the queued values and the histogram of how long popped values were queued.
+/// A queue hands values out in push order, so the push times are kept in a FIFO of their own
+/// next to the values instead of in the values' nodes. It is updated under the same lock as the
+/// values, at the tail on push and at the head on pop, so both stay in step.
+#[derive(Debug, Default)]
+pub struct DwellTimes {
+    pub pushed_at: Mutex<VecDeque<Instant>>,
+    pub histogram: Histogram,
+}
+
+/// Lets consumers into wait_and_pop one at a time in arrival order, the one leaving hands the
+/// turn directly to the next in line so a returning consumer can't jump the queue.
+#[derive(Debug, Default)]
+pub struct Turnstile {
+    /// Whether a consumer holds the turn, and the consumers waiting for it.
+    pub state: Mutex<(bool, VecDeque<Unparker>)>,
+}
+
+/// Holds the turn of a [Turnstile] and passes it on when dropped.
+#[derive(Debug)]
+pub struct Turn<'a> {
+    pub turnstile: &'a Turnstile,
+}
+
+/// Counts a consumer in [InnerMultiq::consumers] until it leaves wait_and_pop.
+struct Waiting<'a>(&'a AtomicUsize);
+
+/// What a queue does when a thread panicked while holding one of its locks, e.g. inside the
+/// weight function of a byte budget. The queue itself is never left half updated by such a panic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PoisonPolicy {
+    /// Keep using the queue as if nothing happened.
+    #[default]
+    Ignore,
+    /// Fail every later operation, `try_*` methods return [QueuePoisoned] and the others panic.
+    Propagate,
+}
+
+/// Error returned by the `try_*` methods of a queue with [PoisonPolicy::Propagate] after
+/// a thread panicked while holding one of its locks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuePoisoned;
+
+impl fmt::Display for QueuePoisoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a thread panicked while holding a queue lock")
+    }
+}
+
+impl std::error::Error for QueuePoisoned {}
+
+/// Error returned by the waiting pops of a queue that is empty and [Multiq::close]d.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueClosed;
+
+impl fmt::Display for QueueClosed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the queue is closed")
+    }
+}
+
+impl std::error::Error for QueueClosed {}
+
+/// Which wait list of a queue a blocked consumer is in.
+#[derive(Debug, Clone, Copy)]
+enum Waiter {
+    Pop,
+    Peek,
+}
+
+/// Why a waiting pop returned without a value.
+enum Interrupted {
+    Closed,
+    Cancelled,
+}
+
+/// Number of values at the front the Debug output of a [Multiq] shows.
+const DEBUG_VALUES: usize = 8;
+
+/// Weight of a new sample in the load averages, about the last 20 operations dominate.
+pub(crate) const LOAD_ALPHA: f64 = 0.1;
+
+/// Load counters updated by every push and pop, see [Multiq::load_stats].
+#[derive(Debug)]
+pub struct QueueStats {
+    pub depth: AtomicUsize,
+    pub depth_average: Ewma,
+    /// Seconds consumers spent in wait_and_pop.
+    pub wait_average: Ewma,
+}
+
+/// Snapshot of a queue's load returned by [Multiq::load_stats].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LoadStats {
+    /// Number of values in the queue right now.
+    pub depth: usize,
+    /// Moving average of the depth seen by pushes and pops.
+    pub average_depth: f64,
+    /// Moving average of how long wait_and_pop blocked, zero when values were ready.
+    pub average_wait: Duration,
+}
+
+impl QueueStats {
+    fn new(depth: usize) -> Self {
+        QueueStats {
+            depth: AtomicUsize::new(depth),
+            depth_average: Ewma::new(LOAD_ALPHA),
+            wait_average: Ewma::new(LOAD_ALPHA),
+        }
+    }
+
+    fn pushed(&self, count: usize) {
+        let depth = self.depth.fetch_add(count, Ordering::Relaxed) + count;
+        self.depth_average.update(depth as f64);
+    }
+
+    fn popped(&self, count: usize) {
+        let depth = self.depth.fetch_sub(count, Ordering::Relaxed) - count;
+        self.depth_average.update(depth as f64);
+    }
+}
+
+/// A handle for one producer thread that collects values and pushes them into a [Multiq] in
+/// batches of `capacity`, taking the tail lock once per batch instead of once per value.
+/// Consumers see the values when the batch is full, on [Producer::flush] or when the handle
+/// is dropped.
+#[derive(Debug)]
+pub struct Producer<T, L: RawLock = DefaultLock> {
+    pub queue: Multiq<T, L>,
+    pub buffer: Vec<T>,
+    pub capacity: usize,
+}
+
+/// A handle for one consumer thread that pops up to `capacity` values at once and hands them
+/// out one by one, taking the locks once per batch instead of once per value. Values still
+/// buffered when the handle is dropped go back to the front of the queue.
+#[derive(Debug)]
+pub struct Consumer<T, L: RawLock = DefaultLock> {
+    pub queue: Multiq<T, L>,
+    pub buffer: VecDeque<T>,
+    pub capacity: usize,
+}
+
+/// The sending half of a queue split by [Multiq::split].
+#[derive(Debug)]
+pub struct MultiqSender<T, L: RawLock = DefaultLock> {
+    pub queue: Multiq<T, L>,
+    /// Number of senders of the queue, the last one dropped closes it.
+    pub senders: Arc<AtomicUsize>,
+}
+
+/// The receiving half of a queue split by [Multiq::split].
+#[derive(Debug)]
+pub struct MultiqReceiver<T, L: RawLock = DefaultLock> {
+    pub queue: Multiq<T, L>,
+}
+
+/// Iterator over the values taken by [Multiq::drain], front first.
+#[derive(Debug)]
+pub struct Drain<T> {
+    pub values: std::vec::IntoIter<T>,
+}
+
+/// Limits the total weight of the values held by a queue, see [Multiq::with_byte_budget].
+#[derive(Debug)]
+pub struct ByteBudget<T> {
+    pub semaphore: Semaphore,
+    pub weight: fn(&T) -> usize,
+    /// Permits held by values [Multiq::push_front] let in over the budget, paid back by the
+    /// next pops before they release anything.
+    pub overdrawn: AtomicUsize,
+}
+
+impl<T> ByteBudget<T> {
+    /// Returns the number of permits `value` holds while it is in the queue.
+    fn permits(&self, value: &T) -> usize {
+        (self.weight)(value).min(self.semaphore.capacity)
+    }
+
+    /// Takes the permits of a requeued value without waiting, going over the budget if they
+    /// are not available.
+    fn overdraw(&self, value: &T) {
+        let permits = self.permits(value);
+        if self.semaphore.try_acquire(permits).is_none() {
+            self.overdrawn.fetch_add(permits, Ordering::Relaxed);
+        }
+    }
+
+    /// Gives back the permits of a popped value, paying back overdrawn ones first.
+    fn release(&self, value: &T) {
+        let permits = self.permits(value);
+        let owed = self
+            .overdrawn
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |owed| {
+                Some(owed - owed.min(permits))
+            })
+            .expect("the update never fails");
+        let released = permits - owed.min(permits);
+        if released > 0 {
+            self.semaphore.release(released);
+        }
+    }
+}
+
+impl Turnstile {
+    /// Waits for the turn, returns [None] if `token` is cancelled first.
+    fn enter(&self, token: Option<&CancellationToken>) -> Option<Turn<'_>> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.0 {
+            state.0 = true;
+            return Some(Turn { turnstile: self });
+        }
+        let parker = Parker::new();
+        let unparker = parker.unparker();
+        state.1.push_back(unparker.clone());
+        drop(state);
+        let _cancel_guard = token.map(|token| {
+            let unparker = unparker.clone();
+            token.on_cancel(move || unparker.unpark())
+        });
+        loop {
+            parker.park();
+            let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+            // leaving consumers remove the next one from the line when handing over
+            if !state.1.contains(&unparker) {
+                return Some(Turn { turnstile: self });
+            }
+            if token.is_some_and(CancellationToken::is_cancelled) {
+                state.1.retain(|waiter| waiter != &unparker);
+                return None;
+            }
+        }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        let mut state = self
+            .turnstile
+            .state
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match state.1.pop_front() {
+            // the turn stays taken and goes straight to the next in line
+            Some(next) => next.unpark(),
+            None => state.0 = false,
+        }
+    }
 }
 
-impl<T: Clone> Data<T> {
-    pub fn new(value: T) -> Data<T> {
-        Data {
-            contents: (Some(value), None),
+impl DwellTimes {
+    fn pushed(&self, count: usize) {
+        let now = Instant::now();
+        let mut pushed_at = self.pushed_at.lock().expect("lock acquire failed");
+        pushed_at.extend(std::iter::repeat_n(now, count));
+    }
+
+    fn pushed_front(&self) {
+        let now = Instant::now();
+        let mut pushed_at = self.pushed_at.lock().expect("lock acquire failed");
+        pushed_at.push_front(now);
+    }
+
+    fn popped(&self, count: usize) {
+        let now = Instant::now();
+        let mut pushed_at = self.pushed_at.lock().expect("lock acquire failed");
+        let count = count.min(pushed_at.len());
+        for pushed in pushed_at.drain(..count) {
+            self.histogram.record(now - pushed);
+        }
+    }
+}
+
+impl<T, L: RawLock> Drop for InnerMultiq<T, L> {
+    fn drop(&mut self) {
+        if *self.forget_on_drop.get_mut() {
+            for values in [&mut self.head, &mut self.tail] {
+                let values = values.get_mut().unwrap_or_else(PoisonError::into_inner);
+                mem::forget(mem::take(values));
+            }
         }
     }
 }
 
-impl<T: Clone + PartialEq + std::fmt::Debug> Multiq<T> {
+/// Shows the state of the queue and its first few values. The values are skipped while
+/// another thread holds a lock, so formatting never blocks.
+impl<T: fmt::Debug, L: RawLock> fmt::Debug for Multiq<T, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Multiq");
+        debug
+            .field("len", &self.queue.stats.depth.load(Ordering::Relaxed))
+            .field("closed", &self.is_closed())
+            .field("waiting_consumers", &self.waiting_consumers());
+        match (try_lock(&self.queue.head), try_lock(&self.queue.tail)) {
+            (Some(head), Some(tail)) => debug.field(
+                "front",
+                &head
+                    .iter()
+                    .chain(tail.iter())
+                    .take(DEBUG_VALUES)
+                    .collect::<Vec<_>>(),
+            ),
+            _ => debug.field("front", &format_args!("<locked>")),
+        };
+        debug.finish()
+    }
+}
+
+/// Locks `lock` if it is free, poisoned or not.
+fn try_lock<T, L: RawLock>(lock: &Lock<VecDeque<T>, L>) -> Option<LockGuard<'_, VecDeque<T>, L>> {
+    match lock.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(poisoned)) => Some(poisoned.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+impl<T, L: RawLock> Clone for Multiq<T, L> {
+    fn clone(&self) -> Self {
+        Multiq {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+/// Compares the values of both queues front to back, holding the locks of both. The queues
+/// are locked in address order, so two threads comparing the same pair the other way round
+/// don't deadlock.
+impl<T: PartialEq, L: RawLock> PartialEq for Multiq<T, L> {
+    fn eq(&self, other: &Self) -> bool {
+        if Arc::ptr_eq(&self.queue, &other.queue) {
+            return true;
+        }
+        let (first, second) = if Arc::as_ptr(&self.queue) < Arc::as_ptr(&other.queue) {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let first_head = first.lock(&first.queue.head).expect("queue poisoned");
+        let first_tail = first.lock(&first.queue.tail).expect("queue poisoned");
+        let second_head = second.lock(&second.queue.head).expect("queue poisoned");
+        let second_tail = second.lock(&second.queue.tail).expect("queue poisoned");
+        first_head.len() + first_tail.len() == second_head.len() + second_tail.len()
+            && first_head
+                .iter()
+                .chain(first_tail.iter())
+                .eq(second_head.iter().chain(second_tail.iter()))
+    }
+}
+
+impl<T: Eq, L: RawLock> Eq for Multiq<T, L> {}
+
+impl<T> Multiq<T> {
     /// Creates a new queue.
     pub fn new(value: T) -> Multiq<T> {
-        let queue = Data::new(value);
-        let empty = Mutex::new(Data {
-            contents: (None, None),
-        });
+        Self::with_lock(value)
+    }
+
+    /// Creates a new queue where the total `weight` of queued values never exceeds `budget`,
+    /// e.g. the sum of message sizes in bytes. Push waits until enough values are popped to
+    /// fit the new one, a single value heavier than the whole budget is counted as the budget.
+    /// Only values requeued with [Multiq::push_front] may take the queue over it.
+    pub fn with_byte_budget(value: T, budget: usize, weight: fn(&T) -> usize) -> Multiq<T> {
+        let budget = ByteBudget {
+            semaphore: Semaphore::new(budget),
+            weight,
+            overdrawn: AtomicUsize::new(0),
+        };
+        budget.semaphore.acquire(budget.permits(&value));
+        Self::from_parts([value].into(), Some(budget), PoisonPolicy::default())
+    }
+
+    /// Creates an empty queue split into its two halves, see [Multiq::split].
+    pub fn channel() -> (MultiqSender<T>, MultiqReceiver<T>) {
+        Self::from_parts(VecDeque::new(), None, PoisonPolicy::default()).split()
+    }
+}
+
+impl<T, L: RawLock> Multiq<T, L> {
+    /// Creates a new queue guarded by locks of type `L`,
+    /// e.g. `Multiq::<_, TicketLock>::with_lock(value)` for FIFO-fair locking.
+    pub fn with_lock(value: T) -> Multiq<T, L> {
+        Self::from_parts([value].into(), None, PoisonPolicy::default())
+    }
+
+    /// Creates a new queue that handles poisoned locks according to `policy`.
+    pub fn with_poison_policy(value: T, policy: PoisonPolicy) -> Multiq<T, L> {
+        Self::from_parts([value].into(), None, policy)
+    }
+
+    /// Creates a new queue that records how long each value waited between push and pop in
+    /// a histogram, see [Multiq::latency_snapshot]. Costs a timestamp and a short extra lock
+    /// per push and pop.
+    pub fn with_latency_histogram(value: T) -> Multiq<T, L> {
+        let mut queue = Self::from_parts([value].into(), None, PoisonPolicy::default());
+        let dwell = DwellTimes::default();
+        // the first value
+        dwell.pushed(1);
+        Arc::get_mut(&mut queue.queue)
+            .expect("queue is not shared yet")
+            .dwell = Some(dwell);
+        queue
+    }
+
+    fn from_parts(
+        values: VecDeque<T>,
+        budget: Option<ByteBudget<T>>,
+        poison_policy: PoisonPolicy,
+    ) -> Multiq<T, L> {
         Multiq {
             queue: InnerMultiq {
-                cvar: Condvar::new(),
-                head: Mutex::new(queue),
-                tail: empty,
+                waiters: Mutex::new(VecDeque::new()),
+                waiting: AtomicUsize::new(0),
+                peekers: Mutex::new(VecDeque::new()),
+                peeking: AtomicUsize::new(0),
+                consumers: AtomicUsize::new(0),
+                stats: QueueStats::new(values.len()),
+                head: Lock::new(values),
+                tail: Lock::new(VecDeque::new()),
+                budget,
+                poison_policy,
+                round_robin: AtomicBool::new(false),
+                turnstile: Turnstile::default(),
+                dwell: None,
+                closed: AtomicBool::new(false),
+                forget_on_drop: AtomicBool::new(false),
             }
             .into(),
         }
     }
 
     /// Tales a value from the front of the queue.
-    pub fn pop(&mut self) -> Option<T> {
-        let head = &mut self
-            .queue
-            .head
-            .lock()
-            .expect("lock acquire failed")
-            .contents;
-        let mut value = None;
-        if head.0.is_some() {
-            value = head.0.clone();
-            // shift head to next element
-            if head.1.is_some() {
-                *head = head.1.clone().unwrap().contents;
-            } else {
-                // try to add contents to head from tail
-                let tail = &mut self
-                    .queue
-                    .tail
-                    .lock()
-                    .expect("lock acquire failed")
-                    .contents;
-                if tail.0.is_none() {
-                    // nothing left
-                    *head = (None, None);
-                } else {
-                    // load contents from tail and make tail empty
-                    *head = tail.clone();
-                }
-                *tail = (None, None);
+    pub fn pop(&self) -> Option<T> {
+        self.try_pop().expect("queue poisoned")
+    }
+
+    /// Like [Multiq::pop] but returns an error instead of panicking if the queue is poisoned.
+    pub fn try_pop(&self) -> Result<Option<T>, QueuePoisoned> {
+        let value = self.take_front()?;
+        if let Some(value) = &value {
+            self.release_budget(value);
+            self.queue.stats.popped(1);
+        }
+        Ok(value)
+    }
+
+    /// Pop that waits for a new value to be pushed into queue if it's empty. Returns
+    /// [QueueClosed] once the queue is empty and [Multiq::close]d.
+    pub fn wait_and_pop(&self) -> Result<T, QueueClosed> {
+        self.try_wait_and_pop().expect("queue poisoned")
+    }
+
+    /// Like [Multiq::wait_and_pop] but returns an error instead of panicking if the queue
+    /// is poisoned.
+    pub fn try_wait_and_pop(&self) -> Result<Result<T, QueueClosed>, QueuePoisoned> {
+        Ok(self
+            .wait_and_pop_inner(None)?
+            .map_err(|interrupted| match interrupted {
+                Interrupted::Closed => QueueClosed,
+                Interrupted::Cancelled => unreachable!("not cancellable"),
+            }))
+    }
+
+    /// Like [Multiq::wait_and_pop] but gives up once `token` is cancelled. An empty closed
+    /// queue counts as cancelled too.
+    pub fn wait_and_pop_cancellable(&self, token: &CancellationToken) -> Result<T, Cancelled> {
+        self.wait_and_pop_inner(Some(token))
+            .expect("queue poisoned")
+            .map_err(|_| Cancelled)
+    }
+
+    /// Closes the queue, consumers blocked in wait_and_pop or wait_and_peek wake up and those
+    /// calls return [QueueClosed] from now on whenever the queue is empty, e.g. to stop the
+    /// workers at shutdown. Values still queued are handed out first, pushes keep working.
+    pub fn close(&self) {
+        // set under the tail lock, so a consumer sees it in the same look as the values
+        let tail_lock = self.lock(&self.queue.tail).expect("queue poisoned");
+        self.queue.closed.store(true, Ordering::SeqCst);
+        drop(tail_lock);
+        self.wake(usize::MAX);
+    }
+
+    /// Returns true if [Multiq::close] was called.
+    pub fn is_closed(&self) -> bool {
+        self.queue.closed.load(Ordering::SeqCst)
+    }
+
+    /// Makes consumers blocked in wait_and_pop take turns in arrival order. Without it a
+    /// consumer that comes back right after popping often wins against those already waiting,
+    /// which keeps one hot consumer busy while the others starve. Costs a hand-off per call.
+    pub fn set_round_robin(&self, enabled: bool) {
+        self.queue.round_robin.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Pops a value, waiting until one is pushed, the queue is closed or `token` is cancelled.
+    fn wait_and_pop_inner(
+        &self,
+        token: Option<&CancellationToken>,
+    ) -> Result<Result<T, Interrupted>, QueuePoisoned> {
+        let start = Instant::now();
+        self.queue.consumers.fetch_add(1, Ordering::Relaxed);
+        let _waiting = Waiting(&self.queue.consumers);
+        let _turn = if self.queue.round_robin.load(Ordering::Relaxed) {
+            match self.queue.turnstile.enter(token) {
+                Some(turn) => Some(turn),
+                None => return Ok(Err(Interrupted::Cancelled)),
             }
         } else {
-            // try to pop from tail
-            let tail = &mut self
-                .queue
-                .tail
-                .lock()
-                .expect("lock acquire failed")
-                .contents;
-            if tail.0.is_some() {
-                // pop from tail and load head from tail
-                value = tail.0.clone();
-                // update head if possible
-                if tail.1.is_some() {
-                    *head = tail.1.clone().unwrap().contents
+            None
+        };
+        let value = match self.wait_for(Waiter::Pop, token, || self.take_front())? {
+            Ok(value) => value,
+            Err(interrupted) => return Ok(Err(interrupted)),
+        };
+        self.release_budget(&value);
+        self.queue.stats.popped(1);
+        self.queue
+            .stats
+            .wait_average
+            .update(start.elapsed().as_secs_f64());
+        Ok(Ok(value))
+    }
+
+    /// Takes the value at the front, refilling the head from the tail when it runs empty.
+    fn take_front(&self) -> Result<Option<T>, QueuePoisoned> {
+        let mut head = self.lock(&self.queue.head)?;
+        let value = if head.len() > 1 {
+            head.pop_front()
+        } else {
+            // the head runs empty, refill it from the tail, locked before the value is taken
+            // so a poisoned tail leaves it in place
+            chaos::yield_point();
+            let mut tail = self.lock(&self.queue.tail)?;
+            let value = head.pop_front();
+            // the head takes everything pushed so far, the tail keeps the head's buffer
+            mem::swap(&mut *head, &mut *tail);
+            value.or_else(|| head.pop_front())
+        };
+        if value.is_some() {
+            self.dwell_popped(1);
+        }
+        Ok(value)
+    }
+
+    /// Calls `look` until it finds something, parking in between. Holds no queue lock while
+    /// parked, so pushes, pops and drains of other threads go on meanwhile.
+    fn wait_for<R>(
+        &self,
+        waiter: Waiter,
+        token: Option<&CancellationToken>,
+        mut look: impl FnMut() -> Result<Option<R>, QueuePoisoned>,
+    ) -> Result<Result<R, Interrupted>, QueuePoisoned> {
+        if let Some(found) = look()? {
+            return Ok(Ok(found));
+        }
+        let parker = Parker::new();
+        let unparker = parker.unparker();
+        let _cancel_guard = token.map(|token| {
+            let unparker = unparker.clone();
+            token.on_cancel(move || unparker.unpark())
+        });
+        loop {
+            // registered before looking again, so a push or close the look misses happens
+            // after it and wakes this thread
+            self.register_waiter(waiter, &unparker);
+            chaos::yield_point();
+            let found = look();
+            // a value that is already there is still taken, so no push is lost
+            let interrupted = if self.queue.closed.load(Ordering::SeqCst) {
+                Some(Interrupted::Closed)
+            } else if token.is_some_and(CancellationToken::is_cancelled) {
+                Some(Interrupted::Cancelled)
+            } else {
+                None
+            };
+            match (found, interrupted) {
+                (Ok(None), None) => {}
+                (found, interrupted) => {
+                    if !self.unregister_waiter(waiter, &unparker) {
+                        // a push woke this thread while it was looking anyway, the wakeup is
+                        // passed on so a waiter behind it doesn't sleep through the value
+                        self.wake_waiters(waiter, 1);
+                    }
+                    return Ok(found?.ok_or_else(|| interrupted.expect("interrupted")));
                 }
-                // remove contents of tail
-                *tail = (None, None);
             }
+            chaos::yield_point();
+            parker.park();
+            chaos::yield_point();
+            // woken spuriously, by a push or by a close, either way look again
+            self.unregister_waiter(waiter, &unparker);
+        }
+    }
+
+    /// Pushes a value into the back of the queue.
+    pub fn push(&self, value: T) {
+        self.try_push(value).expect("queue poisoned")
+    }
+
+    /// Like [Multiq::push] but returns an error instead of panicking if the queue is poisoned.
+    pub fn try_push(&self, value: T) -> Result<(), QueuePoisoned> {
+        if let Some(budget) = &self.queue.budget {
+            budget.semaphore.acquire(budget.permits(&value));
+        }
+        let mut tail_lock = match self.lock(&self.queue.tail) {
+            Ok(tail_lock) => tail_lock,
+            Err(poisoned) => {
+                self.release_budget(&value);
+                return Err(poisoned);
+            }
+        };
+        // counted before the value is visible, so a pop never takes the depth below zero
+        self.queue.stats.pushed(1);
+        if let Some(dwell) = &self.queue.dwell {
+            dwell.pushed(1);
         }
-        value
+        tail_lock.push_back(value);
+        drop(tail_lock);
+        self.wake(1);
+        Ok(())
     }
 
-    /// Pop that waits for a new value to be pushed into queue if it's empty.
-    pub fn wait_and_pop(&mut self) -> T {
-        let head = &mut self
+    /// Puts `value` back at the front of the queue, e.g. after a consumer failed to process it,
+    /// so retries keep their order instead of going behind everything pushed since. Never waits
+    /// for the byte budget, the value was let in once already: if producers took the budget
+    /// meanwhile the queue goes over it until the next pops.
+    pub fn push_front(&self, value: T) {
+        if let Some(budget) = &self.queue.budget {
+            budget.overdraw(&value);
+        }
+        let mut head_lock = self.lock(&self.queue.head).expect("queue poisoned");
+        self.queue.stats.pushed(1);
+        if let Some(dwell) = &self.queue.dwell {
+            dwell.pushed_front();
+        }
+        // goes in front of the values in the tail too, they are only popped after the head
+        head_lock.push_front(value);
+        drop(head_lock);
+        self.wake(1);
+    }
+
+    /// Puts the buffered values of a dropped [Consumer] back at the front in order, like
+    /// [Multiq::push_front] but under one head lock. Runs in drop, so it never waits for the
+    /// budget and uses a poisoned head anyway instead of panicking.
+    fn requeue_front(&self, values: VecDeque<T>) {
+        let count = values.len();
+        if count == 0 {
+            return;
+        }
+        if let Some(budget) = &self.queue.budget {
+            for value in &values {
+                budget.overdraw(value);
+            }
+        }
+        let mut head_lock = self
             .queue
             .head
             .lock()
-            .expect("lock acquire failed")
-            .contents;
-        let value;
-        if head.0.is_some() {
-            value = head.0.clone();
-            // shift head to next element
-            if head.1.is_some() {
-                *head = head.1.clone().unwrap().contents;
-            } else {
-                // try to add contents to head from tail
-                let tail = &mut self
-                    .queue
-                    .tail
-                    .lock()
-                    .expect("lock acquire failed")
-                    .contents;
-                if tail.0.is_none() {
-                    // nothing left
-                    *head = (None, None);
-                } else {
-                    // load contents from tail and make tail empty
-                    *head = tail.clone();
-                }
-                *tail = (None, None);
+            .unwrap_or_else(PoisonError::into_inner);
+        self.queue.stats.pushed(count);
+        if let Some(dwell) = &self.queue.dwell {
+            for _ in 0..count {
+                dwell.pushed_front();
             }
-        } else {
-            // try to pop from tail
-            let mut tail_lock = self.queue.tail.lock().expect("lock acquire failed");
-            if tail_lock.contents.0.is_some() {
-                // pop from tail and load head from tail
-                value = tail_lock.contents.0.clone();
-                // update head if possible
-                if tail_lock.contents.1.is_some() {
-                    *head = tail_lock.contents.1.clone().unwrap().contents;
-                }
-            // wait for value to be pushed into tail
-            } else {
-                while tail_lock.contents.0.is_none() {
-                    tail_lock = self.queue.cvar.wait(tail_lock).unwrap();
-                }
-                value = tail_lock.contents.0.clone();
+        }
+        for value in values.into_iter().rev() {
+            head_lock.push_front(value);
+        }
+        drop(head_lock);
+        self.wake(count);
+    }
+
+    /// Pushes all `values` into the back of the queue in order, taking the tail lock once and
+    /// waking at most one waiting consumer per value. Under a byte budget the values go in as
+    /// they get their permits: once the next value doesn't fit, the ones before it are pushed
+    /// and the rest waits for pops, so a batch heavier than the whole budget still goes in.
+    pub fn push_all<I: IntoIterator<Item = T>>(&self, values: I) {
+        let Some(budget) = &self.queue.budget else {
+            return self.push_batch(values.into_iter().collect());
+        };
+        let mut batch = Vec::new();
+        for value in values {
+            let permits = budget.permits(&value);
+            if budget.semaphore.try_acquire(permits).is_none() {
+                // the values taken so far may be what holds the budget on an empty queue
+                self.push_batch(mem::take(&mut batch));
+                budget.semaphore.acquire(permits);
             }
-            // remove contents of tail
-            tail_lock.contents = (None, None);
+            batch.push(value);
         }
-        // always waits for value so can unwrap
-        value.unwrap()
+        self.push_batch(batch);
     }
 
-    /// Pushes a value into the back of the queue.
-    pub fn push(&mut self, value: T) {
-        let mut tail_lock = self.queue.tail.lock().expect("lock acquire failed");
-        if tail_lock.contents.0.is_none() {
-            tail_lock.contents = (Some(value), None);
+    /// Pushes `values` that hold their permits already under one tail lock, giving the permits
+    /// back if the queue is poisoned.
+    fn push_batch(&self, values: Vec<T>) {
+        let count = values.len();
+        if count == 0 {
+            return;
+        }
+        let mut tail_lock = self
+            .lock(&self.queue.tail)
+            .inspect_err(|_| values.iter().for_each(|value| self.release_budget(value)))
+            .expect("queue poisoned");
+        self.queue.stats.pushed(count);
+        if let Some(dwell) = &self.queue.dwell {
+            dwell.pushed(count);
+        }
+        tail_lock.extend(values);
+        drop(tail_lock);
+        self.wake(count);
+    }
+
+    /// Takes up to `n` values from the front of the queue, locking the head and the tail at
+    /// most once each. Returns an empty batch if the queue is empty.
+    pub fn pop_up_to(&self, n: usize) -> Vec<T> {
+        let mut head_lock = self.lock(&self.queue.head).expect("queue poisoned");
+        let taken = n.min(head_lock.len());
+        let mut batch: Vec<T> = head_lock.drain(..taken).collect();
+        if batch.len() < n {
+            // the head ran out, continue with everything pushed so far
+            let mut tail_lock = self.lock(&self.queue.tail).expect("queue poisoned");
+            mem::swap(&mut *head_lock, &mut *tail_lock);
             drop(tail_lock);
-        } else {
-            let mut next = &mut tail_lock.contents.1;
-            while next.is_some() {
-                next = &mut next.as_mut().unwrap().contents.1;
-            }
-            *next = Some(Box::new(Data {
-                contents: (Some(value), None),
-            }));
+            let rest = (n - batch.len()).min(head_lock.len());
+            batch.extend(head_lock.drain(..rest));
+        }
+        self.dwell_popped(batch.len());
+        drop(head_lock);
+        for value in &batch {
+            self.release_budget(value);
+        }
+        if !batch.is_empty() {
+            self.queue.stats.popped(batch.len());
+        }
+        batch
+    }
+
+    /// Takes every value currently in the queue, e.g. so shutdown code can log or requeue the
+    /// ones nobody processed. Both locks are held only to detach the values, not while they
+    /// are collected.
+    pub fn drain(&self) -> Drain<T> {
+        let [head, tail] = {
+            let mut head_lock = self.lock(&self.queue.head).expect("queue poisoned");
+            let mut tail_lock = self.lock(&self.queue.tail).expect("queue poisoned");
+            self.dwell_popped(usize::MAX);
+            [mem::take(&mut *head_lock), mem::take(&mut *tail_lock)]
+        };
+        let mut values = Vec::from(head);
+        values.extend(tail);
+        for value in &values {
+            self.release_budget(value);
+        }
+        if !values.is_empty() {
+            self.queue.stats.popped(values.len());
+        }
+        Drain {
+            values: values.into_iter(),
+        }
+    }
+
+    /// Consumes the handle and returns the values front to back. If it is the last handle the
+    /// values are moved out without taking a lock, otherwise they are drained like
+    /// [Multiq::drain] and the other handles see an empty queue. Like the other methods it
+    /// panics on a poisoned queue only with [PoisonPolicy::Propagate].
+    pub fn into_vec(mut self) -> Vec<T> {
+        let Some(inner) = Arc::get_mut(&mut self.queue) else {
+            return self.drain().collect();
+        };
+        let policy = inner.poison_policy;
+        let take = |values: LockResult<&mut VecDeque<T>>| match (values, policy) {
+            (Ok(values), _) => mem::take(values),
+            (Err(poisoned), PoisonPolicy::Ignore) => mem::take(poisoned.into_inner()),
+            (Err(_), PoisonPolicy::Propagate) => panic!("queue poisoned"),
+        };
+        let head = take(inner.head.get_mut());
+        let tail = take(inner.tail.get_mut());
+        let mut values = Vec::from(head);
+        values.extend(tail);
+        values
+    }
+
+    /// Returns copies of the values front to back without removing them, e.g. to check the
+    /// contents in a test, see [crate::assert_contents_eq].
+    pub fn to_vec(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        let head = self.lock(&self.queue.head).expect("queue poisoned");
+        let tail = self.lock(&self.queue.tail).expect("queue poisoned");
+        head.iter().chain(tail.iter()).cloned().collect()
+    }
+
+    /// Returns a copy of the value at the front of the queue without removing it, e.g. so a
+    /// dispatcher can read its routing key before deciding which worker pops it. Another
+    /// consumer may pop the value before this thread does.
+    pub fn peek(&self) -> Option<T>
+    where
+        T: Clone,
+    {
+        let head = self.lock(&self.queue.head).expect("queue poisoned");
+        if let Some(value) = head.front() {
+            return Some(value.clone());
+        }
+        let tail = self.lock(&self.queue.tail).expect("queue poisoned");
+        tail.front().cloned()
+    }
+
+    /// Like [Multiq::peek] but waits for a value to be pushed if the queue is empty. Returns
+    /// [QueueClosed] once the queue is empty and [Multiq::close]d.
+    pub fn wait_and_peek(&self) -> Result<T, QueueClosed>
+    where
+        T: Clone,
+    {
+        self.wait_for(Waiter::Peek, None, || Ok(self.peek()))
+            .expect("queue poisoned")
+            .map_err(|_| QueueClosed)
+    }
+
+    /// Returns a handle that doesn't keep the queue alive, see [WeakMultiq].
+    pub fn downgrade(&self) -> WeakMultiq<T, L> {
+        WeakMultiq {
+            queue: Arc::downgrade(&self.queue),
+        }
+    }
+
+    /// Splits the handle into a sending and a receiving half, both can be cloned for more
+    /// producers and consumers. Once every sender is dropped the queue is [Multiq::close]d, so
+    /// receivers get the values left and then [QueueClosed]. Other [Multiq] handles of the same
+    /// queue don't count as senders.
+    pub fn split(self) -> (MultiqSender<T, L>, MultiqReceiver<T, L>) {
+        let sender = MultiqSender {
+            queue: self.clone(),
+            senders: Arc::new(AtomicUsize::new(1)),
+        };
+        (sender, MultiqReceiver { queue: self })
+    }
+
+    /// Returns a handle that buffers up to `capacity` values before pushing them all at once,
+    /// for producers that push many small values. Panics if `capacity` is zero.
+    pub fn producer(&self, capacity: usize) -> Producer<T, L> {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        Producer {
+            queue: self.clone(),
+            buffer: Vec::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns a handle that pops up to `capacity` values at a time and serves them from a
+    /// local buffer, for consumers of many small values. Panics if `capacity` is zero.
+    pub fn consumer(&self, capacity: usize) -> Consumer<T, L> {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        Consumer {
+            queue: self.clone(),
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
         }
-        self.queue.cvar.notify_one();
     }
 
     /// Returns true if the queue contains no elements.
     pub fn is_empty(&self) -> bool {
-        let head = &self
-            .queue
-            .head
-            .lock()
-            .expect("lock acquire failed")
-            .contents;
-        let tail = &self
-            .queue
-            .tail
-            .lock()
-            .expect("lock acquire failed")
-            .contents;
-        tail.0.is_none() && tail.1.is_none() && head.0.is_none() && head.1.is_none()
+        self.try_is_empty().expect("queue poisoned")
+    }
+
+    /// Like [Multiq::is_empty] but returns an error instead of panicking if the queue is
+    /// poisoned.
+    pub fn try_is_empty(&self) -> Result<bool, QueuePoisoned> {
+        let head = self.lock(&self.queue.head)?;
+        let tail = self.lock(&self.queue.tail)?;
+        Ok(head.is_empty() && tail.is_empty())
+    }
+
+    /// Formats the queued values front first like a slice, e.g. `[1, 2, 3]`, to log what a
+    /// queue holds while debugging. Holds both locks while formatting.
+    pub fn dump(&self) -> String
+    where
+        T: fmt::Debug,
+    {
+        let head = self.lock(&self.queue.head).expect("queue poisoned");
+        let tail = self.lock(&self.queue.tail).expect("queue poisoned");
+        format!("{:?}", head.iter().chain(tail.iter()).collect::<Vec<_>>())
+    }
+
+    /// Returns the current depth and moving averages of depth and consumer wait time, kept up
+    /// to date by pushes and pops without a background thread, e.g. for sizing a worker pool.
+    pub fn load_stats(&self) -> LoadStats {
+        let stats = &self.queue.stats;
+        LoadStats {
+            depth: stats.depth.load(Ordering::Relaxed),
+            average_depth: stats.depth_average.get(),
+            average_wait: Duration::from_secs_f64(stats.wait_average.get()),
+        }
+    }
+
+    /// Returns the number of threads blocked in wait_and_pop, including those waiting for
+    /// their turn. Consumers that stay blocked while work is expected point at starved
+    /// producers, consumers that stay busy with none blocked at too few consumers.
+    pub fn waiting_consumers(&self) -> usize {
+        self.queue.consumers.load(Ordering::Relaxed)
+    }
+
+    /// Returns the approximate number of bytes the queue allocated, its shared state plus a
+    /// slot per queued value, e.g. to export as a gauge or enforce a memory limit. Spare
+    /// capacity of the buffers and heap memory owned by the values themselves is not counted.
+    pub fn approx_memory_usage(&self) -> usize {
+        mem::size_of::<InnerMultiq<T, L>>()
+            + self.queue.stats.depth.load(Ordering::Relaxed) * mem::size_of::<T>()
+    }
+
+    /// Returns the distribution of how long popped values waited in the queue, or [None] if it
+    /// wasn't created with [Multiq::with_latency_histogram]. Values still queued are not
+    /// included, e.g. poll it periodically and export the quantiles.
+    pub fn latency_snapshot(&self) -> Option<HistogramSnapshot> {
+        self.queue
+            .dwell
+            .as_ref()
+            .map(|dwell| dwell.histogram.snapshot())
+    }
+
+    /// Makes dropping the last handle leak the queued values instead of dropping them one by
+    /// one, e.g. for a large queue that lives until the process exits and would only slow down
+    /// the shutdown. Destructors of the values don't run.
+    pub fn forget_on_drop(&self) {
+        self.queue.forget_on_drop.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns true if a thread panicked while holding one of the queue's locks.
+    pub fn is_poisoned(&self) -> bool {
+        self.queue.head.is_poisoned() || self.queue.tail.is_poisoned()
+    }
+
+    /// Records the dwell times of `count` values popped from the head, called with the head
+    /// lock held. `usize::MAX` records every queued value.
+    fn dwell_popped(&self, count: usize) {
+        if let Some(dwell) = &self.queue.dwell {
+            dwell.popped(count);
+        }
+    }
+
+    /// Acquires one of the queue's locks, applying the poison policy.
+    fn lock<'a>(
+        &self,
+        lock: &'a Lock<VecDeque<T>, L>,
+    ) -> Result<LockGuard<'a, VecDeque<T>, L>, QueuePoisoned> {
+        match (lock.lock(), self.queue.poison_policy) {
+            (Ok(guard), _) => Ok(guard),
+            (Err(poisoned), PoisonPolicy::Ignore) => Ok(poisoned.into_inner()),
+            (Err(_), PoisonPolicy::Propagate) => Err(QueuePoisoned),
+        }
+    }
+
+    /// Locks the wait list of `waiter` and returns it with its length, no user code runs
+    /// under the lock so poison is ignored.
+    fn waiters(&self, waiter: Waiter) -> (MutexGuard<'_, VecDeque<Unparker>>, &AtomicUsize) {
+        let waiters = match waiter {
+            Waiter::Pop => &self.queue.waiters,
+            Waiter::Peek => &self.queue.peekers,
+        };
+        let waiters = waiters.lock().unwrap_or_else(PoisonError::into_inner);
+        (waiters, self.waiting(waiter))
+    }
+
+    /// Returns the length of the wait list of `waiter`.
+    fn waiting(&self, waiter: Waiter) -> &AtomicUsize {
+        match waiter {
+            Waiter::Pop => &self.queue.waiting,
+            Waiter::Peek => &self.queue.peeking,
+        }
+    }
+
+    /// Adds a consumer to the waiters, must be called before it looks for a value so a push
+    /// it misses sees it.
+    fn register_waiter(&self, waiter: Waiter, unparker: &Unparker) {
+        let (mut waiters, waiting) = self.waiters(waiter);
+        waiters.push_back(unparker.clone());
+        waiting.store(waiters.len(), Ordering::SeqCst);
+    }
+
+    /// Removes the entry of `unparker`, returns false if a wake took it already.
+    fn unregister_waiter(&self, waiter: Waiter, unparker: &Unparker) -> bool {
+        let (mut waiters, waiting) = self.waiters(waiter);
+        let registered = waiters.len();
+        waiters.retain(|waiter| waiter != unparker);
+        waiting.store(waiters.len(), Ordering::SeqCst);
+        waiters.len() < registered
+    }
+
+    /// Unparks up to `count` consumers waiting in wait_and_pop, longest waiting first, and
+    /// every consumer waiting in wait_and_peek.
+    fn wake(&self, count: usize) {
+        self.wake_waiters(Waiter::Pop, count);
+        self.wake_waiters(Waiter::Peek, usize::MAX);
+    }
+
+    fn wake_waiters(&self, waiter: Waiter, count: usize) {
+        chaos::yield_point();
+        // waiters register before looking for a value, so one that missed the pushed value
+        // was counted before the push released its lock
+        if self.waiting(waiter).load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        chaos::yield_point();
+        let (mut waiters, waiting) = self.waiters(waiter);
+        let woken: Vec<Unparker> = (0..count).map_while(|_| waiters.pop_front()).collect();
+        waiting.store(waiters.len(), Ordering::SeqCst);
+        drop(waiters);
+        for waiter in woken {
+            waiter.unpark();
+        }
+    }
+
+    /// Gives back the budget held by a popped value.
+    fn release_budget(&self, value: &T) {
+        if let Some(budget) = &self.queue.budget {
+            budget.release(value);
+        }
+    }
+}
+
+impl<T, L: RawLock> Producer<T, L> {
+    /// Adds `value` to the batch, pushing the batch once it holds `capacity` values.
+    pub fn push(&mut self, value: T) {
+        self.buffer.push(value);
+        if self.buffer.len() >= self.capacity {
+            self.flush();
+        }
+    }
+
+    /// Pushes the buffered values into the queue right away.
+    pub fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            let batch = mem::replace(&mut self.buffer, Vec::with_capacity(self.capacity));
+            self.queue.push_all(batch);
+        }
+    }
+
+    /// Returns the number of values waiting in the batch.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl<T, L: RawLock> Drop for Producer<T, L> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<T, L: RawLock> Consumer<T, L> {
+    /// Takes the next value like [Multiq::wait_and_pop], refilling the buffer from the queue
+    /// when it runs empty.
+    pub fn recv(&mut self) -> Result<T, QueueClosed> {
+        match self.try_recv() {
+            Some(value) => Ok(value),
+            None => self.queue.wait_and_pop(),
+        }
+    }
+
+    /// Takes the next value like [Multiq::pop], or returns [None] if the buffer and the queue
+    /// are empty.
+    pub fn try_recv(&mut self) -> Option<T> {
+        if self.buffer.is_empty() {
+            self.buffer.extend(self.queue.pop_up_to(self.capacity));
+        }
+        self.buffer.pop_front()
+    }
+
+    /// Returns the number of values popped from the queue but not received yet.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+impl<T, L: RawLock> Drop for Consumer<T, L> {
+    fn drop(&mut self) {
+        self.queue.requeue_front(mem::take(&mut self.buffer));
+    }
+}
+
+impl<T, L: RawLock> WeakMultiq<T, L> {
+    /// Returns a handle of the queue, or [None] if every [Multiq] handle was dropped.
+    pub fn upgrade(&self) -> Option<Multiq<T, L>> {
+        self.queue.upgrade().map(|queue| Multiq { queue })
+    }
+}
+
+impl<T, L: RawLock> Clone for WeakMultiq<T, L> {
+    fn clone(&self) -> Self {
+        WeakMultiq {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T, L: RawLock> MultiqSender<T, L> {
+    /// Pushes `value` into the back of the queue like [Multiq::push].
+    pub fn send(&self, value: T) {
+        self.queue.push(value);
+    }
+
+    /// Pushes all `values` into the back of the queue like [Multiq::push_all].
+    pub fn send_all<I: IntoIterator<Item = T>>(&self, values: I) {
+        self.queue.push_all(values);
+    }
+}
+
+impl<T, L: RawLock> Clone for MultiqSender<T, L> {
+    fn clone(&self) -> Self {
+        self.senders.fetch_add(1, Ordering::Relaxed);
+        MultiqSender {
+            queue: self.queue.clone(),
+            senders: self.senders.clone(),
+        }
+    }
+}
+
+impl<T, L: RawLock> Drop for MultiqSender<T, L> {
+    fn drop(&mut self) {
+        // AcqRel so the close comes after the pushes of every other sender
+        if self.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.queue.close();
+        }
+    }
+}
+
+impl<T, L: RawLock> MultiqReceiver<T, L> {
+    /// Takes the value at the front like [Multiq::wait_and_pop], waiting while the queue is
+    /// empty. Returns [QueueClosed] once it is empty and every sender is dropped.
+    pub fn recv(&self) -> Result<T, QueueClosed> {
+        self.queue.wait_and_pop()
+    }
+
+    /// Takes the value at the front like [Multiq::pop], or returns [None] if the queue is
+    /// empty.
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.pop()
+    }
+
+    /// Returns true once every sender is dropped, values may still be queued.
+    pub fn is_disconnected(&self) -> bool {
+        self.queue.is_closed()
+    }
+}
+
+impl<T, L: RawLock> Clone for MultiqReceiver<T, L> {
+    fn clone(&self) -> Self {
+        MultiqReceiver {
+            queue: self.queue.clone(),
+        }
+    }
+}
+
+impl<T> Iterator for Drain<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.values.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.values.size_hint()
     }
 }
+
+impl<T> ExactSizeIterator for Drain<T> {}
@@ -0,0 +1,81 @@
+#[cfg(any(windows, target_vendor = "apple"))]
+use crate::lock::RawLock;
+#[cfg(any(windows, target_vendor = "apple"))]
+use std::cell::UnsafeCell;
+
+#[cfg(windows)]
+#[link(name = "kernel32")]
+extern "system" {
+    fn AcquireSRWLockExclusive(lock: *mut usize);
+    fn TryAcquireSRWLockExclusive(lock: *mut usize) -> u8;
+    fn ReleaseSRWLockExclusive(lock: *mut usize);
+}
+
+#[cfg(target_vendor = "apple")]
+extern "C" {
+    fn os_unfair_lock_lock(lock: *mut u32);
+    fn os_unfair_lock_trylock(lock: *mut u32) -> bool;
+    fn os_unfair_lock_unlock(lock: *mut u32);
+}
+
+/// A [RawLock] over the slim reader/writer lock of Windows, used exclusively. It is a single
+/// pointer sized word that the kernel only gets involved with under contention, without the
+/// std Mutex and Condvar [crate::lock::StdLock] puts its blocked threads to sleep on. The
+/// default lock with the os-lock feature on Windows.
+#[cfg(windows)]
+#[derive(Debug, Default)]
+pub struct SrwLock {
+    /// The SRWLOCK, all zero is unlocked.
+    pub lock: UnsafeCell<usize>,
+}
+
+#[cfg(windows)]
+unsafe impl Send for SrwLock {}
+#[cfg(windows)]
+unsafe impl Sync for SrwLock {}
+
+#[cfg(windows)]
+unsafe impl RawLock for SrwLock {
+    fn lock(&self) {
+        unsafe { AcquireSRWLockExclusive(self.lock.get()) }
+    }
+
+    fn try_lock(&self) -> bool {
+        unsafe { TryAcquireSRWLockExclusive(self.lock.get()) != 0 }
+    }
+
+    unsafe fn unlock(&self) {
+        ReleaseSRWLockExclusive(self.lock.get())
+    }
+}
+
+/// A [RawLock] over `os_unfair_lock` of macOS and iOS, which parks waiters in the kernel and
+/// lets the owner's priority be boosted while they wait. It must be unlocked by the thread
+/// that locked it, [crate::lock::LockGuard] can't be sent to another thread for that reason.
+/// The default lock with the os-lock feature on Apple platforms.
+#[cfg(target_vendor = "apple")]
+#[derive(Debug, Default)]
+pub struct UnfairLock {
+    /// The os_unfair_lock, zero is unlocked.
+    pub lock: UnsafeCell<u32>,
+}
+
+#[cfg(target_vendor = "apple")]
+unsafe impl Send for UnfairLock {}
+#[cfg(target_vendor = "apple")]
+unsafe impl Sync for UnfairLock {}
+
+#[cfg(target_vendor = "apple")]
+unsafe impl RawLock for UnfairLock {
+    fn lock(&self) {
+        unsafe { os_unfair_lock_lock(self.lock.get()) }
+    }
+
+    fn try_lock(&self) -> bool {
+        unsafe { os_unfair_lock_trylock(self.lock.get()) }
+    }
+
+    unsafe fn unlock(&self) {
+        os_unfair_lock_unlock(self.lock.get())
+    }
+}
@@ -0,0 +1,177 @@
+use crate::boundq::Boundq;
+use crate::promise::{self, JobHandle};
+use crate::thread_pool::ThreadPool;
+use std::{
+    cmp::Ordering,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Number of queued items per worker, keeps memory bounded for long iterators while making
+/// sure workers rarely wait for the producer.
+const ITEMS_PER_WORKER: usize = 4;
+/// Smallest number of values worth a job of its own, below that the hand-off costs more
+/// than the work, same idea as `min_per_thread` in the Williams book.
+const MIN_PER_JOB: usize = 25;
+
+/// Folds `values` with `op` starting from `init` like [Iterator::fold], with the values split
+/// into one block per pool thread that are reduced in parallel. Based on `parallel_accumulate`
+/// from chapter 8 of "C++ Concurrency in Action", `op` has to be associative since blocks
+/// are reduced separately, but their results are combined in order.
+pub fn parallel_accumulate<T, F>(pool: &ThreadPool, values: Vec<T>, init: T, op: F) -> T
+where
+    T: Send + 'static,
+    F: Fn(T, T) -> T + Send + Sync + 'static,
+{
+    let op = Arc::new(op);
+    let blocks: Vec<_> = split(values, pool.threads())
+        .into_iter()
+        .map(|block| {
+            let op = op.clone();
+            pool.submit(move || block.into_iter().reduce(|acc, value| op(acc, value)))
+        })
+        .collect();
+    blocks
+        .into_iter()
+        .filter_map(wait)
+        .fold(init, |acc, value| op(acc, value))
+}
+
+/// Calls `f` on every value, spread over the pool threads, and returns once all calls are
+/// done. Panics if any of the calls panicked.
+pub fn parallel_for_each<T, F>(pool: &ThreadPool, values: Vec<T>, f: F)
+where
+    T: Send + 'static,
+    F: Fn(T) + Send + Sync + 'static,
+{
+    let f = Arc::new(f);
+    let blocks: Vec<_> = split(values, pool.threads())
+        .into_iter()
+        .map(|block| {
+            let f = f.clone();
+            pool.submit(move || block.into_iter().for_each(|value| f(value)))
+        })
+        .collect();
+    // wait for every block before reporting a failure, so no call runs after the return
+    let results: Vec<_> = blocks.into_iter().map(JobHandle::wait).collect();
+    for result in results {
+        result.unwrap_or_else(|error| panic!("parallel job failed: {error}"));
+    }
+}
+
+/// Sorts `values` with a parallel quicksort based on chapter 8 of "C++ Concurrency in Action".
+/// The calling thread partitions around pivots until the parts are small enough to give every
+/// pool thread some work, and the pool sorts the parts. Unlike the book's version, pool
+/// threads never wait for each other, so the pool can't run out of threads to make progress.
+pub fn parallel_quick_sort<T: Ord + Send + 'static>(pool: &ThreadPool, values: Vec<T>) -> Vec<T> {
+    let min_len = (values.len() / pool.threads()).max(MIN_PER_JOB);
+    let mut parts = Vec::new();
+    partition(pool, values, min_len, &mut parts);
+    parts.into_iter().flat_map(wait).collect()
+}
+
+/// Splits `values` in order into at most `threads` blocks of similar length.
+fn split<T>(mut values: Vec<T>, threads: usize) -> Vec<Vec<T>> {
+    let blocks = (values.len() / MIN_PER_JOB).clamp(1, threads);
+    let block_len = values.len().div_ceil(blocks).max(1);
+    let mut result = Vec::with_capacity(blocks);
+    while values.len() > block_len {
+        let rest = values.split_off(block_len);
+        result.push(std::mem::replace(&mut values, rest));
+    }
+    result.push(values);
+    result
+}
+
+/// Appends handles to the sorted parts of `values` to `parts`, in order.
+fn partition<T: Ord + Send + 'static>(
+    pool: &ThreadPool,
+    mut values: Vec<T>,
+    min_len: usize,
+    parts: &mut Vec<JobHandle<Vec<T>>>,
+) {
+    if values.len() <= min_len {
+        parts.push(pool.submit(move || {
+            values.sort_unstable();
+            values
+        }));
+        return;
+    }
+    // middle pivot so already sorted input doesn't hit the quadratic case
+    let pivot = values.swap_remove(values.len() / 2);
+    let (mut lower, mut equal, mut upper) = (Vec::new(), Vec::new(), Vec::new());
+    for value in values {
+        match value.cmp(&pivot) {
+            Ordering::Less => lower.push(value),
+            Ordering::Equal => equal.push(value),
+            Ordering::Greater => upper.push(value),
+        }
+    }
+    equal.push(pivot);
+    partition(pool, lower, min_len, parts);
+    // values equal to the pivot are already in place, hand them over without a job
+    let (ready, handle) = promise::promise();
+    ready.set(equal);
+    parts.push(handle);
+    partition(pool, upper, min_len, parts);
+}
+
+fn wait<R>(handle: JobHandle<R>) -> R {
+    handle
+        .wait()
+        .unwrap_or_else(|error| panic!("parallel job failed: {error}"))
+}
+
+/// Data-parallel for-each over any iterator, see [ParConsume::into_par_consume].
+pub trait ParConsume: IntoIterator {
+    /// Consumes the iterator on the calling thread and hands every item to one of `threads`
+    /// temporary workers that run `f` on it, returning once all items are processed.
+    /// Items are passed through a [Boundq], so the iterator is only advanced as fast as the
+    /// workers keep up. The order in which items are processed is unspecified.
+    /// If `f` panics the remaining items are dropped unprocessed and the panic is resumed
+    /// on the calling thread.
+    fn into_par_consume<F>(self, threads: usize, f: F)
+    where
+        Self: Sized,
+        Self::Item: Send,
+        F: Fn(Self::Item) + Sync,
+    {
+        assert!(threads > 0, "need at least one thread");
+        // None tells a worker that the iterator is exhausted
+        let queue = Boundq::new(threads * ITEMS_PER_WORKER);
+        let panicked = Mutex::new(None);
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| {
+                    while let Some(item) = queue.pop() {
+                        if panicked.lock().expect("lock acquire failed").is_some() {
+                            // keep draining so the producer never blocks on a full queue
+                            continue;
+                        }
+                        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| f(item))) {
+                            panicked
+                                .lock()
+                                .expect("lock acquire failed")
+                                .get_or_insert(payload);
+                        }
+                    }
+                });
+            }
+            for item in self {
+                if panicked.lock().expect("lock acquire failed").is_some() {
+                    break;
+                }
+                queue.push(Some(item));
+            }
+            for _ in 0..threads {
+                queue.push(None);
+            }
+        });
+        if let Some(payload) = panicked.into_inner().expect("lock acquire failed") {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl<I: IntoIterator> ParConsume for I {}
@@ -0,0 +1,194 @@
+use std::{
+    cell::Cell,
+    marker::PhantomData,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "linux")]
+use crate::futex;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicU32, Ordering};
+#[cfg(not(target_os = "linux"))]
+use std::sync::{Condvar, Mutex};
+
+/// Blocks the thread that owns it until the paired [Unparker] is used. Works like
+/// [std::thread::park], but the token belongs to the pair instead of the thread, so it can be
+/// stored in wait lists and doesn't get consumed by unrelated code parking the same thread.
+/// An unpark that happens before the park is remembered and makes the next park return at once.
+/// Uses a futex on Linux and a mutex with a condvar elsewhere.
+#[derive(Debug)]
+pub struct Parker {
+    pub inner: Arc<InnerParker>,
+    /// Only the owning thread may park, so the parker is not [Sync].
+    pub owner: PhantomData<Cell<()>>,
+}
+
+/// Wakes up the thread owning the paired [Parker], can be cloned and sent to other threads.
+#[derive(Debug, Clone)]
+pub struct Unparker {
+    pub inner: Arc<InnerParker>,
+}
+
+#[cfg(target_os = "linux")]
+const EMPTY: u32 = 0;
+#[cfg(target_os = "linux")]
+const NOTIFIED: u32 = 1;
+#[cfg(target_os = "linux")]
+const PARKED: u32 = 2;
+
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct InnerParker {
+    pub state: AtomicU32,
+}
+
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub struct InnerParker {
+    pub notified: Mutex<bool>,
+    pub cvar: Condvar,
+}
+
+impl Parker {
+    /// Creates a new parker without a pending token.
+    pub fn new() -> Self {
+        Parker {
+            inner: Arc::new(InnerParker::new()),
+            owner: PhantomData,
+        }
+    }
+
+    /// Returns an unparker paired with this parker.
+    pub fn unparker(&self) -> Unparker {
+        Unparker {
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Blocks until the token is made available, then consumes it.
+    /// May also return spuriously, so callers recheck their condition in a loop.
+    pub fn park(&self) {
+        self.inner.park(None);
+    }
+
+    /// Blocks until the token is made available or `timeout` passes.
+    /// Returns true if the token was consumed.
+    pub fn park_timeout(&self, timeout: Duration) -> bool {
+        self.inner.park(Some(Instant::now() + timeout))
+    }
+
+    /// Blocks until the token is made available or `deadline` passes.
+    /// Returns true if the token was consumed.
+    pub fn park_deadline(&self, deadline: Instant) -> bool {
+        self.inner.park(Some(deadline))
+    }
+}
+
+impl Default for Parker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Unparker {
+    /// Makes the token available, waking the parked thread if there is one.
+    pub fn unpark(&self) {
+        self.inner.unpark();
+    }
+}
+
+impl PartialEq for Unparker {
+    /// Two unparkers are equal if they wake the same parker.
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl Eq for Unparker {}
+
+#[cfg(target_os = "linux")]
+impl InnerParker {
+    fn new() -> Self {
+        InnerParker {
+            state: AtomicU32::new(EMPTY),
+        }
+    }
+
+    fn park(&self, deadline: Option<Instant>) -> bool {
+        // NOTIFIED -> EMPTY means the token was already there
+        if self
+            .state
+            .compare_exchange(NOTIFIED, EMPTY, Ordering::Acquire, Ordering::Acquire)
+            .is_ok()
+        {
+            return true;
+        }
+        if self
+            .state
+            .compare_exchange(EMPTY, PARKED, Ordering::Acquire, Ordering::Acquire)
+            .is_err()
+        {
+            // unparked in between, state can only be NOTIFIED here
+            self.state.store(EMPTY, Ordering::Release);
+            return true;
+        }
+        loop {
+            let in_time = futex::wait(&self.state, PARKED, deadline);
+            if self
+                .state
+                .compare_exchange(NOTIFIED, EMPTY, Ordering::Acquire, Ordering::Acquire)
+                .is_ok()
+            {
+                return true;
+            }
+            if !in_time {
+                // the token may still arrive right now, swap tells which one happened
+                return self.state.swap(EMPTY, Ordering::Acquire) == NOTIFIED;
+            }
+        }
+    }
+
+    fn unpark(&self) {
+        if self.state.swap(NOTIFIED, Ordering::Release) == PARKED {
+            futex::wake(&self.state, 1);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl InnerParker {
+    fn new() -> Self {
+        InnerParker {
+            notified: Mutex::new(false),
+            cvar: Condvar::new(),
+        }
+    }
+
+    fn park(&self, deadline: Option<Instant>) -> bool {
+        let mut notified = self.notified.lock().expect("lock acquire failed");
+        while !*notified {
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return false;
+                    }
+                    notified = self
+                        .cvar
+                        .wait_timeout(notified, deadline - now)
+                        .expect("lock acquire failed")
+                        .0;
+                }
+                None => notified = self.cvar.wait(notified).expect("lock acquire failed"),
+            }
+        }
+        *notified = false;
+        true
+    }
+
+    fn unpark(&self) {
+        *self.notified.lock().expect("lock acquire failed") = true;
+        self.cvar.notify_one();
+    }
+}
@@ -0,0 +1,133 @@
+use crate::event::Event;
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+/// Write side of a one shot result channel, see [promise]. The value is handed over through
+/// a mutex and the waiting side is released by an [Event], so waiting costs nothing once
+/// the result is there.
+#[derive(Debug)]
+pub struct Promise<T> {
+    pub shared: Arc<Shared<T>>,
+}
+
+/// Read side of a [Promise], returned by [crate::thread_pool::ThreadPool::submit].
+/// The result is handed out once, later calls see [JobError::Taken].
+#[derive(Debug)]
+pub struct JobHandle<T> {
+    pub shared: Arc<Shared<T>>,
+}
+
+#[derive(Debug)]
+pub struct Shared<T> {
+    pub ready: Event,
+    pub result: Mutex<Option<Result<T, JobError>>>,
+}
+
+/// Reason a [JobHandle] has no value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobError {
+    /// The job panicked before producing its value.
+    Panicked,
+    /// The promise was dropped without a value, e.g. the job never ran.
+    Abandoned,
+    /// The value was already taken from the handle.
+    Taken,
+}
+
+impl fmt::Display for JobError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JobError::Panicked => write!(f, "job panicked"),
+            JobError::Abandoned => write!(f, "job dropped without a result"),
+            JobError::Taken => write!(f, "job result already taken"),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+/// Creates a connected [Promise] and [JobHandle].
+pub fn promise<T>() -> (Promise<T>, JobHandle<T>) {
+    let shared: Arc<Shared<T>> = Shared {
+        ready: Event::new(),
+        result: Mutex::new(None),
+    }
+    .into();
+    (
+        Promise {
+            shared: shared.clone(),
+        },
+        JobHandle { shared },
+    )
+}
+
+impl<T> Promise<T> {
+    /// Stores the value and releases everyone waiting on the handle.
+    pub fn set(self, value: T) {
+        self.complete(Ok(value));
+    }
+
+    fn complete(&self, result: Result<T, JobError>) {
+        let mut slot = self.shared.result.lock().expect("lock acquire failed");
+        if slot.is_none() && !self.shared.ready.is_set() {
+            *slot = Some(result);
+        }
+        drop(slot);
+        self.shared.ready.set();
+    }
+}
+
+impl<T> Drop for Promise<T> {
+    fn drop(&mut self) {
+        // a no-op after set, otherwise the job unwound or was thrown away
+        if thread::panicking() {
+            self.complete(Err(JobError::Panicked));
+        } else {
+            self.complete(Err(JobError::Abandoned));
+        }
+    }
+}
+
+impl<T> JobHandle<T> {
+    /// Blocks until the job finished and returns its value.
+    pub fn wait(self) -> Result<T, JobError> {
+        self.shared.ready.wait();
+        self.take()
+    }
+
+    /// Returns the result if the job finished, without blocking.
+    pub fn try_get(&mut self) -> Option<Result<T, JobError>> {
+        if self.shared.ready.is_set() {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    /// Blocks until the job finished or `timeout` passes, returns None on timeout.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<Result<T, JobError>> {
+        if self.shared.ready.wait_timeout(timeout) {
+            Some(self.take())
+        } else {
+            None
+        }
+    }
+
+    /// Returns true if the job finished.
+    pub fn is_ready(&self) -> bool {
+        self.shared.ready.is_set()
+    }
+
+    fn take(&self) -> Result<T, JobError> {
+        self.shared
+            .result
+            .lock()
+            .expect("lock acquire failed")
+            .take()
+            .unwrap_or(Err(JobError::Taken))
+    }
+}
@@ -0,0 +1,88 @@
+use crate::parker::Parker;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+/// A token bucket rate limiter, e.g. to throttle producers pushing into a queue. Tokens refill
+/// at a steady rate up to `burst`, taking tokens never blocks other threads. Implemented as the
+/// generic cell rate algorithm: instead of a token count it keeps a single atomic timestamp,
+/// the time at which the bucket would be full again, so acquiring is one compare-exchange and
+/// refilling needs no background thread. A request for more tokens than `burst` is clamped,
+/// otherwise it could never be satisfied.
+#[derive(Debug)]
+pub struct RateLimiter {
+    pub start: Instant,
+    /// Nanoseconds after start at which all tokens taken so far are paid back.
+    pub full_at: AtomicU64,
+    /// Nanoseconds it takes to refill one token.
+    pub interval: u64,
+    pub burst: u64,
+}
+
+impl RateLimiter {
+    /// Creates a limiter that allows `per_second` tokens per second on average and up to
+    /// `burst` at once, starting full.
+    pub fn new(per_second: u64, burst: u64) -> Self {
+        assert!(per_second > 0, "rate must be greater than zero");
+        assert!(burst > 0, "burst must be greater than zero");
+        Self::with_interval(Duration::from_nanos(1_000_000_000 / per_second), burst)
+    }
+
+    /// Creates a limiter that refills one token every `interval` and holds up to `burst`.
+    pub fn with_interval(interval: Duration, burst: u64) -> Self {
+        RateLimiter {
+            start: Instant::now(),
+            full_at: AtomicU64::new(0),
+            interval: (interval.as_nanos() as u64).max(1),
+            burst,
+        }
+    }
+
+    /// Takes `tokens` tokens if they are available right now, returns true on success.
+    pub fn try_acquire(&self, tokens: u64) -> bool {
+        self.acquire_or_wait_time(tokens).is_ok()
+    }
+
+    /// Takes `tokens` tokens, parking the current thread until they are refilled.
+    pub fn acquire(&self, tokens: u64) {
+        let parker = Parker::new();
+        while let Err(wait) = self.acquire_or_wait_time(tokens) {
+            parker.park_timeout(wait);
+        }
+    }
+
+    /// Returns the number of tokens that could be taken right now.
+    pub fn available(&self) -> u64 {
+        let now = self.now();
+        let full_at = self.full_at.load(Ordering::Acquire).max(now);
+        self.burst - (full_at - now).div_ceil(self.interval).min(self.burst)
+    }
+
+    /// Takes the tokens or returns how long it takes until they are available.
+    fn acquire_or_wait_time(&self, tokens: u64) -> Result<(), Duration> {
+        let cost = tokens.min(self.burst) * self.interval;
+        let capacity = self.burst * self.interval;
+        let mut full_at = self.full_at.load(Ordering::Acquire);
+        loop {
+            let now = self.now();
+            let new_full_at = full_at.max(now) + cost;
+            if new_full_at - now > capacity {
+                return Err(Duration::from_nanos(new_full_at - now - capacity));
+            }
+            match self.full_at.compare_exchange_weak(
+                full_at,
+                new_full_at,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Ok(()),
+                Err(current) => full_at = current,
+            }
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+}
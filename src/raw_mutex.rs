@@ -0,0 +1,199 @@
+use crate::futex;
+use crate::lock::{Lock, LockGuard, RawLock};
+use std::{
+    hint,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        LockResult, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked and there may be threads sleeping on the futex.
+const CONTENDED: u32 = 2;
+/// Number of attempts to take a briefly held lock before going to sleep.
+const SPIN_LIMIT: u32 = 100;
+
+/// A mutex built directly on the Linux futex syscall, following "Futexes Are Tricky" by
+/// Ulrich Drepper. Uncontended lock and unlock are a single atomic instruction, a contended
+/// lock spins for a while before sleeping, because most critical sections in the crate's
+/// containers are only a few instructions long and a syscall costs more than the wait.
+#[derive(Debug, Default)]
+pub struct RawMutex {
+    pub state: AtomicU32,
+}
+
+/// A condition variable paired with [RawMutex], waiters sleep on a sequence number that is
+/// bumped by every notification, so a notification between unlock and sleep is not lost.
+#[derive(Debug, Default)]
+pub struct RawCondvar {
+    pub sequence: AtomicU32,
+}
+
+/// Drop-in replacement for [std::sync::Mutex] over [RawMutex].
+pub type Mutex<T> = Lock<T, RawMutex>;
+
+/// Guard returned by [Mutex::lock], unlocks the mutex when dropped.
+pub type MutexGuard<'a, T> = LockGuard<'a, T, RawMutex>;
+
+/// Drop-in replacement for [std::sync::Condvar] over [RawCondvar].
+#[derive(Debug, Default)]
+pub struct Condvar {
+    pub raw: RawCondvar,
+}
+
+impl RawMutex {
+    /// Creates a new unlocked mutex.
+    pub const fn new() -> Self {
+        RawMutex {
+            state: AtomicU32::new(UNLOCKED),
+        }
+    }
+
+    /// Acquires the mutex, spinning briefly and then sleeping until it is available.
+    pub fn lock(&self) {
+        if self
+            .state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            self.lock_contended();
+        }
+    }
+
+    /// Acquires the mutex if it is not held, returns true on success.
+    pub fn try_lock(&self) -> bool {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    /// Releases the mutex, waking one sleeping thread if there may be any.
+    ///
+    /// # Safety
+    /// The mutex must be held by the current thread.
+    pub unsafe fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex::wake(&self.state, 1);
+        }
+    }
+
+    fn lock_contended(&self) {
+        for _ in 0..SPIN_LIMIT {
+            let state = self.state.load(Ordering::Relaxed);
+            if state == UNLOCKED
+                && self
+                    .state
+                    .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                return;
+            }
+            if state == CONTENDED {
+                // others are already sleeping, spinning won't help
+                break;
+            }
+            hint::spin_loop();
+        }
+        // taking it as CONTENDED is pessimistic but makes sure unlock wakes the next sleeper
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            futex::wait(&self.state, CONTENDED, None);
+        }
+    }
+}
+
+impl RawCondvar {
+    /// Creates a new condition variable.
+    pub const fn new() -> Self {
+        RawCondvar {
+            sequence: AtomicU32::new(0),
+        }
+    }
+
+    /// Unlocks `mutex`, sleeps until notified or `deadline` passes and locks `mutex` again.
+    /// Returns false if the deadline passed. May wake spuriously.
+    ///
+    /// # Safety
+    /// `mutex` must be held by the current thread.
+    pub unsafe fn wait(&self, mutex: &RawMutex, deadline: Option<Instant>) -> bool {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        mutex.unlock();
+        let in_time = futex::wait(&self.sequence, sequence, deadline);
+        mutex.lock();
+        in_time
+    }
+
+    /// Wakes up one waiting thread.
+    pub fn notify_one(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        futex::wake(&self.sequence, 1);
+    }
+
+    /// Wakes up all waiting threads.
+    pub fn notify_all(&self) {
+        self.sequence.fetch_add(1, Ordering::Relaxed);
+        futex::wake(&self.sequence, i32::MAX);
+    }
+}
+
+unsafe impl RawLock for RawMutex {
+    fn lock(&self) {
+        RawMutex::lock(self);
+    }
+
+    fn try_lock(&self) -> bool {
+        RawMutex::try_lock(self)
+    }
+
+    unsafe fn unlock(&self) {
+        RawMutex::unlock(self);
+    }
+}
+
+impl Condvar {
+    /// Creates a new condition variable.
+    pub const fn new() -> Self {
+        Condvar {
+            raw: RawCondvar::new(),
+        }
+    }
+
+    /// Unlocks the guard's mutex, sleeps until notified and locks it again. May wake spuriously.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        unsafe { self.raw.wait(&guard.lock.raw, None) };
+        if guard.lock.is_poisoned() {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    /// Like [Condvar::wait] but gives up after `timeout`, the flag is true if it timed out.
+    pub fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, bool)> {
+        let in_time = unsafe {
+            self.raw
+                .wait(&guard.lock.raw, Some(Instant::now() + timeout))
+        };
+        if guard.lock.is_poisoned() {
+            Err(PoisonError::new((guard, !in_time)))
+        } else {
+            Ok((guard, !in_time))
+        }
+    }
+
+    /// Wakes up one waiting thread.
+    pub fn notify_one(&self) {
+        self.raw.notify_one();
+    }
+
+    /// Wakes up all waiting threads.
+    pub fn notify_all(&self) {
+        self.raw.notify_all();
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    ops::Deref,
+    sync::{
+        atomic::{AtomicPtr, AtomicUsize, Ordering},
+        Mutex, PoisonError,
+    },
+};
+
+/// A read-copy-update cell. Readers get a guard to the current value without taking a lock,
+/// writers build a new value from the old one and swap it in, so readers are never blocked.
+/// Replaced values are freed by epochs, so a reader always finishes with the value it started
+/// with:
+///
+/// A reader announces itself on the counter of the current epoch. A writer that replaced a
+/// value retires it with the epoch it read after the swap, and the epoch moves on once no
+/// reader is left in the epoch before the current one. Readers that enter after a move load
+/// the value current by then, so a value retired in epoch `e` is unreachable once the epoch
+/// reached `e + 2`: both the readers of `e` and those of `e - 1` have left. New readers always
+/// join the current epoch and never hold up the older one, so values are freed under a steady
+/// stream of readers too, only a reader that stays inside holds them back. Two counters
+/// suffice, the epochs that can have readers at the same time differ in parity.
+#[derive(Debug)]
+pub struct Rcu<T> {
+    pub current: AtomicPtr<T>,
+    pub epoch: AtomicUsize,
+    /// Readers inside each epoch, indexed by the parity of the epoch.
+    pub readers: [AtomicUsize; 2],
+    /// Replaced values with the epoch they were retired in, oldest first. Kept as pointers,
+    /// readers may still borrow them.
+    pub retired: Mutex<Vec<(usize, *mut T)>>,
+    pub writer: Mutex<()>,
+}
+
+/// Read access to the value of [Rcu] at the time [Rcu::read] was called. Its fields are
+/// private, dropping it leaves the epoch it entered.
+#[derive(Debug)]
+pub struct RcuGuard<'a, T> {
+    rcu: &'a Rcu<T>,
+    /// A pointer rather than a reference, the guard's drop may be what frees the value.
+    value: *const T,
+    epoch: usize,
+}
+
+unsafe impl<T: Send + Sync> Sync for Rcu<T> {}
+unsafe impl<T: Send> Send for Rcu<T> {}
+
+impl<T> Rcu<T> {
+    /// Creates a new cell holding `value`.
+    pub fn new(value: T) -> Self {
+        Rcu {
+            current: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            epoch: AtomicUsize::new(0),
+            readers: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            retired: Mutex::new(Vec::new()),
+            writer: Mutex::new(()),
+        }
+    }
+
+    /// Returns a guard to the current value, the value stays alive while the guard is held.
+    pub fn read(&self) -> RcuGuard<'_, T> {
+        let epoch = loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            // announce the reader before checking the epoch again, so a writer that moves the
+            // epoch on after the check sees it
+            self.readers[epoch % 2].fetch_add(1, Ordering::SeqCst);
+            if self.epoch.load(Ordering::SeqCst) == epoch {
+                break epoch;
+            }
+            // the epoch moved on meanwhile, this counter may be the one a writer waits on
+            self.readers[epoch % 2].fetch_sub(1, Ordering::SeqCst);
+        };
+        let value = self.current.load(Ordering::SeqCst);
+        RcuGuard {
+            rcu: self,
+            value,
+            epoch,
+        }
+    }
+
+    /// Replaces the value with the one built by `f` from the current value.
+    /// Writers are serialized, so no update is lost.
+    pub fn update(&self, f: impl FnOnce(&T) -> T) {
+        let _writer = self
+            .writer
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let old = self.current.load(Ordering::SeqCst);
+        let new = Box::into_raw(Box::new(f(unsafe { &*old })));
+        self.current.store(new, Ordering::SeqCst);
+        // read after the swap, readers of later epochs load the new value
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        self.retired
+            .lock()
+            .expect("lock acquire failed")
+            .push((epoch, old));
+        self.try_reclaim();
+    }
+
+    /// Replaces the value with `value`.
+    pub fn store(&self, value: T) {
+        self.update(|_| value);
+    }
+
+    /// Returns the number of replaced values which are not freed yet.
+    pub fn pending_retired(&self) -> usize {
+        self.retired.lock().expect("lock acquire failed").len()
+    }
+
+    /// Moves the epoch on as far as the readers allow, at most twice since that makes every
+    /// value retired so far unreachable, and frees the values that became unreachable.
+    fn try_reclaim(&self) {
+        let mut retired = self.retired.lock().expect("lock acquire failed");
+        for _ in 0..2 {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            // the epoch before the current one shares its counter with the next one
+            if self.readers[(epoch + 1) % 2].load(Ordering::SeqCst) != 0 {
+                break;
+            }
+            // moved under the retired lock, so only one thread moves it at a time
+            self.epoch.store(epoch + 1, Ordering::SeqCst);
+        }
+        let epoch = self.epoch.load(Ordering::SeqCst);
+        retired.retain(|&(retired_in, value)| {
+            if retired_in + 2 > epoch {
+                return true;
+            }
+            drop(unsafe { Box::from_raw(value) });
+            false
+        });
+    }
+}
+
+impl<T> Deref for RcuGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.value }
+    }
+}
+
+impl<T> Drop for RcuGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.rcu.readers[self.epoch % 2].fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.rcu.try_reclaim();
+        }
+    }
+}
+
+impl<T> Drop for Rcu<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Box::from_raw(*self.current.get_mut()) });
+        let retired = self
+            .retired
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (_, value) in retired.drain(..) {
+            drop(unsafe { Box::from_raw(value) });
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use crate::lock::{DefaultLock, RawLock};
+use std::{
+    cell::{Cell, UnsafeCell},
+    fmt::{self, Debug},
+    marker::PhantomData,
+    ops::Deref,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A mutex that the owning thread can lock again while already holding it, e.g. from a
+/// callback invoked under the lock. The inner [RawLock] is taken once by the first lock and
+/// released when the last guard of the owner is dropped. Since several guards of the same
+/// thread can exist at once, guards only give shared access, wrap the value in a [Cell] or
+/// [std::cell::RefCell] to modify it.
+pub struct ReentrantMutex<T, R: RawLock = DefaultLock> {
+    pub raw: R,
+    /// Id of the owning thread, zero if unlocked.
+    pub owner: AtomicUsize,
+    /// Number of guards held by the owner, only touched by the owner.
+    pub count: Cell<usize>,
+    pub data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send, R: RawLock> Send for ReentrantMutex<T, R> {}
+unsafe impl<T: Send, R: RawLock> Sync for ReentrantMutex<T, R> {}
+
+/// Guard returned by [ReentrantMutex::lock].
+pub struct ReentrantGuard<'a, T, R: RawLock = DefaultLock> {
+    pub mutex: &'a ReentrantMutex<T, R>,
+    /// The guard must be dropped by the owner, so it is not [Send].
+    pub owner: PhantomData<*const ()>,
+}
+
+static NEXT_THREAD_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Returns a non zero id of the current thread. Ids are never reused, so a thread that exits
+/// while owning a mutex, e.g. with a leaked guard, can't hand the ownership to a later thread.
+fn current_thread_id() -> usize {
+    thread_local! {
+        static ID: usize = NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed);
+    }
+    ID.with(|id| *id)
+}
+
+impl<T, R: RawLock> ReentrantMutex<T, R> {
+    /// Creates a new unlocked mutex holding `value`.
+    pub fn new(value: T) -> Self {
+        ReentrantMutex {
+            raw: R::default(),
+            owner: AtomicUsize::new(0),
+            count: Cell::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Acquires the mutex, returns at once if the current thread already holds it.
+    pub fn lock(&self) -> ReentrantGuard<'_, T, R> {
+        let me = current_thread_id();
+        // only this thread can store its own id, so a stale read can't match
+        if self.owner.load(Ordering::Relaxed) == me {
+            self.count.set(self.count.get() + 1);
+        } else {
+            self.raw.lock();
+            self.owner.store(me, Ordering::Relaxed);
+            self.count.set(1);
+        }
+        ReentrantGuard {
+            mutex: self,
+            owner: PhantomData,
+        }
+    }
+
+    /// Acquires the mutex if it is free or already held by the current thread.
+    pub fn try_lock(&self) -> Option<ReentrantGuard<'_, T, R>> {
+        let me = current_thread_id();
+        if self.owner.load(Ordering::Relaxed) == me {
+            self.count.set(self.count.get() + 1);
+        } else if self.raw.try_lock() {
+            self.owner.store(me, Ordering::Relaxed);
+            self.count.set(1);
+        } else {
+            return None;
+        }
+        Some(ReentrantGuard {
+            mutex: self,
+            owner: PhantomData,
+        })
+    }
+
+    /// Returns true if the current thread holds the mutex.
+    pub fn is_owned_by_current_thread(&self) -> bool {
+        self.owner.load(Ordering::Relaxed) == current_thread_id()
+    }
+
+    /// Consumes the mutex and returns the value.
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: Debug, R: RawLock> Debug for ReentrantMutex<T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("ReentrantMutex");
+        match self.try_lock() {
+            Some(guard) => debug.field("data", &&*guard),
+            None => debug.field("data", &"<locked>"),
+        };
+        debug.finish()
+    }
+}
+
+impl<T, R: RawLock> Deref for ReentrantGuard<'_, T, R> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T, R: RawLock> Drop for ReentrantGuard<'_, T, R> {
+    fn drop(&mut self) {
+        let count = self.mutex.count.get() - 1;
+        self.mutex.count.set(count);
+        if count == 0 {
+            self.mutex.owner.store(0, Ordering::Relaxed);
+            unsafe { self.mutex.raw.unlock() };
+        }
+    }
+}
+
+impl<T: Debug, R: RawLock> Debug for ReentrantGuard<'_, T, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
@@ -0,0 +1,134 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    thread::{self, ThreadId},
+};
+
+/// A registry of threads taking part in deferred reclamation. Reclamation schemes that have
+/// to know whether any thread may still hold a reference (hazard pointers, epochs, the
+/// counters used by [crate::stackus::Stackus]) can walk the live participants and check
+/// which of them are pinned. Threads are registered on first use of [current] and
+/// unregistered when they exit, thread pools can also register and pin explicitly.
+#[derive(Debug, Default)]
+pub struct Registry {
+    pub participants: Mutex<Vec<Arc<Participant>>>,
+}
+
+/// A registered thread.
+#[derive(Debug)]
+pub struct Participant {
+    pub thread: ThreadId,
+    pub name: Option<String>,
+    /// Number of active pins, the thread may hold protected references while it is not zero.
+    pub pins: AtomicUsize,
+}
+
+/// Keeps a participant pinned until dropped, created by [Participant::pin].
+#[derive(Debug)]
+pub struct PinGuard<'a> {
+    pub participant: &'a Participant,
+}
+
+/// Unregisters the owning thread from the global registry when the thread exits.
+struct LocalParticipant(Arc<Participant>);
+
+thread_local! {
+    static LOCAL: LocalParticipant = LocalParticipant(Registry::global().register());
+}
+
+static GLOBAL: OnceLock<Registry> = OnceLock::new();
+
+/// Returns the current thread's entry in the global registry, registering it on first call.
+pub fn current() -> Arc<Participant> {
+    LOCAL.with(|local| local.0.clone())
+}
+
+impl Registry {
+    /// Creates a new empty registry.
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// Returns the registry shared by the whole process.
+    pub fn global() -> &'static Registry {
+        GLOBAL.get_or_init(Registry::new)
+    }
+
+    /// Registers the current thread, or returns its existing entry if it is already registered.
+    pub fn register(&self) -> Arc<Participant> {
+        let current = thread::current();
+        let mut participants = self.participants.lock().expect("lock acquire failed");
+        if let Some(existing) = participants.iter().find(|p| p.thread == current.id()) {
+            return existing.clone();
+        }
+        let participant = Arc::new(Participant {
+            thread: current.id(),
+            name: current.name().map(String::from),
+            pins: AtomicUsize::new(0),
+        });
+        participants.push(participant.clone());
+        participant
+    }
+
+    /// Removes a thread from the registry, returns false if it was not registered.
+    pub fn unregister(&self, thread: ThreadId) -> bool {
+        let mut participants = self.participants.lock().expect("lock acquire failed");
+        let before = participants.len();
+        participants.retain(|p| p.thread != thread);
+        participants.len() != before
+    }
+
+    /// Returns a snapshot of the registered threads.
+    pub fn live_threads(&self) -> Vec<Arc<Participant>> {
+        self.participants
+            .lock()
+            .expect("lock acquire failed")
+            .clone()
+    }
+
+    /// Returns the number of registered threads.
+    pub fn len(&self) -> usize {
+        self.participants.lock().expect("lock acquire failed").len()
+    }
+
+    /// Returns true if no thread is registered.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if no registered thread is pinned, i.e. none can hold protected references.
+    pub fn is_quiescent(&self) -> bool {
+        self.participants
+            .lock()
+            .expect("lock acquire failed")
+            .iter()
+            .all(|p| !p.is_pinned())
+    }
+}
+
+impl Participant {
+    /// Pins the participant until the returned guard is dropped. Pins nest.
+    pub fn pin(&self) -> PinGuard<'_> {
+        self.pins.fetch_add(1, Ordering::SeqCst);
+        PinGuard { participant: self }
+    }
+
+    /// Returns true if the participant holds at least one pin.
+    pub fn is_pinned(&self) -> bool {
+        self.pins.load(Ordering::SeqCst) != 0
+    }
+}
+
+impl Drop for PinGuard<'_> {
+    fn drop(&mut self) {
+        self.participant.pins.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for LocalParticipant {
+    fn drop(&mut self) {
+        Registry::global().unregister(self.0.thread);
+    }
+}
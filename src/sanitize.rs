@@ -0,0 +1,29 @@
+#[cfg(tsan)]
+extern "C" {
+    fn __tsan_acquire(addr: *mut std::ffi::c_void);
+    fn __tsan_release(addr: *mut std::ffi::c_void);
+}
+
+/// Tells ThreadSanitizer that everything the current thread did so far happens before a
+/// later [acquire] of the same address, called where a node is handed to the reclamation.
+/// The counters of the reclamation order these accesses through several atomics, which
+/// the sanitizer can lose track of and then report the free of a node as a race with its
+/// last read. Without `--cfg tsan` this compiles to nothing.
+#[cfg(tsan)]
+pub(crate) fn release<T>(addr: *const T) {
+    unsafe { __tsan_release(addr.cast_mut().cast()) }
+}
+
+/// Pairs with [release], called right before a retired node is freed.
+#[cfg(tsan)]
+pub(crate) fn acquire<T>(addr: *const T) {
+    unsafe { __tsan_acquire(addr.cast_mut().cast()) }
+}
+
+#[cfg(not(tsan))]
+#[inline(always)]
+pub(crate) fn release<T>(_addr: *const T) {}
+
+#[cfg(not(tsan))]
+#[inline(always)]
+pub(crate) fn acquire<T>(_addr: *const T) {}
@@ -0,0 +1,133 @@
+use crate::dequeus::Dequeus;
+use crate::multiq::Multiq;
+
+/// A worker checks the injector first every this many pops, so values pushed from outside
+/// aren't starved by workers that keep feeding their own deques.
+pub const INJECTOR_INTERVAL: u32 = 61;
+
+/// The core of a work-stealing executor as a reusable component: each worker has a local
+/// [Dequeus] it pushes to and pops from at the back, values from outside and local overflow go
+/// to a shared [Multiq] injector, and a worker that runs out of work steals half of the values
+/// of another. Values pushed by a worker are likely to be handled by the same thread while
+/// its caches are warm. Parking idle workers is left to the caller.
+#[derive(Debug)]
+pub struct Scheduler<T> {
+    /// Values from outside and overflow of full local deques. Holds [Option]s so it can start
+    /// out empty, only [Some] is pushed.
+    pub injector: Multiq<Option<T>>,
+    pub locals: Vec<Dequeus<T>>,
+    /// Number of values a local deque takes before half of them move to the injector.
+    pub local_capacity: usize,
+}
+
+/// The handle of one worker of a [Scheduler], each worker thread is meant to use its own.
+#[derive(Debug)]
+pub struct Worker<'a, T> {
+    pub scheduler: &'a Scheduler<T>,
+    pub index: usize,
+    /// Number of pops so far, see [INJECTOR_INTERVAL].
+    pub ticks: u32,
+}
+
+impl<T> Scheduler<T> {
+    /// Creates a new scheduler for `workers` workers whose local deques hold up to 256 values.
+    pub fn new(workers: usize) -> Self {
+        Self::with_local_capacity(workers, 256)
+    }
+
+    /// Creates a new scheduler for `workers` workers whose local deques hold up to
+    /// `local_capacity` values.
+    pub fn with_local_capacity(workers: usize, local_capacity: usize) -> Self {
+        assert!(workers > 0, "workers must be greater than zero");
+        assert!(
+            local_capacity > 0,
+            "local_capacity must be greater than zero"
+        );
+        // a Multiq starts with a value, take it right away
+        let injector = Multiq::new(None);
+        injector.pop();
+        Scheduler {
+            injector,
+            locals: (0..workers).map(|_| Dequeus::new()).collect(),
+            local_capacity,
+        }
+    }
+
+    /// Returns the handle of the worker at `index`.
+    pub fn worker(&self, index: usize) -> Worker<'_, T> {
+        assert!(index < self.locals.len(), "no worker at index {index}");
+        Worker {
+            scheduler: self,
+            index,
+            ticks: 0,
+        }
+    }
+
+    /// Pushes a value from outside the workers to the injector.
+    pub fn inject(&self, value: T) {
+        self.injector.push(Some(value));
+    }
+
+    /// Returns the number of workers.
+    pub fn workers(&self) -> usize {
+        self.locals.len()
+    }
+
+    /// Returns true if neither the injector nor any local deque holds a value.
+    pub fn is_empty(&self) -> bool {
+        self.injector.is_empty() && self.locals.iter().all(Dequeus::is_empty)
+    }
+}
+
+impl<T> Worker<'_, T> {
+    /// Pushes a value to the worker's local deque, moving the older half of it to the injector
+    /// first if it is full.
+    pub fn push(&self, value: T) {
+        let local = self.local();
+        if local.len() >= self.scheduler.local_capacity {
+            let overflow = local.steal_half();
+            self.scheduler
+                .injector
+                .push_all(overflow.into_iter().map(Some));
+        }
+        local.push_back(value);
+    }
+
+    /// Takes the next value for this worker: the newest of its local deque, else the oldest of
+    /// the injector, else the oldest of another worker's deque together with half of its values.
+    /// Returns [None] if there is no value anywhere.
+    pub fn pop(&mut self) -> Option<T> {
+        self.ticks = self.ticks.wrapping_add(1);
+        if self.ticks.is_multiple_of(INJECTOR_INTERVAL) {
+            if let Some(value) = self.pop_injector() {
+                return Some(value);
+            }
+        }
+        self.local()
+            .pop_back()
+            .or_else(|| self.pop_injector())
+            .or_else(|| self.steal())
+    }
+
+    fn steal(&self) -> Option<T> {
+        let workers = self.scheduler.locals.len();
+        // starts at the next worker, so not every idle worker goes for the same victim
+        (1..workers).find_map(|offset| {
+            let victim = &self.scheduler.locals[(self.index + offset) % workers];
+            let mut stolen = victim.steal_half().into_iter();
+            let first = stolen.next()?;
+            for value in stolen {
+                self.local().push_back(value);
+            }
+            Some(first)
+        })
+    }
+
+    fn pop_injector(&self) -> Option<T> {
+        self.scheduler.injector.pop().flatten()
+    }
+
+    fn local(&self) -> &Dequeus<T> {
+        &self.scheduler.locals[self.index]
+    }
+}
@@ -0,0 +1,110 @@
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::parker::{Parker, Unparker};
+use std::sync::Mutex;
+
+/// A lock-based weighted counting semaphore. Each acquire takes any number of permits at once,
+/// which allows limiting resources measured in units other than item count, e.g. bytes.
+/// A request for more permits than the semaphore was created with is clamped to its capacity,
+/// otherwise it could never be satisfied.
+#[derive(Debug)]
+pub struct Semaphore {
+    /// Threads blocked in acquire, all of them are woken on release.
+    pub waiters: Mutex<Vec<Unparker>>,
+    pub available: Mutex<usize>,
+    pub capacity: usize,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with `permits` available permits.
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            waiters: Mutex::new(Vec::new()),
+            available: Mutex::new(permits),
+            capacity: permits,
+        }
+    }
+
+    /// Takes `permits` permits, waiting until enough of them are released.
+    /// Returns the number of permits actually taken, which must be passed to [Semaphore::release].
+    pub fn acquire(&self, permits: usize) -> usize {
+        self.acquire_inner(permits, None)
+            .expect("acquire without a token can't be cancelled")
+    }
+
+    /// Like [Semaphore::acquire] but gives up without taking any permits once `token` is
+    /// cancelled.
+    pub fn acquire_cancellable(
+        &self,
+        permits: usize,
+        token: &CancellationToken,
+    ) -> Result<usize, Cancelled> {
+        self.acquire_inner(permits, Some(token))
+    }
+
+    fn acquire_inner(
+        &self,
+        permits: usize,
+        token: Option<&CancellationToken>,
+    ) -> Result<usize, Cancelled> {
+        let permits = permits.min(self.capacity);
+        let mut available = self.available.lock().expect("lock acquire failed");
+        if *available >= permits {
+            *available -= permits;
+            return Ok(permits);
+        }
+        let parker = Parker::new();
+        let unparker = parker.unparker();
+        let _cancel_guard = token.map(|token| {
+            let unparker = unparker.clone();
+            token.on_cancel(move || unparker.unpark())
+        });
+        while *available < permits {
+            if let Some(token) = token {
+                token.check()?;
+            }
+            // registering under the lock makes sure the next release sees this waiter
+            self.waiters
+                .lock()
+                .expect("lock acquire failed")
+                .push(unparker.clone());
+            drop(available);
+            parker.park();
+            available = self.available.lock().expect("lock acquire failed");
+            self.waiters
+                .lock()
+                .expect("lock acquire failed")
+                .retain(|waiter| waiter != &unparker);
+        }
+        *available -= permits;
+        Ok(permits)
+    }
+
+    /// Takes `permits` permits if that many are available right now.
+    /// Returns the number of permits taken, or [None] if there are not enough.
+    pub fn try_acquire(&self, permits: usize) -> Option<usize> {
+        let permits = permits.min(self.capacity);
+        let mut available = self.available.lock().expect("lock acquire failed");
+        if *available < permits {
+            return None;
+        }
+        *available -= permits;
+        Some(permits)
+    }
+
+    /// Gives back `permits` permits and wakes up the threads waiting for them.
+    pub fn release(&self, permits: usize) {
+        let mut available = self.available.lock().expect("lock acquire failed");
+        *available += permits;
+        // waiters need different amounts, so every one of them has to recheck
+        let waiters = std::mem::take(&mut *self.waiters.lock().expect("lock acquire failed"));
+        drop(available);
+        for waiter in waiters {
+            waiter.unpark();
+        }
+    }
+
+    /// Returns the number of permits that can be acquired right now.
+    pub fn available_permits(&self) -> usize {
+        *self.available.lock().expect("lock acquire failed")
+    }
+}
@@ -0,0 +1,105 @@
+use crate::spin::Backoff;
+use std::{
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{fence, AtomicUsize, Ordering},
+};
+
+/// A sequence lock for small [Copy] values. Writers bump a sequence counter to an odd value,
+/// write, and bump it back to even. Readers never write to shared memory: they copy the
+/// value and retry if the counter was odd or changed meanwhile, so frequent reads of e.g.
+/// statistics snapshots don't slow down the writer. Writers are serialized with each other
+/// through the same counter.
+#[derive(Debug)]
+pub struct SeqLock<T: Copy> {
+    pub sequence: AtomicUsize,
+    pub value: UnsafeCell<T>,
+}
+
+/// A write in progress, stores the even `next` sequence when dropped. If the update panics
+/// before writing, `next` is still the sequence from before it, so readers and writers go on
+/// with the old value instead of waiting for an odd sequence forever.
+struct Writing<'a> {
+    sequence: &'a AtomicUsize,
+    next: usize,
+}
+
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    /// Creates a new lock holding `value`.
+    pub fn new(value: T) -> Self {
+        SeqLock {
+            sequence: AtomicUsize::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a consistent copy of the value, retrying while a write is in progress.
+    pub fn read(&self) -> T {
+        let mut backoff = Backoff::new();
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                backoff.snooze();
+                continue;
+            }
+            // may race with a writer, the copy is only used if the sequence didn't change
+            let value = unsafe { ptr::read_volatile(self.value.get()) };
+            fence(Ordering::Acquire);
+            if self.sequence.load(Ordering::Relaxed) == before {
+                return value;
+            }
+        }
+    }
+
+    /// Replaces the value.
+    pub fn write(&self, value: T) {
+        self.update(|current| *current = value);
+    }
+
+    /// Modifies the value in place, readers see either the old or the new value, never a mix.
+    /// If `f` panics the value stays as it was.
+    pub fn update(&self, f: impl FnOnce(&mut T)) {
+        let mut sequence = self.sequence.load(Ordering::Relaxed);
+        let mut backoff = Backoff::new();
+        loop {
+            // odd means another writer is active
+            if sequence & 1 == 0 {
+                match self.sequence.compare_exchange_weak(
+                    sequence,
+                    sequence + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => break,
+                    Err(current) => sequence = current,
+                }
+            } else {
+                backoff.snooze();
+                sequence = self.sequence.load(Ordering::Relaxed);
+            }
+        }
+        let mut writing = Writing {
+            sequence: &self.sequence,
+            next: sequence,
+        };
+        // make the odd sequence visible before any of the writes below
+        fence(Ordering::Release);
+        let mut value = unsafe { ptr::read_volatile(self.value.get()) };
+        f(&mut value);
+        unsafe { ptr::write_volatile(self.value.get(), value) };
+        writing.next = sequence + 2;
+    }
+
+    /// Returns the number of completed writes.
+    pub fn version(&self) -> usize {
+        self.sequence.load(Ordering::Acquire) / 2
+    }
+}
+
+impl Drop for Writing<'_> {
+    fn drop(&mut self) {
+        self.sequence.store(self.next, Ordering::Release);
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Condvar, Mutex},
+};
+
+/// Restores order after parallel processing: workers complete values tagged with the sequence
+/// number they were given, e.g. the position of the job taken from a [crate::multiq::Multiq],
+/// and the consumer receives them strictly by sequence number. Values that complete ahead of
+/// a gap are buffered until the gap is filled.
+#[derive(Debug)]
+pub struct Sequencer<T> {
+    pub cvar: Condvar,
+    pub state: Mutex<SequencerState<T>>,
+}
+
+#[derive(Debug)]
+pub struct SequencerState<T> {
+    /// Sequence number of the next value to release.
+    pub next: u64,
+    pub pending: BTreeMap<u64, T>,
+}
+
+impl<T> Sequencer<T> {
+    /// Creates a sequencer whose first value has sequence number `first`.
+    pub fn new(first: u64) -> Self {
+        Sequencer {
+            cvar: Condvar::new(),
+            state: Mutex::new(SequencerState {
+                next: first,
+                pending: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Hands over the value with sequence number `sequence`, in any order.
+    /// Panics if that sequence number was already completed.
+    pub fn complete(&self, sequence: u64, value: T) {
+        let mut state = self.state.lock().expect("lock acquire failed");
+        assert!(
+            sequence >= state.next && !state.pending.contains_key(&sequence),
+            "sequence number {sequence} completed twice"
+        );
+        let unblocks = sequence == state.next;
+        state.pending.insert(sequence, value);
+        drop(state);
+        if unblocks {
+            self.cvar.notify_all();
+        }
+    }
+
+    /// Takes the next value in order if it has completed.
+    pub fn try_recv(&self) -> Option<T> {
+        let mut state = self.state.lock().expect("lock acquire failed");
+        state.release()
+    }
+
+    /// Takes the next value in order, waiting until it completes.
+    pub fn recv(&self) -> T {
+        let mut state = self.state.lock().expect("lock acquire failed");
+        loop {
+            if let Some(value) = state.release() {
+                return value;
+            }
+            state = self.cvar.wait(state).expect("lock acquire failed");
+        }
+    }
+
+    /// Takes every value that is ready in order, up to the first gap.
+    pub fn drain_ready(&self) -> Vec<T> {
+        let mut state = self.state.lock().expect("lock acquire failed");
+        std::iter::from_fn(|| state.release()).collect()
+    }
+
+    /// Returns the sequence number of the next value to be released.
+    pub fn next_sequence(&self) -> u64 {
+        self.state.lock().expect("lock acquire failed").next
+    }
+
+    /// Returns the number of completed values that were not received yet.
+    pub fn buffered(&self) -> usize {
+        self.state
+            .lock()
+            .expect("lock acquire failed")
+            .pending
+            .len()
+    }
+}
+
+impl<T> SequencerState<T> {
+    fn release(&mut self) -> Option<T> {
+        let value = self.pending.remove(&self.next)?;
+        self.next += 1;
+        Some(value)
+    }
+}
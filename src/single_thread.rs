@@ -0,0 +1,63 @@
+use crate::container::{ConcurrentQueue, ConcurrentStack};
+use std::{cell::RefCell, collections::VecDeque};
+
+/// A [ConcurrentQueue] for code that is tested on a single thread, e.g. business logic that
+/// takes a `&dyn ConcurrentQueue<T>`. Backed by a [RefCell], so tests run deterministically
+/// and without the cost of atomics or locks. It is not [Sync], so it can't be shared with
+/// other threads by accident.
+#[derive(Debug, Default)]
+pub struct SingleThreadQueue<T> {
+    pub values: RefCell<VecDeque<T>>,
+}
+
+/// The [ConcurrentStack] counterpart of [SingleThreadQueue].
+#[derive(Debug, Default)]
+pub struct SingleThreadStack<T> {
+    pub values: RefCell<Vec<T>>,
+}
+
+impl<T> SingleThreadQueue<T> {
+    /// Creates a new empty queue.
+    pub fn new() -> Self {
+        SingleThreadQueue {
+            values: RefCell::new(VecDeque::new()),
+        }
+    }
+}
+
+impl<T> SingleThreadStack<T> {
+    /// Creates a new empty stack.
+    pub fn new() -> Self {
+        SingleThreadStack {
+            values: RefCell::new(Vec::new()),
+        }
+    }
+}
+
+impl<T> ConcurrentQueue<T> for SingleThreadQueue<T> {
+    fn push(&self, value: T) {
+        self.values.borrow_mut().push_back(value);
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        self.values.borrow_mut().pop_front()
+    }
+
+    fn len(&self) -> usize {
+        self.values.borrow().len()
+    }
+}
+
+impl<T> ConcurrentStack<T> for SingleThreadStack<T> {
+    fn push(&self, value: T) {
+        self.values.borrow_mut().push(value);
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        self.values.borrow_mut().pop()
+    }
+
+    fn len(&self) -> usize {
+        self.values.borrow().len()
+    }
+}
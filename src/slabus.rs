@@ -0,0 +1,190 @@
+use std::sync::{
+    atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    RwLock,
+};
+
+/// Marks the end of the free list.
+const NO_SLOT: u32 = u32::MAX;
+
+/// A fixed capacity concurrent slab. Free slot indices are kept in a lock-free stack
+/// threaded through the slots themselves, its head is tagged with a counter bumped on every
+/// change so a stale compare_exchange can't succeed after the same index was reused (ABA).
+/// Every slot has a generation which is bumped on removal, so a [Key] of a removed value
+/// never matches the value inserted in its place later.
+#[derive(Debug)]
+pub struct Slabus<T> {
+    pub slots: Vec<Slot<T>>,
+    /// Low 32 bits are the index of the first free slot, high 32 bits are the ABA tag.
+    pub free_head: AtomicU64,
+    pub len: AtomicUsize,
+}
+
+#[derive(Debug)]
+pub struct Slot<T> {
+    pub next_free: AtomicU32,
+    pub entry: RwLock<Entry<T>>,
+}
+
+#[derive(Debug)]
+pub struct Entry<T> {
+    pub generation: u32,
+    pub value: Option<T>,
+}
+
+/// Handle to a value stored in [Slabus], returned by [Slabus::insert].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub index: u32,
+    pub generation: u32,
+}
+
+impl<T> Slabus<T> {
+    /// Creates a new slab with room for `capacity` values.
+    pub fn with_capacity(capacity: usize) -> Self {
+        assert!(capacity < NO_SLOT as usize, "capacity is too large");
+        let slots = (0..capacity)
+            .map(|i| Slot {
+                next_free: AtomicU32::new(if i + 1 < capacity {
+                    i as u32 + 1
+                } else {
+                    NO_SLOT
+                }),
+                entry: RwLock::new(Entry {
+                    generation: 0,
+                    value: None,
+                }),
+            })
+            .collect();
+        Slabus {
+            slots,
+            free_head: AtomicU64::new(if capacity > 0 { 0 } else { NO_SLOT as u64 }),
+            len: AtomicUsize::new(0),
+        }
+    }
+
+    /// Stores a value and returns the key to access it, or gives the value back if the slab is full.
+    pub fn insert(&self, value: T) -> Result<Key, T> {
+        let Some(index) = self.allocate() else {
+            return Err(value);
+        };
+        let mut entry = self.slots[index as usize]
+            .entry
+            .write()
+            .expect("lock acquire failed");
+        entry.value = Some(value);
+        self.len.fetch_add(1, Ordering::SeqCst);
+        Ok(Key {
+            index,
+            generation: entry.generation,
+        })
+    }
+
+    /// Returns a copy of the value for `key`, or [None] if it was removed.
+    pub fn get(&self, key: Key) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.with(key, T::clone)
+    }
+
+    /// Calls `f` with a reference to the value for `key`, or returns [None] if it was removed.
+    pub fn with<R>(&self, key: Key, f: impl FnOnce(&T) -> R) -> Option<R> {
+        let entry = self
+            .slots
+            .get(key.index as usize)?
+            .entry
+            .read()
+            .expect("lock acquire failed");
+        if entry.generation != key.generation {
+            return None;
+        }
+        entry.value.as_ref().map(f)
+    }
+
+    /// Removes and returns the value for `key`, or [None] if it was already removed.
+    pub fn remove(&self, key: Key) -> Option<T> {
+        let mut entry = self
+            .slots
+            .get(key.index as usize)?
+            .entry
+            .write()
+            .expect("lock acquire failed");
+        if entry.generation != key.generation {
+            return None;
+        }
+        let value = entry.value.take()?;
+        // stale keys stop matching from now on
+        entry.generation = entry.generation.wrapping_add(1);
+        drop(entry);
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        self.release(key.index);
+        Some(value)
+    }
+
+    /// Returns true if `key` refers to a value that is still stored.
+    pub fn contains(&self, key: Key) -> bool {
+        self.with(key, |_| ()).is_some()
+    }
+
+    /// Returns the number of stored values.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// Returns true if the slab contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of values the slab can hold.
+    pub fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Pops an index from the free list.
+    fn allocate(&self) -> Option<u32> {
+        let mut head = self.free_head.load(Ordering::Acquire);
+        loop {
+            let index = head as u32;
+            if index == NO_SLOT {
+                return None;
+            }
+            let next = self.slots[index as usize].next_free.load(Ordering::Acquire);
+            let new_head = Self::tagged(head, next);
+            match self.free_head.compare_exchange_weak(
+                head,
+                new_head,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(index),
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Pushes an index back to the free list.
+    fn release(&self, index: u32) {
+        let mut head = self.free_head.load(Ordering::Acquire);
+        loop {
+            self.slots[index as usize]
+                .next_free
+                .store(head as u32, Ordering::Release);
+            match self.free_head.compare_exchange_weak(
+                head,
+                Self::tagged(head, index),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Builds a new free list head pointing at `index` with the tag of `head` bumped.
+    fn tagged(head: u64, index: u32) -> u64 {
+        let tag = (head >> 32).wrapping_add(1);
+        (tag << 32) | index as u64
+    }
+}
@@ -0,0 +1,140 @@
+use crate::spin::Backoff;
+use crate::stackus::{Nodus, ReclaimConfig, Stackus};
+use std::{
+    alloc::{handle_alloc_error, Layout},
+    cell::Cell,
+    fmt,
+    marker::PhantomData,
+    mem::ManuallyDrop,
+    ptr,
+    sync::{atomic::Ordering, Arc},
+};
+
+/// A stack with a single pusher and any number of poppers, e.g. one logging thread feeding
+/// several writers. With only one pusher the push is wait-free: the new node is swapped into
+/// the head in one instruction and linked to the old head right after, instead of retrying a
+/// compare-exchange against concurrent pops. A pop that finds the node in between spins for
+/// those few instructions. The nodes, counters and reclamation are those of a
+/// [crate::stackus::Stackus], only push and pop are its own. The stack is private, the pops
+/// of a Stackus don't know about the unlinked node and would follow its marker.
+pub struct SpStackus<T> {
+    stack: Arc<Stackus<T>>,
+}
+
+/// The only handle that can push onto a [SpStackus], it can be sent but not shared or cloned.
+pub struct SpPusher<T> {
+    stack: Arc<Stackus<T>>,
+    owner: PhantomData<Cell<()>>,
+}
+
+/// Marks a node whose next pointer the pusher is about to store, never dereferenced.
+fn linking<T>() -> *mut ManuallyDrop<Nodus<T>> {
+    ptr::dangling_mut()
+}
+
+impl<T> SpStackus<T> {
+    /// Creates a new empty stack and the only handle that can push onto it.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> (SpPusher<T>, SpStackus<T>) {
+        let stack = Arc::new(Stackus::empty(ReclaimConfig::default()));
+        (
+            SpPusher {
+                stack: stack.clone(),
+                owner: PhantomData,
+            },
+            SpStackus { stack },
+        )
+    }
+
+    /// Removes the element from the top of the stack and returns it, or [None] if it is empty.
+    pub fn pop(&self) -> Option<T> {
+        let stack = &*self.stack;
+        stack.enter_pop();
+        // Acquire pairs with the swap of the push, so the value is visible
+        let mut node = stack.head.load(Ordering::Acquire);
+        loop {
+            if node.is_null() {
+                stack.threads_in_pop.fetch_sub(1, Ordering::Release);
+                return None;
+            }
+            // counted in threads_in_pop, so the node can't be freed while it is read
+            let next = unsafe { next_of(node) };
+            match stack
+                .head
+                .compare_exchange_weak(node, next, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(current) => node = current,
+            }
+        }
+        stack.len.fetch_sub(1, Ordering::Relaxed);
+        let value = ManuallyDrop::into_inner(unsafe { node.read() }).value;
+        // decrements threads_in_pop and frees or defers the node
+        stack.try_reclaim(node);
+        Some(value)
+    }
+
+    /// Returns true if the stack contains no elements.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+impl<T> Clone for SpStackus<T> {
+    fn clone(&self) -> Self {
+        SpStackus {
+            stack: self.stack.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for SpStackus<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpStackus")
+            .field("len", &self.stack.len())
+            .field("pending_retired", &self.stack.pending_retired())
+            .finish()
+    }
+}
+
+impl<T> SpPusher<T> {
+    /// Inserts a value at the top of the stack, finishes in a fixed number of steps no matter
+    /// what the poppers do.
+    pub fn push(&self, value: T) {
+        let Ok(node) = Stackus::allocate(value, linking()) else {
+            handle_alloc_error(Layout::new::<ManuallyDrop<Nodus<T>>>());
+        };
+        self.stack.len.fetch_add(1, Ordering::Relaxed);
+        // Release publishes the value to the pop that loads the node
+        let old_head = self.stack.head.swap(node, Ordering::Release);
+        unsafe { node.as_ref().expect("node is not null") }
+            .next
+            .store(old_head, Ordering::Release);
+    }
+}
+
+impl<T> fmt::Debug for SpPusher<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SpPusher")
+    }
+}
+
+/// Returns the next pointer of `node`, waiting for the pusher to store it.
+///
+/// # Safety
+/// `node` must be protected from reclamation by threads_in_pop.
+unsafe fn next_of<T>(node: *mut ManuallyDrop<Nodus<T>>) -> *mut ManuallyDrop<Nodus<T>> {
+    let mut backoff = Backoff::new();
+    loop {
+        // Acquire pairs with the store of the pusher, the nodes below were pushed before it
+        let next = node
+            .as_ref()
+            .expect("node is not null")
+            .next
+            .load(Ordering::Acquire);
+        if next != linking() {
+            return next;
+        }
+        backoff.snooze();
+    }
+}
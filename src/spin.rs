@@ -0,0 +1,128 @@
+#[cfg(not(loom))]
+use std::{hint, thread};
+use std::{
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Rounds of spinning after which a [Backoff] stops doubling the spins per round.
+#[cfg(not(loom))]
+const MAX_SPIN_SHIFT: u32 = 6;
+
+/// Stands for [None] in PARK_NANOS.
+const NO_PARK: u64 = u64::MAX;
+
+static SPIN_ROUNDS: AtomicU32 = AtomicU32::new(SpinPolicy::DEFAULT.spin_rounds);
+static YIELD_ROUNDS: AtomicU32 = AtomicU32::new(SpinPolicy::DEFAULT.yield_rounds);
+static PARK_NANOS: AtomicU64 = AtomicU64::new(NO_PARK);
+
+/// How the busy-wait loops of the crate wait for another thread, e.g. a ticket lock waiting
+/// for its turn or [crate::stackus::Stackus::pop_spin] waiting for a value. They spin with
+/// [std::hint::spin_loop] first, then give the time slice away with [std::thread::yield_now] and
+/// finally park for a while between checks. On a machine with fewer CPUs than busy threads,
+/// e.g. a cloud VM with 2 vCPUs, spinning burns the time the thread being waited for needs to
+/// make progress, so there fewer spins and earlier parking work better. Set for the whole
+/// process with [set_spin_policy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpinPolicy {
+    /// Rounds of spinning, each spins twice as long as the one before up to 64 spins.
+    pub spin_rounds: u32,
+    /// Rounds of yielding after the spinning.
+    pub yield_rounds: u32,
+    /// How long to sleep per round once the yielding is done, [None] keeps yielding.
+    pub park: Option<Duration>,
+}
+
+/// The wait of one busy-wait loop, escalating according to the [SpinPolicy] read when it is
+/// created. Create one per wait, the rounds don't go back down.
+#[derive(Debug)]
+pub struct Backoff {
+    pub policy: SpinPolicy,
+    pub round: u32,
+}
+
+impl SpinPolicy {
+    /// Spins about a hundred times, then keeps yielding. Waits the shortest on a machine with
+    /// a CPU for every busy thread.
+    pub const DEFAULT: SpinPolicy = SpinPolicy {
+        spin_rounds: 7,
+        yield_rounds: 0,
+        park: None,
+    };
+
+    /// Spins a few times, yields a few times and then sleeps 100 microseconds per round, for
+    /// machines with more busy threads than CPUs.
+    pub const OVERSUBSCRIBED: SpinPolicy = SpinPolicy {
+        spin_rounds: 2,
+        yield_rounds: 8,
+        park: Some(Duration::from_micros(100)),
+    };
+}
+
+impl Default for SpinPolicy {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Sets the [SpinPolicy] of every busy-wait loop of the crate that starts afterwards.
+pub fn set_spin_policy(policy: SpinPolicy) {
+    SPIN_ROUNDS.store(policy.spin_rounds, Ordering::Relaxed);
+    YIELD_ROUNDS.store(policy.yield_rounds, Ordering::Relaxed);
+    let park = policy.park.map_or(NO_PARK, |park| {
+        park.as_nanos().min(NO_PARK as u128 - 1) as u64
+    });
+    PARK_NANOS.store(park, Ordering::Relaxed);
+}
+
+/// Returns the current [SpinPolicy].
+pub fn spin_policy() -> SpinPolicy {
+    let park = PARK_NANOS.load(Ordering::Relaxed);
+    SpinPolicy {
+        spin_rounds: SPIN_ROUNDS.load(Ordering::Relaxed),
+        yield_rounds: YIELD_ROUNDS.load(Ordering::Relaxed),
+        park: (park != NO_PARK).then(|| Duration::from_nanos(park)),
+    }
+}
+
+impl Backoff {
+    /// Starts a wait under the current global [SpinPolicy].
+    pub fn new() -> Self {
+        Backoff {
+            policy: spin_policy(),
+            round: 0,
+        }
+    }
+
+    /// Waits one round, a little longer than the round before.
+    pub fn snooze(&mut self) {
+        // loom explores the interleavings only if a spin loop hands over to it
+        #[cfg(loom)]
+        loom::thread::yield_now();
+        #[cfg(not(loom))]
+        {
+            let SpinPolicy {
+                spin_rounds,
+                yield_rounds,
+                park,
+            } = self.policy;
+            if self.round < spin_rounds {
+                for _ in 0..1 << self.round.min(MAX_SPIN_SHIFT) {
+                    hint::spin_loop();
+                }
+            } else {
+                match park {
+                    Some(park) if self.round - spin_rounds >= yield_rounds => thread::sleep(park),
+                    _ => thread::yield_now(),
+                }
+            }
+        }
+        self.round = self.round.saturating_add(1);
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
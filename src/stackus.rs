@@ -1,172 +1,880 @@
+use crate::chaos;
+use crate::sanitize;
+use crate::spin::Backoff;
+#[cfg(loom)]
+use loom::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize};
+#[cfg(not(loom))]
+use std::sync::atomic::{fence, AtomicBool, AtomicPtr, AtomicUsize};
 use std::{
     alloc::{self, handle_alloc_error, Layout},
-    fmt::Debug,
+    fmt::{self, Debug},
+    marker::PhantomData,
     mem::ManuallyDrop,
+    ops::Deref,
     ptr::{self, null_mut},
-    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
 };
 
 type AllocatedNode<T> = ManuallyDrop<Nodus<T>>;
 
+/// Number of values from the top the Debug output of a [Stackus] shows.
+const DEBUG_VALUES: usize = 8;
+
 /// A lock-free general purpose stack. Implenemented based on the book
 /// "C++ Concurrency in Action: Practical Multithreading" by Anthony Williams.
 /// Has to use [ManuallyDrop] because using [ptr::read()] on [!Copy] type will
 /// take the node by value, leaving the place pointer points to logically uninitialized.
 /// See https://users.rust-lang.org/t/why-does-reading-a-raw-pointer-cause-a-drop/66411 for details.
-#[derive(Debug)]
+///
+/// # Memory ordering
+///
+/// Every operation that links a node into head (push, replace_top) does so with a Release
+/// compare_exchange after writing the node, every operation that dereferences a node it got from
+/// head (pop, pop_if, replace_top, pop_raw, pop_all, snapshot) gets the pointer with an Acquire
+/// load or read-modify-write. All later writes to head are read-modify-writes, which continue the
+/// release sequence of the push, so a pop also sees the nodes below the top fully written.
+/// The pending list works the same way: nodes are chained with Release and taken with Acquire.
+///
+/// Reclamation is the only place that needs sequential consistency. A popping thread
+/// increments threads_in_pop and then loads head, the thread that freed a node unlinks it from
+/// head and then loads threads_in_pop. With acquire and release alone both loads could miss the
+/// other thread's write, so each side has a SeqCst [fence] between its write and its load: either
+/// the popping thread sees the node unlinked, or the reclaiming thread sees it in
+/// threads_in_pop and leaves the node in the pending list. threads_in_pop is decremented with
+/// Release and checked with Acquire, so the reads of a thread that left happen before the free.
+/// Snapshots take the same handshake over snapshots and threads_in_pop. len and retired_count
+/// are only counters and use Relaxed. `tests/loom.rs` checks the scheme with loom.
 pub struct Stackus<T> {
     pub head: AtomicPtr<AllocatedNode<T>>,
     pub threads_in_pop: AtomicUsize,
     pub list_to_delete: AtomicPtr<AllocatedNode<T>>,
+    /// Number of popped nodes waiting in list_to_delete to be deallocated.
+    pub retired_count: AtomicUsize,
+    /// Number of live [Snapshot]s, pops wait until it drops to zero.
+    snapshots: AtomicUsize,
+    /// Number of values in the stack, counted before a push links its node so it never drops
+    /// below zero.
+    pub(crate) len: AtomicUsize,
+    pub reclaim: ReclaimConfig,
+    /// Number of pops that found themselves alone, counts [ReclaimConfig::scan_interval].
+    lone_pops: AtomicUsize,
+    /// Set by [Stackus::forget_on_drop].
+    pub forget_on_drop: AtomicBool,
+}
+
+/// When pops free the nodes waiting in the pending list, passed to
+/// [Stackus::with_reclaim_config]. Only a pop that finds no other pop in progress can free
+/// them, by default every such pop does. Waiting for longer lists frees them in bigger batches
+/// with fewer atomic swaps, at the cost of memory held meanwhile. The stack counts the popping
+/// threads instead of using hazard pointers or epochs, so there are no per-thread slots to size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReclaimConfig {
+    /// Number of pending nodes below which pops leave the list alone.
+    pub scan_threshold: usize,
+    /// Only every this many pops that could free the list do, must not be zero.
+    pub scan_interval: usize,
+}
+
+/// A shared handle of a [Stackus] that can be cloned and sent to other threads like a [Multiq],
+/// instead of wrapping the stack in an [Arc] by hand. Derefs to the stack for all its methods.
+/// The stack is freed with the last handle.
+///
+/// [Multiq]: crate::multiq::Multiq
+#[derive(Debug)]
+pub struct StackusHandle<T> {
+    pub stack: Arc<Stackus<T>>,
+}
+
+/// Read-only view of the values in a [Stackus], returned by [Stackus::snapshot].
+/// While it exists pops wait, since a pop moves the value out of its node and a reader could
+/// see it being dropped, pushes go on but their values are not part of the snapshot. Its fields
+/// are private, like those of [Iter] and [RetiredNode], as their pointers are dereferenced.
+#[derive(Debug)]
+pub struct Snapshot<'a, T> {
+    stack: &'a Stackus<T>,
+    head: *mut AllocatedNode<T>,
+}
+
+/// Iterator over the values of a [Snapshot] from top to bottom.
+#[derive(Debug)]
+pub struct Iter<'a, T> {
+    node: *mut AllocatedNode<T>,
+    snapshot: PhantomData<&'a T>,
+}
+
+/// Owned iterator over the values taken by [Stackus::pop_all], yields them from top to bottom.
+#[derive(Debug)]
+pub struct PopAll<T> {
+    pub values: std::vec::IntoIter<T>,
+}
+
+/// How long [Stackus::pop_spin] keeps trying while the stack is empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpinLimit {
+    /// Give up after this many rounds of backoff, each round waits as the global
+    /// [SpinPolicy](crate::spin::SpinPolicy) says.
+    Rounds(u32),
+    /// Give up once this much time passed.
+    Timeout(Duration),
+}
+
+/// Error returned by [Stackus::try_push] when no memory is left for a node, holds the value
+/// that was not pushed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushError<T>(pub T);
+
+impl<T> fmt::Display for PushError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "out of memory while pushing onto the stack")
+    }
+}
+
+impl<T: Debug> std::error::Error for PushError<T> {}
+
+/// A node unlinked by [Stackus::pop_raw] that is not handed to the stack's reclamation yet.
+/// The value stays in the node and can be read through the guard until the node is retired,
+/// either explicitly or when the guard is dropped.
+#[derive(Debug)]
+pub struct RetiredNode<'a, T> {
+    stack: &'a Stackus<T>,
+    node: *mut AllocatedNode<T>,
 }
 
 #[derive(Debug)]
 pub struct Nodus<T> {
     pub value: T,
-    pub next: *mut AllocatedNode<T>,
+    /// Atomic because a pop can still read it after another thread unlinked the node and
+    /// reused it to chain the node to list_to_delete.
+    pub next: AtomicPtr<AllocatedNode<T>>,
 }
 
 impl<T> Stackus<T> {
     /// Constructs a new stack.
     pub fn new(value: T) -> Self {
-        let new_node = ManuallyDrop::new(Nodus {
-            value,
-            next: ptr::null_mut(),
-        });
-        let layout = Layout::new::<Nodus<T>>();
-        let ptr = unsafe { alloc::alloc(layout) as *mut ManuallyDrop<Nodus<T>> };
-        if ptr.is_null() {
-            handle_alloc_error(layout);
-        }
-        unsafe { ptr::write(ptr, new_node) };
+        Self::with_reclaim_config(value, ReclaimConfig::default())
+    }
+
+    /// Constructs a new stack that frees popped nodes according to `reclaim`.
+    pub fn with_reclaim_config(value: T, reclaim: ReclaimConfig) -> Self {
+        let stack = Self::empty(reclaim);
+        stack.push(value);
+        stack
+    }
+
+    /// Constructs an empty stack, for [crate::sp_stackus::SpStackus] which links its own nodes.
+    pub(crate) fn empty(reclaim: ReclaimConfig) -> Self {
+        assert!(
+            reclaim.scan_interval > 0,
+            "scan_interval must be greater than zero"
+        );
         Stackus {
-            head: AtomicPtr::new(ptr),
+            head: AtomicPtr::new(null_mut()),
             threads_in_pop: AtomicUsize::new(0),
             list_to_delete: AtomicPtr::new(null_mut()),
+            retired_count: AtomicUsize::new(0),
+            snapshots: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            reclaim,
+            lone_pops: AtomicUsize::new(0),
+            forget_on_drop: AtomicBool::new(false),
         }
     }
 
-    /// Insert an element at the top of the stack.
-    pub fn push(&self, value: T) {
-        let new_node = ManuallyDrop::new(Nodus {
+    /// Allocates a node holding `value` and `next`, gives the value back if that fails.
+    pub(crate) fn allocate(
+        value: T,
+        next: *mut AllocatedNode<T>,
+    ) -> Result<*mut AllocatedNode<T>, T> {
+        let layout = Layout::new::<AllocatedNode<T>>();
+        let ptr = unsafe { alloc::alloc(layout) as *mut AllocatedNode<T> };
+        if ptr.is_null() {
+            return Err(value);
+        }
+        let node = ManuallyDrop::new(Nodus {
             value,
-            next: self.head.load(Ordering::SeqCst),
+            next: AtomicPtr::new(next),
         });
-        let layout = Layout::new::<Nodus<T>>();
-        let ptr = unsafe { alloc::alloc(layout) as *mut ManuallyDrop<Nodus<T>> };
-        if ptr.is_null() {
-            handle_alloc_error(layout);
+        unsafe { ptr::write(ptr, node) };
+        Ok(ptr)
+    }
+
+    /// Insert an element at the top of the stack.
+    pub fn push(&self, value: T) {
+        if self.try_push(value).is_err() {
+            handle_alloc_error(Layout::new::<AllocatedNode<T>>());
         }
-        let heap_ref = unsafe {
-            ptr::write(ptr, new_node);
-            ptr.as_mut().expect("ptr is not null")
-        };
+    }
+
+    /// Like [Stackus::push] but gives the value back if the node can't be allocated,
+    /// instead of aborting the process, so a service can shed load when memory runs out.
+    pub fn try_push(&self, value: T) -> Result<(), PushError<T>> {
+        // the pushing thread never reads through head, Relaxed is enough until the publish
+        let mut next = self.head.load(Ordering::Relaxed);
+        let ptr = Self::allocate(value, next).map_err(PushError)?;
+        let heap_ref = unsafe { ptr.as_mut().expect("ptr is not null") };
+        self.len.fetch_add(1, Ordering::Relaxed);
         loop {
+            // Release publishes the value and next to the thread that pops the node
             match self.head.compare_exchange_weak(
-                heap_ref.next,
+                next,
                 heap_ref,
-                Ordering::SeqCst,
+                Ordering::Release,
                 Ordering::Relaxed,
             ) {
                 Ok(_) => {
                     break;
                 }
-                Err(_) => heap_ref.next = self.head.load(Ordering::SeqCst),
+                Err(current) => {
+                    next = current;
+                    heap_ref.next.store(next, Ordering::Relaxed);
+                }
             }
         }
+        Ok(())
     }
 
     /// Removes telement from the top of the stack and returns it, or ['None'] if it
     /// is empty.
     pub fn pop(&self) -> Option<T> {
-        self.threads_in_pop.fetch_add(1, Ordering::SeqCst);
-        let old_head = self.head.load(Ordering::SeqCst);
+        self.enter_pop();
+        // Acquire pairs with the Release of the push, so next and value are visible
+        let mut old_head = self.head.load(Ordering::Acquire);
         loop {
-            if !self.head.load(Ordering::SeqCst).is_null() {
-                if self
-                    .head
-                    .compare_exchange_weak(
-                        old_head,
-                        unsafe { old_head.read().next },
-                        Ordering::SeqCst,
-                        Ordering::Relaxed,
-                    )
-                    .is_ok()
-                {
-                    let allocated_node = unsafe { old_head.read() };
-                    let inner = ManuallyDrop::into_inner(allocated_node);
-                    self.try_reclaim(old_head);
-                    return Some(inner.value);
+            chaos::yield_point();
+            if !old_head.is_null() {
+                match self.head.compare_exchange_weak(
+                    old_head,
+                    // counted in threads_in_pop, so the node can't be freed while it is read
+                    unsafe { old_head.as_ref().expect("node is not null") }
+                        .next
+                        .load(Ordering::Relaxed),
+                    Ordering::Acquire,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => {
+                        self.len.fetch_sub(1, Ordering::Relaxed);
+                        let allocated_node = unsafe { old_head.read() };
+                        let inner = ManuallyDrop::into_inner(allocated_node);
+                        self.try_reclaim(old_head);
+                        return Some(inner.value);
+                    }
+                    Err(current) => old_head = current,
                 }
             } else {
-                self.threads_in_pop.fetch_sub(1, Ordering::SeqCst);
+                self.threads_in_pop.fetch_sub(1, Ordering::Release);
                 return None;
             }
         }
     }
 
+    /// Like [Stackus::pop] but spins with exponential backoff while the stack is empty, until
+    /// a value is pushed or `limit` is reached. For latency-sensitive consumers that would
+    /// rather burn a few cycles than return [None] and get rescheduled. Past the spinning of
+    /// the global [SpinPolicy](crate::spin::SpinPolicy) it yields and parks instead.
+    pub fn pop_spin(&self, limit: SpinLimit) -> Option<T> {
+        let start = Instant::now();
+        let mut backoff = Backoff::new();
+        loop {
+            // waiting on the head alone doesn't register the thread as popping
+            if !self.is_empty() {
+                if let Some(value) = self.pop() {
+                    return Some(value);
+                }
+            }
+            let exhausted = match limit {
+                SpinLimit::Rounds(rounds) => backoff.round >= rounds,
+                SpinLimit::Timeout(timeout) => start.elapsed() >= timeout,
+            };
+            if exhausted {
+                return None;
+            }
+            backoff.snooze();
+        }
+    }
+
+    /// Removes the top element only if `predicate` returns true for it, e.g. to take a timer
+    /// only once its deadline passed. If another thread changes the top in between, the new
+    /// top is checked again, so the removed element always passed the predicate as the top.
+    /// The predicate may be called several times.
+    ///
+    /// The predicate runs under a [Snapshot], so no pop can move the value out while it is
+    /// read, and it must not pop from the stack itself.
+    pub fn pop_if<F: FnMut(&T) -> bool>(&self, mut predicate: F) -> Option<T> {
+        let snapshot = self.snapshot();
+        let mut node = snapshot.head;
+        loop {
+            // nodes are neither popped nor freed while the snapshot lives, only pushes move head
+            let top = unsafe { node.as_ref()? };
+            if !predicate(&top.value) {
+                return None;
+            }
+            match self.head.compare_exchange_weak(
+                node,
+                top.next.load(Ordering::Relaxed),
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => node = current,
+            }
+        }
+        drop(snapshot);
+        // other snapshots may still read the unlinked node, enter_pop waits for them like for
+        // any pop, after that it is reclaimed the same way
+        self.enter_pop();
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        let inner = ManuallyDrop::into_inner(unsafe { node.read() });
+        self.try_reclaim(node);
+        Some(inner.value)
+    }
+
+    /// Removes the top element only if it equals `expected`, e.g. for a state machine that
+    /// pops a state only while it is still the one it observed. Compares under a [Snapshot]
+    /// like [Stackus::pop_if].
+    pub fn pop_if_eq(&self, expected: &T) -> Option<T>
+    where
+        T: PartialEq,
+    {
+        self.pop_if(|top| top == expected)
+    }
+
+    /// Replaces the value at the top of the stack and returns the old one, or pushes `value`
+    /// and returns [None] if the stack is empty. The top node is swapped for a new one in a
+    /// single exchange, e.g. to coalesce repeated update events so only the latest is kept.
+    pub fn replace_top(&self, value: T) -> Option<T> {
+        let layout = Layout::new::<Nodus<T>>();
+        let new_node = unsafe { alloc::alloc(layout) as *mut ManuallyDrop<Nodus<T>> };
+        if new_node.is_null() {
+            handle_alloc_error(layout);
+        }
+        unsafe {
+            ptr::write(
+                new_node,
+                ManuallyDrop::new(Nodus {
+                    value,
+                    next: AtomicPtr::new(null_mut()),
+                }),
+            )
+        };
+        // counted up front in case the stack is empty and this becomes a push
+        self.len.fetch_add(1, Ordering::Relaxed);
+        self.enter_pop();
+        let mut node = self.head.load(Ordering::Acquire);
+        loop {
+            // counted in threads_in_pop, so the node can't be freed while it is read
+            let next = match unsafe { node.as_ref() } {
+                Some(top) => top.next.load(Ordering::Relaxed),
+                None => null_mut(),
+            };
+            unsafe { new_node.as_ref().expect("node is not null") }
+                .next
+                .store(next, Ordering::Relaxed);
+            // Release publishes the new node, Acquire reads the next of the replaced one
+            match self.head.compare_exchange_weak(
+                node,
+                new_node,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(current) => node = current,
+            }
+        }
+        if node.is_null() {
+            self.threads_in_pop.fetch_sub(1, Ordering::Release);
+            return None;
+        }
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        let inner = ManuallyDrop::into_inner(unsafe { node.read() });
+        self.try_reclaim(node);
+        Some(inner.value)
+    }
+
+    /// Unlinks the top node without taking its value or reclaiming it, for embedders that
+    /// decide themselves when a popped node may be freed, e.g. at the end of their own epoch.
+    /// The node is freed by the stack's usual reclamation after [RetiredNode::retire].
+    pub fn pop_raw(&self) -> Option<RetiredNode<'_, T>> {
+        self.enter_pop();
+        let mut node = self.head.load(Ordering::Acquire);
+        loop {
+            if node.is_null() {
+                self.threads_in_pop.fetch_sub(1, Ordering::Release);
+                return None;
+            }
+            chaos::yield_point();
+            // counted in threads_in_pop, so the node can't be freed while it is read
+            let next = unsafe { node.as_ref().expect("node is not null") }
+                .next
+                .load(Ordering::Relaxed);
+            match self
+                .head
+                .compare_exchange_weak(node, next, Ordering::Acquire, Ordering::Acquire)
+            {
+                Ok(_) => break,
+                Err(current) => node = current,
+            }
+        }
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        // the node is unlinked but not pending, so nobody frees it until it is retired
+        self.threads_in_pop.fetch_sub(1, Ordering::Release);
+        Some(RetiredNode { stack: self, node })
+    }
+
+    /// Removes all elements with a single atomic swap of the head and returns them in LIFO order.
+    /// Concurrent pushes and pops never contend with the drain beyond that one swap.
+    pub fn pop_all(&self) -> PopAll<T> {
+        self.enter_pop();
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Acquire);
+        let mut values = Vec::new();
+        while !node.is_null() {
+            let inner = ManuallyDrop::into_inner(unsafe { node.read() });
+            values.push(inner.value);
+            // a concurrent pop may have loaded this node before the swap, so it can only
+            // be freed through the same path as popped nodes
+            self.chain_pending_node(node);
+            node = inner.next.load(Ordering::Relaxed);
+        }
+        self.len.fetch_sub(values.len(), Ordering::Relaxed);
+        self.threads_in_pop.fetch_sub(1, Ordering::Release);
+        self.reclaim_now();
+        PopAll {
+            values: values.into_iter(),
+        }
+    }
+
+    /// Consumes the stack and returns its values top to bottom. Owning the stack means no
+    /// other thread can reach it, so the nodes are freed right away without the handshake
+    /// of [Stackus::pop_all].
+    pub fn into_vec(self) -> Vec<T> {
+        let mut node = self.head.swap(ptr::null_mut(), Ordering::Relaxed);
+        let mut values = Vec::with_capacity(self.len.swap(0, Ordering::Relaxed));
+        while !node.is_null() {
+            let inner = ManuallyDrop::into_inner(unsafe { node.read() });
+            unsafe { alloc::dealloc(node as _, Layout::new::<AllocatedNode<T>>()) };
+            values.push(inner.value);
+            node = inner.next.load(Ordering::Relaxed);
+        }
+        // dropping self frees the pending list
+        values
+    }
+
+    /// Takes a read-only snapshot of the stack, e.g. for a monitoring thread that counts or
+    /// inspects outstanding items without popping them. Pops block until it is dropped,
+    /// so the thread holding it must not pop.
+    pub fn snapshot(&self) -> Snapshot<'_, T> {
+        self.snapshots.fetch_add(1, Ordering::Relaxed);
+        // pops announce themselves in threads_in_pop before checking snapshots, so once it
+        // reads zero every later pop sees this snapshot and waits, the fences of both sides
+        // keep them from missing each other's increment
+        fence(Ordering::SeqCst);
+        let mut backoff = Backoff::new();
+        while self.threads_in_pop.load(Ordering::Acquire) != 0 {
+            backoff.snooze();
+        }
+        Snapshot {
+            stack: self,
+            head: self.head.load(Ordering::Acquire),
+        }
+    }
+
+    /// Like [Stackus::snapshot] but returns [None] instead of waiting while a pop is in
+    /// progress.
+    fn try_snapshot(&self) -> Option<Snapshot<'_, T>> {
+        self.snapshots.fetch_add(1, Ordering::Relaxed);
+        // same handshake as snapshot, a pop either sees the count or was seen here
+        fence(Ordering::SeqCst);
+        if self.threads_in_pop.load(Ordering::Acquire) != 0 {
+            self.snapshots.fetch_sub(1, Ordering::Release);
+            return None;
+        }
+        Some(Snapshot {
+            stack: self,
+            head: self.head.load(Ordering::Acquire),
+        })
+    }
+
+    /// Returns copies of the values top to bottom without removing them, read from a
+    /// [Snapshot], e.g. to check the contents in a test, see [crate::assert_contents_eq].
+    pub fn to_vec(&self) -> Vec<T>
+    where
+        T: Clone,
+    {
+        self.snapshot().iter().cloned().collect()
+    }
+
+    /// Registers the current thread in threads_in_pop, waiting while a snapshot is taken.
+    pub(crate) fn enter_pop(&self) {
+        loop {
+            self.threads_in_pop.fetch_add(1, Ordering::Relaxed);
+            // orders the increment before the loads of snapshots and head, pairs with the
+            // fences in snapshot and the reclamation
+            fence(Ordering::SeqCst);
+            if self.snapshots.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            self.threads_in_pop.fetch_sub(1, Ordering::Release);
+            let mut backoff = Backoff::new();
+            while self.snapshots.load(Ordering::Acquire) != 0 {
+                backoff.snooze();
+            }
+        }
+    }
+
     /// If multiple threads are calling pop() on the same stack instance, need a way to
     /// track when it's safe to delete a node, this essentially a special purpose GC just for nodes.
     /// If there are no threads calling pop(), it's safe to delete all the nodes awaiting deletion,
     /// threads_in_pop incremented on entry and decremented on exit, its's safe to delete
-    /// nodes when the counter is zero.
-    fn try_reclaim(&self, old_head: *mut ManuallyDrop<Nodus<T>>) {
-        if self.threads_in_pop.load(Ordering::SeqCst) == 1 {
-            // claim list of nodes to be deleted
-            let nodes_to_delete = self.list_to_delete.swap(ptr::null_mut(), Ordering::AcqRel);
-            // check if counter is still 1 while list was creating and decrement so no other thread can access
-            if self.threads_in_pop.fetch_sub(1, Ordering::SeqCst) == 1 {
-                Self::delete_nodes(nodes_to_delete);
-            } else {
-                // if another pop started need to return back claimed nodes_to_delete
-                self.chain_pending_nodes(nodes_to_delete);
-            }
-            // delete old_head
-            unsafe { alloc::dealloc(old_head as _, Layout::for_value(&old_head.as_ref())) };
-        } else {
-            // add old_head to the list of nodes_to_delte
-            self.chain_pending_nodes(old_head);
-            self.threads_in_pop.fetch_sub(1, Ordering::SeqCst);
+    /// nodes when the counter is zero. Called by a pop that unlinked old_head, decrements
+    /// threads_in_pop exactly once on every path.
+    pub(crate) fn try_reclaim(&self, old_head: *mut ManuallyDrop<Nodus<T>>) {
+        // old_head is unlinked, a pop that isn't counted yet will load a later head
+        fence(Ordering::SeqCst);
+        if self.threads_in_pop.load(Ordering::Acquire) != 1 {
+            // another pop may have loaded old_head before it was unlinked and still read it
+            self.chain_pending_node(old_head);
+            self.threads_in_pop.fetch_sub(1, Ordering::Release);
+            return;
+        }
+        // the only pop, so nobody else loaded old_head
+        if !self.scan_due() {
+            self.threads_in_pop.fetch_sub(1, Ordering::Release);
+            unsafe { alloc::dealloc(old_head as _, Layout::new::<AllocatedNode<T>>()) };
+            return;
         }
+        // claim list of nodes to be deleted
+        let nodes_to_delete = self.list_to_delete.swap(ptr::null_mut(), Ordering::Acquire);
+        chaos::yield_point();
+        // the claimed nodes were unlinked by other threads, same handshake for them
+        fence(Ordering::SeqCst);
+        // check if counter is still 1 while list was claimed and decrement so no other thread can access
+        if self.threads_in_pop.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.delete_nodes(nodes_to_delete);
+        } else if !nodes_to_delete.is_null() {
+            // a pop that started meanwhile may read the claimed nodes, return them
+            self.chain_pending_nodes(nodes_to_delete);
+        }
+        unsafe { alloc::dealloc(old_head as _, Layout::new::<AllocatedNode<T>>()) };
     }
 
-    fn delete_nodes(list: *mut ManuallyDrop<Nodus<T>>) {
+    /// Returns true if a pop that is alone should free the pending list, see [ReclaimConfig].
+    fn scan_due(&self) -> bool {
+        if self.retired_count.load(Ordering::Relaxed) < self.reclaim.scan_threshold {
+            return false;
+        }
+        self.lone_pops
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(self.reclaim.scan_interval)
+    }
+
+    /// Deallocates every node in the list, returns how many were freed.
+    fn delete_nodes(&self, mut list: *mut ManuallyDrop<Nodus<T>>) -> usize {
+        let mut deleted = 0;
         while !list.is_null() {
-            unsafe { alloc::dealloc(list as _, Layout::for_value(&list.as_ref())) };
+            let next = unsafe { list.as_ref().expect("list is not null") }
+                .next
+                .load(Ordering::Relaxed);
+            sanitize::acquire(list);
+            unsafe { alloc::dealloc(list as _, Layout::new::<AllocatedNode<T>>()) };
+            list = next;
+            deleted += 1;
         }
+        self.retired_count.fetch_sub(deleted, Ordering::Relaxed);
+        deleted
+    }
+
+    /// Adds a single popped node to the front of list_to_delete.
+    fn chain_pending_node(&self, node: *mut ManuallyDrop<Nodus<T>>) {
+        sanitize::release(node);
+        self.retired_count.fetch_add(1, Ordering::Relaxed);
+        self.chain_pending(node, node);
     }
 
+    /// Returns a claimed list of nodes to list_to_delete, they are still counted in
+    /// retired_count.
     fn chain_pending_nodes(&self, list: *mut ManuallyDrop<Nodus<T>>) {
-        let null = null_mut();
-        // if list is null just insert else loop until next is null and insert taken list
-        match self.list_to_delete.compare_exchange_weak(
-            null,
-            list,
-            Ordering::SeqCst,
-            Ordering::Relaxed,
-        ) {
-            Ok(_) => {}
-            Err(_) => unsafe { self.list_to_delete.load(Ordering::SeqCst).read().next = list },
+        // the list is claimed, so nobody else changes its links while it is walked
+        let mut last = list;
+        loop {
+            let next = unsafe { last.as_ref().expect("list is not null") }
+                .next
+                .load(Ordering::Relaxed);
+            if next.is_null() {
+                break;
+            }
+            last = next;
         }
+        self.chain_pending(list, last);
+    }
+
+    /// Puts the unlinked nodes from `first` to `last` in front of list_to_delete.
+    fn chain_pending(&self, first: *mut AllocatedNode<T>, last: *mut AllocatedNode<T>) {
+        // last is unlinked from the stack so its next can be reused for the pending list
+        let mut pending = self.list_to_delete.load(Ordering::Relaxed);
+        loop {
+            unsafe { last.as_ref().expect("node is not null") }
+                .next
+                .store(pending, Ordering::Relaxed);
+            // Release hands the nodes and everything read through them to the thread freeing them
+            match self.list_to_delete.compare_exchange_weak(
+                pending,
+                first,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(current) => pending = current,
+            }
+        }
+    }
+
+    /// Deallocates all nodes awaiting deletion if no thread is currently popping, returns
+    /// how many nodes were freed. Useful to release memory during idle periods, since
+    /// pending nodes are otherwise only freed by a pop that finds itself alone. Ignores the
+    /// [ReclaimConfig].
+    pub fn reclaim_now(&self) -> usize {
+        if self.threads_in_pop.fetch_add(1, Ordering::Acquire) != 0 {
+            self.threads_in_pop.fetch_sub(1, Ordering::Release);
+            return 0;
+        }
+        let nodes_to_delete = self.list_to_delete.swap(ptr::null_mut(), Ordering::Acquire);
+        chaos::yield_point();
+        // pairs with the fence of every pop, see try_reclaim
+        fence(Ordering::SeqCst);
+        if self.threads_in_pop.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.delete_nodes(nodes_to_delete)
+        } else {
+            if !nodes_to_delete.is_null() {
+                self.chain_pending_nodes(nodes_to_delete);
+            }
+            0
+        }
+    }
+
+    /// Makes dropping the stack leak its nodes and values instead of freeing them one by one,
+    /// e.g. for a large stack that lives until the process exits and would only slow down the
+    /// shutdown. Destructors of the values don't run.
+    pub fn forget_on_drop(&self) {
+        self.forget_on_drop.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns the node chain from head and the list of nodes waiting to be freed as a Graphviz
+    /// digraph, each node labelled with its address, e.g. to render with `dot -Tsvg` while
+    /// debugging the reclamation. Counts itself in threads_in_pop like a pop, so no node it
+    /// walks is freed meanwhile.
+    #[cfg(feature = "debug-dump")]
+    pub fn dump_dot(&self) -> String {
+        use std::fmt::Write;
+
+        self.enter_pop();
+        let mut dot = String::from("digraph Stackus {\n    rankdir=LR;\n    node [shape=box];\n");
+        let lists = [
+            ("head", self.head.load(Ordering::Acquire), "solid"),
+            (
+                "pending",
+                self.list_to_delete.load(Ordering::Acquire),
+                "dashed",
+            ),
+        ];
+        for (name, mut node, style) in lists {
+            writeln!(dot, "    {name} [shape=plaintext];").expect("writing to a String");
+            let mut from = name.to_string();
+            while !node.is_null() {
+                writeln!(dot, "    \"{node:p}\" [style={style}];").expect("writing to a String");
+                writeln!(dot, "    {from} -> \"{node:p}\";").expect("writing to a String");
+                from = format!("\"{node:p}\"");
+                node = unsafe { node.as_ref().expect("node is not null") }
+                    .next
+                    .load(Ordering::Relaxed);
+            }
+        }
+        self.threads_in_pop.fetch_sub(1, Ordering::Release);
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Returns the number of popped nodes which are not deallocated yet.
+    pub fn pending_retired(&self) -> usize {
+        self.retired_count.load(Ordering::Relaxed)
+    }
+
+    /// Returns the approximate number of bytes the stack allocated for its nodes, including
+    /// popped nodes not freed yet, e.g. to export as a gauge or enforce a memory limit. Heap
+    /// memory owned by the values themselves is not counted.
+    pub fn approx_memory_usage(&self) -> usize {
+        (self.len() + self.pending_retired()) * std::mem::size_of::<AllocatedNode<T>>()
     }
 
     /// Returns true if the stack contains no elements.
     pub fn is_empty(&self) -> bool {
-        if self.head.load(Ordering::SeqCst).is_null() {
-            true
-        } else {
-            false
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+
+    /// Returns the number of elements in the stack, may already be stale when other threads
+    /// push or pop.
+    pub fn len(&self) -> usize {
+        self.len.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Iterator for PopAll<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.values.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.values.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for PopAll<T> {}
+
+impl<T> RetiredNode<'_, T> {
+    /// Drops the value and hands the node to the stack's reclamation.
+    pub fn retire(self) {
+        // dropping does the work, the method just makes the intent visible at the call site
+    }
+
+    /// Takes the value out and hands the node to the stack's reclamation.
+    pub fn into_value(self) -> T {
+        let this = ManuallyDrop::new(self);
+        let value = unsafe { ptr::read(&this.node.as_ref().expect("node is not null").value) };
+        this.stack.chain_pending_node(this.node);
+        value
+    }
+}
+
+impl<T> Deref for RetiredNode<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &self.node.as_ref().expect("node is not null").value }
+    }
+}
+
+impl<T> Drop for RetiredNode<'_, T> {
+    fn drop(&mut self) {
+        // moved out instead of dropped in place, other pops may still borrow the node
+        drop(unsafe { ptr::read(&self.node.as_ref().expect("node is not null").value) });
+        self.stack.chain_pending_node(self.node);
+    }
+}
+
+impl Default for ReclaimConfig {
+    fn default() -> Self {
+        ReclaimConfig {
+            scan_threshold: 0,
+            scan_interval: 1,
+        }
+    }
+}
+
+impl<T> StackusHandle<T> {
+    /// Creates a new stack behind a shared handle.
+    pub fn new(value: T) -> Self {
+        Stackus::new(value).into()
+    }
+}
+
+impl<T> Clone for StackusHandle<T> {
+    fn clone(&self) -> Self {
+        StackusHandle {
+            stack: self.stack.clone(),
+        }
+    }
+}
+
+impl<T> Deref for StackusHandle<T> {
+    type Target = Stackus<T>;
+
+    fn deref(&self) -> &Stackus<T> {
+        &self.stack
+    }
+}
+
+impl<T> From<Stackus<T>> for StackusHandle<T> {
+    fn from(stack: Stackus<T>) -> Self {
+        StackusHandle {
+            stack: Arc::new(stack),
         }
     }
 }
 
+impl<T> Snapshot<'_, T> {
+    /// Returns an iterator over the values from top to bottom.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            node: self.head,
+            snapshot: PhantomData,
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Snapshot<'_, T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> Drop for Snapshot<'_, T> {
+    fn drop(&mut self) {
+        // the reads of the snapshot happen before the pops that waited for it
+        self.stack.snapshots.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        // nodes reachable from the snapshot head are neither popped nor freed while it lives
+        let node = unsafe { self.node.as_ref()? };
+        self.node = node.next.load(Ordering::Relaxed);
+        Some(&node.value)
+    }
+}
+
+/// Shows the counters of the stack and its top few values. The values are read from a
+/// [Snapshot] only while no pop is in progress, so formatting never waits, e.g. from the
+/// predicate of [Stackus::pop_if].
+impl<T: Debug> Debug for Stackus<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Stackus");
+        debug
+            .field("len", &self.len())
+            .field("pending_retired", &self.pending_retired())
+            .field(
+                "threads_in_pop",
+                &self.threads_in_pop.load(Ordering::Relaxed),
+            );
+        match self.try_snapshot() {
+            Some(snapshot) => debug.field(
+                "top",
+                &snapshot.iter().take(DEBUG_VALUES).collect::<Vec<_>>(),
+            ),
+            None => debug.field("top", &format_args!("<popping>")),
+        };
+        debug.finish()
+    }
+}
+
 impl<T> Drop for Stackus<T> {
     fn drop(self: &mut Stackus<T>) {
-        let mut cur_head = self.head.load(Ordering::SeqCst);
-        while !cur_head.is_null() {
-            let next_head = unsafe { self.head.load(Ordering::SeqCst).read().next };
-            unsafe { alloc::dealloc(cur_head as _, Layout::for_value(&cur_head.as_ref())) };
-            cur_head = next_head;
+        if self.forget_on_drop.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut node = self.head.load(Ordering::Relaxed);
+        while !node.is_null() {
+            let inner = ManuallyDrop::into_inner(unsafe { node.read() });
+            unsafe { alloc::dealloc(node as _, Layout::new::<AllocatedNode<T>>()) };
+            // the value drops at the end of the iteration
+            node = inner.next.load(Ordering::Relaxed);
         }
+        self.delete_nodes(self.list_to_delete.load(Ordering::Relaxed));
     }
 }
@@ -0,0 +1,193 @@
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+};
+
+/// A bounded single-producer single-consumer queue of up to `N` values that lives entirely
+/// inline and is built by a const fn, so it can be a `static` on targets without a heap. It
+/// only needs atomic loads and stores of `core`, pushes and pops never lock, so the producer
+/// or the consumer may run in an interrupt handler.
+///
+/// The producer and the consumer side are claimed once through [StaticQueue::producer] and
+/// [StaticQueue::consumer], which keeps the single producer and single consumer rule checked
+/// even for a queue shared as a `static`. For that the fields of the queue and of its handles
+/// are private, a second handle or a cleared claim would let two producers write one slot.
+///
+/// # Interrupts
+///
+/// An interrupt handler that produces while a thread consumes can't keep a claimed handle
+/// around, it pushes through [StaticQueue::push_unchecked] and the thread pops through
+/// [StaticQueue::pop_unchecked]. Neither runs a compare-exchange or any other read-modify-write,
+/// which cores such as thumbv6m lack. Each side loads its own position relaxed, as only it
+/// writes that position, loads the other side's position with acquire and publishes its own
+/// with release once the slot is written or read. The release of the producer orders the write
+/// of a value before the consumer reads it, the release of the consumer orders that read before
+/// the slot is written again. No volatile access is needed, an interrupt preempting the
+/// consumer on the same core is ordered by the same atomics as a producer on another core, and
+/// the compiler doesn't move slot accesses across them.
+///
+/// The crate needs std, so it is only built for hosted targets. CI runs the tests on thumbv7
+/// Linux under qemu-user, which covers the orderings on an ARM core but neither a bare-metal
+/// build nor a real interrupt.
+#[derive(Debug)]
+pub struct StaticQueue<T, const N: usize> {
+    /// Position of the next value to pop, only written by the consumer. Counts up to twice
+    /// the capacity before it wraps, so a full queue can be told apart from an empty one.
+    head: AtomicUsize,
+    /// Position of the next value to push, only written by the producer.
+    tail: AtomicUsize,
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    producer_taken: AtomicBool,
+    consumer_taken: AtomicBool,
+}
+
+/// The pushing side of a [StaticQueue], released again when dropped.
+#[derive(Debug)]
+pub struct StaticProducer<'a, T, const N: usize> {
+    queue: &'a StaticQueue<T, N>,
+}
+
+/// The popping side of a [StaticQueue], released again when dropped.
+#[derive(Debug)]
+pub struct StaticConsumer<'a, T, const N: usize> {
+    queue: &'a StaticQueue<T, N>,
+}
+
+unsafe impl<T: Send, const N: usize> Send for StaticQueue<T, N> {}
+unsafe impl<T: Send, const N: usize> Sync for StaticQueue<T, N> {}
+
+impl<T, const N: usize> StaticQueue<T, N> {
+    /// Creates a new empty queue, usable as `static QUEUE: StaticQueue<u8, 64> = StaticQueue::new();`.
+    pub const fn new() -> Self {
+        assert!(N > 0, "capacity must be greater than zero");
+        StaticQueue {
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            producer_taken: AtomicBool::new(false),
+            consumer_taken: AtomicBool::new(false),
+        }
+    }
+
+    /// Claims the pushing side, or returns [None] while another [StaticProducer] exists.
+    pub fn producer(&self) -> Option<StaticProducer<'_, T, N>> {
+        (!self.producer_taken.swap(true, Ordering::Acquire))
+            .then_some(StaticProducer { queue: self })
+    }
+
+    /// Claims the popping side, or returns [None] while another [StaticConsumer] exists.
+    pub fn consumer(&self) -> Option<StaticConsumer<'_, T, N>> {
+        (!self.consumer_taken.swap(true, Ordering::Acquire))
+            .then_some(StaticConsumer { queue: self })
+    }
+
+    /// Returns the number of values in the queue, which may be outdated by the time it returns.
+    pub fn len(&self) -> usize {
+        let head = self.head.load(Ordering::Acquire);
+        Self::distance(head, self.tail.load(Ordering::Acquire))
+    }
+
+    /// Returns true if the queue contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the maximum number of values the queue holds.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Inserts a value at the back of the queue without a claimed [StaticProducer], or hands
+    /// it back if the queue is full. Meant for an interrupt handler, see the type docs.
+    ///
+    /// # Safety
+    ///
+    /// Pushes must not run concurrently with each other or with a [StaticProducer], e.g.
+    /// because a single interrupt handler is the only producer.
+    pub unsafe fn push_unchecked(&self, value: T) -> Result<(), T> {
+        let tail = self.tail.load(Ordering::Relaxed);
+        // pairs with the release store of the consumer, the slot is free to reuse after it
+        if Self::distance(self.head.load(Ordering::Acquire), tail) == N {
+            return Err(value);
+        }
+        (*self.slots[tail % N].get()).write(value);
+        self.tail.store(Self::next(tail), Ordering::Release);
+        Ok(())
+    }
+
+    /// Removes the value at the front of the queue without a claimed [StaticConsumer] and
+    /// returns it, or [None] if it is empty.
+    ///
+    /// # Safety
+    ///
+    /// Pops must not run concurrently with each other or with a [StaticConsumer].
+    pub unsafe fn pop_unchecked(&self) -> Option<T> {
+        let head = self.head.load(Ordering::Relaxed);
+        // pairs with the release store of the producer, the value is written before it
+        if self.tail.load(Ordering::Acquire) == head {
+            return None;
+        }
+        let value = (*self.slots[head % N].get()).assume_init_read();
+        self.head.store(Self::next(head), Ordering::Release);
+        Some(value)
+    }
+
+    const fn distance(head: usize, tail: usize) -> usize {
+        (tail + 2 * N - head) % (2 * N)
+    }
+
+    const fn next(position: usize) -> usize {
+        (position + 1) % (2 * N)
+    }
+}
+
+impl<T, const N: usize> StaticProducer<'_, T, N> {
+    /// Inserts a value at the back of the queue, or hands it back if the queue is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        // the claim makes this the only producer
+        unsafe { self.queue.push_unchecked(value) }
+    }
+
+    /// Returns true if a push would fail right now.
+    pub fn is_full(&self) -> bool {
+        self.queue.len() == N
+    }
+}
+
+impl<T, const N: usize> StaticConsumer<'_, T, N> {
+    /// Removes the value at the front of the queue and returns it, or [None] if it is empty.
+    pub fn pop(&mut self) -> Option<T> {
+        // the claim makes this the only consumer
+        unsafe { self.queue.pop_unchecked() }
+    }
+}
+
+impl<T, const N: usize> Drop for StaticProducer<'_, T, N> {
+    fn drop(&mut self) {
+        self.queue.producer_taken.store(false, Ordering::Release);
+    }
+}
+
+impl<T, const N: usize> Drop for StaticConsumer<'_, T, N> {
+    fn drop(&mut self) {
+        self.queue.consumer_taken.store(false, Ordering::Release);
+    }
+}
+
+impl<T, const N: usize> Default for StaticQueue<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, const N: usize> Drop for StaticQueue<T, N> {
+    fn drop(&mut self) {
+        let (head, tail) = (*self.head.get_mut(), *self.tail.get_mut());
+        let mut index = head;
+        while index != tail {
+            unsafe { self.slots[index % N].get_mut().assume_init_drop() };
+            index = Self::next(index);
+        }
+    }
+}
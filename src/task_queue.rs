@@ -0,0 +1,75 @@
+use crate::multiq::Multiq;
+use crate::thread_pool::Job;
+use std::fmt;
+
+/// A [Multiq] of boxed closures, for hand-rolled worker loops that don't need a
+/// [crate::thread_pool::ThreadPool]. Producers submit closures, consumers run them on their own
+/// thread. A task that panics unwinds into the thread that runs it.
+#[derive(Debug, Clone)]
+pub struct TaskQueue {
+    pub queue: Multiq<Task>,
+}
+
+/// A closure waiting in a [TaskQueue].
+pub struct Task(pub Job);
+
+impl fmt::Debug for Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Task")
+    }
+}
+
+impl TaskQueue {
+    /// Creates a new empty queue.
+    pub fn new() -> Self {
+        // a Multiq starts with a value, take it right away
+        let queue = Multiq::new(Task(Box::new(|| {})));
+        queue.pop();
+        TaskQueue { queue }
+    }
+
+    /// Adds `task` to the back of the queue.
+    pub fn submit<F: FnOnce() + Send + 'static>(&self, task: F) {
+        self.queue.push(Task(Box::new(task)));
+    }
+
+    /// Runs the task at the front of the queue, returns false if there was none.
+    pub fn run_one(&self) -> bool {
+        match self.queue.pop() {
+            Some(Task(task)) => {
+                task();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Waits until a task is submitted if the queue is empty, then runs it. Returns without
+    /// running anything if the queue is empty and closed.
+    pub fn wait_and_run_one(&self) {
+        if let Ok(Task(task)) = self.queue.wait_and_pop() {
+            task();
+        }
+    }
+
+    /// Runs tasks until the queue is empty, including ones submitted by the tasks themselves.
+    /// Returns the number of tasks run.
+    pub fn run_until_empty(&self) -> usize {
+        let mut ran = 0;
+        while self.run_one() {
+            ran += 1;
+        }
+        ran
+    }
+
+    /// Returns true if the queue contains no tasks.
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl Default for TaskQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,18 +1,67 @@
-use crate::multiq::Multiq;
-use crate::stackus::Stackus;
+use crate::accumulator::Accumulator;
+use crate::appendus::Appendus;
+use crate::assert_contents_eq;
+use crate::bitus::Bitus;
+use crate::boundq::{Boundq, OverflowPolicy};
+use crate::broadcastus::{Broadcastus, Lagged};
+use crate::bus::{Backpressure, Bus};
+use crate::bytes_queue::BytesQueue;
+use crate::cancellation::{CancellationToken, Cancelled};
+#[cfg(feature = "chaos")]
+use crate::chaos;
+use crate::container::{ConcurrentQueue, ConcurrentStack};
+use crate::counter_map::CounterMap;
+use crate::dequeus::Dequeus;
+use crate::dispatcher::Dispatcher;
+use crate::event::Event;
+#[cfg(feature = "executor")]
+use crate::executor::{block_on, Executor};
+use crate::grouped_queue::GroupedQueue;
+use crate::histogram::Histogram;
+use crate::inline_stackus::InlineStackus;
+use crate::intrusive_stackus::{IntrusiveStackus, Link, Linked};
+use crate::keyed_mutex::KeyedMutex;
+use crate::left_right::LeftRight;
+use crate::lock::{DefaultLock, Lock, RawLock};
+use crate::mapus::Mapus;
+use crate::mcs_lock::McsLock;
+use crate::multiq::{Multiq, PoisonPolicy, QueueClosed, QueuePoisoned, WeakMultiq};
+use crate::parallel::{self, ParConsume};
+use crate::parker::Parker;
+use crate::promise::JobError;
+use crate::rate_limiter::RateLimiter;
+use crate::rcu::Rcu;
+use crate::reentrant_mutex::ReentrantMutex;
+use crate::registry::{self, Registry};
+use crate::scheduler::Scheduler;
+use crate::seqlock::SeqLock;
+use crate::sequencer::Sequencer;
+use crate::single_thread::{SingleThreadQueue, SingleThreadStack};
+use crate::slabus::Slabus;
+use crate::sp_stackus::SpStackus;
+use crate::spin::{self, Backoff, SpinPolicy};
+use crate::stackus::{PushError, ReclaimConfig, SpinLimit, Stackus, StackusHandle};
+use crate::static_queue::StaticQueue;
+use crate::task_queue::TaskQueue;
+use crate::thread_pool::{Autoscale, PanicPolicy, Priority, RejectionPolicy, ThreadPool};
+use crate::ticket_lock::TicketLock;
+use crate::timer_driver::TimerDriver;
+use crate::watch::Watch;
 use ::std::thread;
+use std::collections::HashMap;
 use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc, Barrier,
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    mpsc, Arc, Barrier,
 };
+use std::time::{Duration, Instant};
 #[test]
 fn queue_test() {
-    let mut q = Multiq::new(1);
-    let mut q2 = q.clone();
-    let mut q3 = q.clone();
-    let mut q4 = q.clone();
-    let mut q5 = q.clone();
-    let mut q6 = q.clone();
+    let q = Multiq::new(1);
+    let q2 = q.clone();
+    let q3 = q.clone();
+    let q4 = q.clone();
+    let q5 = q.clone();
+    let q6 = q.clone();
 
     let thread3 = thread::spawn(move || q2.push(2));
     thread3.join().unwrap();
@@ -23,7 +72,7 @@ fn queue_test() {
         q4.pop();
     });
     let thread3 = thread::spawn(move || {
-        let value: i32 = q5.wait_and_pop();
+        let value: i32 = q5.wait_and_pop().unwrap();
         assert!(value.is_positive())
     });
     let thread4 = thread::spawn(move || q6.push(3));
@@ -32,7 +81,7 @@ fn queue_test() {
     thread3.join().unwrap();
     thread4.join().unwrap();
     q.pop();
-    assert_eq!(q.is_empty(), true);
+    assert!(q.is_empty());
 }
 
 #[test]
@@ -56,11 +105,11 @@ fn stack_push_works() {
         handle.join().unwrap();
     }
     let mut res = stack.pop();
-    let mut sum_of_popped_values = 0 + res.unwrap();
+    let mut sum_of_popped_values = res.unwrap();
     while res.is_some() {
         res = stack.pop();
-        if res.is_some() {
-            sum_of_popped_values = sum_of_popped_values + res.unwrap();
+        if let Some(value) = res {
+            sum_of_popped_values += value;
         }
     }
     assert_eq!(
@@ -73,10 +122,8 @@ fn stack_push_works() {
 fn stack_pop_works() {
     // sum of first 10 is 55
     let stack = Arc::new(Stackus::new(1));
-    let mut i = 2;
-    for _ in 0..9 {
+    for i in 2..=10 {
         stack.push(i);
-        i += 1;
     }
     const THREAD_NUM: usize = 5;
     let mut handles = Vec::with_capacity(5);
@@ -91,7 +138,7 @@ fn stack_pop_works() {
         handles.push(thread::spawn(move || {
             barrier.wait();
             while let Some(item) = stack.pop() {
-                res = res + item;
+                res += item;
             }
             results1.fetch_add(res, Ordering::Relaxed);
         }));
@@ -99,13 +146,3309 @@ fn stack_pop_works() {
     for handle in handles {
         handle.join().unwrap();
     }
-    assert_eq!(55 as usize, results.load(Ordering::SeqCst));
+    assert_eq!(55_usize, results.load(Ordering::SeqCst));
 }
 
 #[test]
 fn reclaim_works() {
     let arcus = Arc::new(1);
     let stack = Stackus::new(arcus.clone());
-    while let Some(_) = stack.pop() {}
+    while stack.pop().is_some() {}
     assert_eq!(Arc::strong_count(&arcus), 1);
 }
+
+#[test]
+fn broadcast_every_subscriber_gets_every_value() {
+    let bus = Broadcastus::new(16);
+    let mut handles = Vec::new();
+    for _ in 0..3 {
+        let mut subscriber = bus.subscribe();
+        handles.push(thread::spawn(move || {
+            let mut sum = 0;
+            for _ in 0..10 {
+                sum += subscriber.recv().unwrap();
+            }
+            sum
+        }));
+    }
+    for i in 1..=10 {
+        bus.push(i);
+    }
+    for handle in handles {
+        assert_eq!(handle.join().unwrap(), 55);
+    }
+}
+
+#[test]
+fn broadcast_lagging_subscriber_skips_oldest() {
+    let bus = Broadcastus::new(2);
+    let mut subscriber = bus.subscribe();
+    for i in 1..=5 {
+        bus.push(i);
+    }
+    assert_eq!(subscriber.try_recv(), Err(Lagged(3)));
+    assert_eq!(subscriber.try_recv(), Ok(Some(4)));
+    assert_eq!(subscriber.recv(), Ok(5));
+    assert_eq!(subscriber.try_recv(), Ok(None));
+}
+
+#[test]
+fn watch_wakes_on_publish() {
+    let config = Watch::new(0);
+    let mut watcher = config.watcher();
+    assert!(!watcher.has_changed());
+    let waiter = thread::spawn(move || watcher.wait_for_change());
+    config.publish(1);
+    assert_eq!(waiter.join().unwrap(), 1);
+    assert_eq!(config.get(), 1);
+    assert_eq!(config.version(), 1);
+}
+
+#[test]
+fn event_releases_all_waiters() {
+    let event = Arc::new(Event::new());
+    let released = Arc::new(AtomicUsize::new(0));
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let event = event.clone();
+        let released = released.clone();
+        handles.push(thread::spawn(move || {
+            event.wait();
+            released.fetch_add(1, Ordering::SeqCst);
+        }));
+    }
+    assert!(!event.wait_timeout(Duration::from_millis(10)));
+    assert_eq!(released.load(Ordering::SeqCst), 0);
+    event.set();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(released.load(Ordering::SeqCst), 4);
+    event.reset();
+    assert!(!event.is_set());
+}
+
+#[test]
+fn keyed_mutex_serializes_same_key() {
+    let locks = Arc::new(KeyedMutex::<&str>::with_stripes(8));
+    let balance = Arc::new(AtomicUsize::new(0));
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let locks = locks.clone();
+        let balance = balance.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..100 {
+                let _guard = locks.lock(&"account");
+                // non atomic read-modify-write is only correct under the key lock
+                let current = balance.load(Ordering::SeqCst);
+                thread::yield_now();
+                balance.store(current + 1, Ordering::SeqCst);
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(balance.load(Ordering::SeqCst), 400);
+    let guard = locks.lock(&"account");
+    assert!(locks.try_lock(&"account").is_none());
+    drop(guard);
+    assert!(locks.try_lock(&"account").is_some());
+}
+
+#[test]
+fn reclaim_now_frees_pending_nodes() {
+    let stack = Arc::new(Stackus::new(0));
+    for i in 1..1000 {
+        stack.push(i);
+    }
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let stack = stack.clone();
+        handles.push(thread::spawn(move || while stack.pop().is_some() {}));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let pending = stack.pending_retired();
+    assert_eq!(stack.reclaim_now(), pending);
+    assert_eq!(stack.pending_retired(), 0);
+}
+
+#[test]
+fn pop_all_drains_in_lifo_order() {
+    let stack = Arc::new(Stackus::new(0));
+    let mut handles = Vec::new();
+    for t in 0..4 {
+        let stack = stack.clone();
+        handles.push(thread::spawn(move || {
+            for i in 1..=100 {
+                stack.push(t * 100 + i);
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let drained = stack.pop_all();
+    assert_eq!(drained.len(), 401);
+    assert_eq!(drained.sum::<i32>(), (1..=400).sum::<i32>());
+    assert!(stack.is_empty());
+    stack.push(1);
+    stack.push(2);
+    assert_eq!(stack.pop_all().collect::<Vec<_>>(), vec![2, 1]);
+}
+
+#[test]
+fn bounded_queue_deadlines() {
+    let q = Boundq::new(2);
+    q.push(1);
+    assert_eq!(q.try_push(2), Ok(()));
+    let deadline = Instant::now() + Duration::from_millis(20);
+    assert_eq!(q.push_deadline(3, deadline), Err(3));
+    assert!(Instant::now() >= deadline);
+
+    let consumer = q.clone();
+    let handle = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        consumer.pop()
+    });
+    let deadline = Instant::now() + Duration::from_secs(5);
+    assert_eq!(q.push_deadline(3, deadline), Ok(()));
+    assert_eq!(handle.join().unwrap(), 1);
+
+    assert_eq!(q.pop(), 2);
+    assert_eq!(q.pop_deadline(Instant::now()), Some(3));
+    let deadline = Instant::now() + Duration::from_millis(10);
+    assert_eq!(q.pop_deadline(deadline), None);
+    assert!(q.is_empty());
+}
+
+#[test]
+fn byte_budget_push_all_heavier_than_the_budget() {
+    let q = Multiq::with_byte_budget(String::new(), 10, String::len);
+    assert_eq!(q.pop(), Some(String::new()));
+    let (sender, receiver) = mpsc::channel();
+    let producer = q.clone();
+    thread::spawn(move || {
+        producer.push_all(["aaaaa", "bbbbb", "ccccc"].map(String::from));
+        sender.send(()).unwrap();
+    });
+    // the first two fit, the third goes in once one of them is popped
+    assert_eq!(q.wait_and_pop().unwrap(), "aaaaa");
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(q.drain().collect::<Vec<_>>(), ["bbbbb", "ccccc"]);
+}
+
+#[test]
+fn byte_budget_smaller_than_a_producer_batch() {
+    let q = Multiq::with_byte_budget(String::new(), 4, String::len);
+    assert_eq!(q.pop(), Some(String::new()));
+    let consumer = {
+        let q = q.clone();
+        thread::spawn(move || {
+            (0..14)
+                .map(|_| q.wait_and_pop().unwrap())
+                .collect::<Vec<_>>()
+        })
+    };
+    let (sender, receiver) = mpsc::channel();
+    let producing = q.clone();
+    thread::spawn(move || {
+        let mut producer = producing.producer(8);
+        // the eighth value flushes a batch twice the budget, the last six flush on drop
+        (0..14).for_each(|value| producer.push(value.to_string()));
+        drop(producer);
+        sender.send(()).unwrap();
+    });
+    receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    let expected: Vec<_> = (0..14).map(|value| value.to_string()).collect();
+    assert_eq!(consumer.join().unwrap(), expected);
+}
+
+#[test]
+fn byte_budget_blocks_push_until_pop() {
+    let q = Multiq::with_byte_budget(String::from("abcd"), 8, String::len);
+    q.push(String::from("efgh"));
+    let budget = &q.queue.budget.as_ref().unwrap().semaphore;
+    assert_eq!(budget.available_permits(), 0);
+    let producer = q.clone();
+    let handle = thread::spawn(move || producer.push(String::from("ij")));
+    thread::sleep(Duration::from_millis(10));
+    assert_eq!(q.pop(), Some(String::from("abcd")));
+    handle.join().unwrap();
+    assert_eq!(
+        q.queue
+            .budget
+            .as_ref()
+            .unwrap()
+            .semaphore
+            .available_permits(),
+        2
+    );
+}
+
+#[test]
+fn bitset_hands_out_unique_slots() {
+    let slots = Arc::new(Bitus::new(100));
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let slots = slots.clone();
+        handles.push(thread::spawn(move || {
+            (0..25)
+                .map(|_| slots.acquire_first_zero().unwrap())
+                .collect::<Vec<_>>()
+        }));
+    }
+    let mut taken = Vec::new();
+    for handle in handles {
+        taken.extend(handle.join().unwrap());
+    }
+    taken.sort();
+    assert_eq!(taken, (0..100).collect::<Vec<_>>());
+    assert_eq!(slots.acquire_first_zero(), None);
+    assert!(slots.clear(70));
+    assert!(!slots.test(70));
+    assert_eq!(slots.find_first_zero(), Some(70));
+    assert!(!slots.set(70));
+    slots.clear(3);
+    slots.clear(64);
+    assert_eq!(
+        slots.iter().take(5).collect::<Vec<_>>(),
+        vec![0, 1, 2, 4, 5]
+    );
+    assert_eq!(slots.count_ones(), 98);
+}
+
+#[test]
+fn slab_rejects_stale_keys() {
+    let slab = Arc::new(Slabus::with_capacity(64));
+    let mut handles = Vec::new();
+    for t in 0..4 {
+        let slab = slab.clone();
+        handles.push(thread::spawn(move || {
+            for i in 0..1000 {
+                let key = slab.insert(t * 1000 + i).unwrap();
+                assert_eq!(slab.get(key), Some(t * 1000 + i));
+                assert_eq!(slab.remove(key), Some(t * 1000 + i));
+                assert_eq!(slab.get(key), None);
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert!(slab.is_empty());
+
+    let slab = Slabus::with_capacity(1);
+    let first = slab.insert("first").unwrap();
+    assert_eq!(slab.insert("full"), Err("full"));
+    slab.remove(first);
+    let second = slab.insert("second").unwrap();
+    assert_eq!(first.index, second.index);
+    assert!(!slab.contains(first));
+    assert_eq!(slab.with(second, |value| value.len()), Some(6));
+}
+
+#[test]
+fn seqlock_reads_are_never_torn() {
+    let stats = Arc::new(SeqLock::new((0_u64, 0_u64)));
+    let writer = {
+        let stats = stats.clone();
+        thread::spawn(move || {
+            for i in 1..=10_000 {
+                stats.write((i, i * 2));
+            }
+        })
+    };
+    let mut readers = Vec::new();
+    for _ in 0..3 {
+        let stats = stats.clone();
+        readers.push(thread::spawn(move || {
+            for _ in 0..10_000 {
+                let (pushed, bytes) = stats.read();
+                assert_eq!(bytes, pushed * 2);
+            }
+        }));
+    }
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    stats.update(|(pushed, _)| *pushed += 1);
+    assert_eq!(stats.read(), (10_001, 20_000));
+    assert_eq!(stats.version(), 10_001);
+}
+
+#[test]
+fn left_right_readers_see_whole_writes() {
+    let routes = Arc::new(LeftRight::new(vec![0; 8]));
+    let writer = {
+        let routes = routes.clone();
+        thread::spawn(move || {
+            for i in 1..=1000 {
+                routes.write(|table| table.iter_mut().for_each(|route| *route = i));
+            }
+        })
+    };
+    let mut readers = Vec::new();
+    for _ in 0..3 {
+        let routes = routes.clone();
+        readers.push(thread::spawn(move || {
+            for _ in 0..1000 {
+                routes.read(|table| assert!(table.iter().all(|route| *route == table[0])));
+            }
+        }));
+    }
+    writer.join().unwrap();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+    assert_eq!(routes.read(|table| table.clone()), vec![1000; 8]);
+}
+
+#[test]
+fn rcu_readers_keep_their_snapshot() {
+    let table = Arc::new(Rcu::new(vec![1, 2, 3]));
+    let snapshot = table.read();
+    table.update(|old| old.iter().map(|route| route * 10).collect());
+    assert_eq!(*snapshot, vec![1, 2, 3]);
+    assert_eq!(*table.read(), vec![10, 20, 30]);
+    assert_eq!(table.pending_retired(), 1);
+    drop(snapshot);
+    assert_eq!(table.pending_retired(), 0);
+
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let table = table.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..100 {
+                table.update(|old| old.iter().map(|route| route + 1).collect());
+                let current = table.read();
+                assert!(current.windows(2).all(|pair| pair[1] - pair[0] == 10));
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(*table.read(), vec![410, 420, 430]);
+}
+
+#[test]
+fn rcu_frees_replaced_values_under_continuous_readers() {
+    let rcu = Rcu::new(0);
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                // the next guard is taken before the last one goes, so a reader is always in
+                let mut guard = rcu.read();
+                while !stop.load(Ordering::Relaxed) {
+                    let next = rcu.read();
+                    assert!(*next >= *guard);
+                    guard = next;
+                }
+            });
+        }
+        for value in 1..=1000 {
+            rcu.store(value);
+        }
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut value = 1000;
+        while rcu.pending_retired() > 2 {
+            assert!(
+                Instant::now() < deadline,
+                "replaced values were never freed"
+            );
+            value += 1;
+            rcu.store(value);
+            thread::yield_now();
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+    rcu.store(0);
+    assert_eq!(rcu.pending_retired(), 0);
+}
+
+#[test]
+fn registry_tracks_live_and_pinned_threads() {
+    let registry = Registry::new();
+    let me = registry.register();
+    assert!(Arc::ptr_eq(&me, &registry.register()));
+    let guard = me.pin();
+    assert!(!registry.is_quiescent());
+    drop(guard);
+    assert!(registry.is_quiescent());
+    assert!(registry.unregister(me.thread));
+    assert!(registry.is_empty());
+
+    let worker = thread::Builder::new()
+        .name(String::from("registered"))
+        .spawn(|| {
+            let participant = registry::current();
+            assert_eq!(participant.name.as_deref(), Some("registered"));
+            assert!(Registry::global()
+                .live_threads()
+                .iter()
+                .any(|p| p.thread == participant.thread));
+            participant.thread
+        })
+        .unwrap();
+    let id = worker.join().unwrap();
+    assert!(Registry::global()
+        .live_threads()
+        .iter()
+        .all(|p| p.thread != id));
+}
+
+#[test]
+fn parker_keeps_early_token_and_times_out() {
+    let parker = Parker::new();
+    let unparker = parker.unparker();
+    unparker.unpark();
+    // token from before the park is not lost
+    assert!(parker.park_timeout(Duration::from_secs(5)));
+    assert!(!parker.park_timeout(Duration::from_millis(10)));
+    let handle = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        unparker.unpark();
+    });
+    parker.park();
+    handle.join().unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn futex_mutex_and_condvar() {
+    use crate::raw_mutex::{Condvar, Mutex};
+    let state = Arc::new((Mutex::new(0), Condvar::new()));
+    let mut handles = Vec::new();
+    for _ in 0..4 {
+        let state = state.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..1000 {
+                *state.0.lock().unwrap() += 1;
+            }
+            state.1.notify_all();
+        }));
+    }
+    let mut count = state.0.lock().unwrap();
+    while *count < 4000 {
+        count = state.1.wait(count).unwrap();
+    }
+    drop(count);
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let (count, timed_out) = state
+        .1
+        .wait_timeout(state.0.lock().unwrap(), Duration::from_millis(10))
+        .unwrap();
+    assert!(timed_out);
+    assert_eq!(*count, 4000);
+}
+
+#[test]
+fn queue_over_ticket_lock() {
+    let q = Multiq::<usize, TicketLock>::with_lock(0);
+    let mut handles = Vec::new();
+    for t in 0..4 {
+        let q = q.clone();
+        handles.push(thread::spawn(move || {
+            for i in 1..=100 {
+                q.push(t * 100 + i);
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let q = q.clone();
+    let mut sum = 0;
+    while let Some(value) = q.pop() {
+        sum += value;
+    }
+    assert_eq!(sum, (1..=400).sum::<usize>());
+    assert_eq!(q.queue.head.raw.queue_len(), 0);
+}
+
+#[test]
+fn mcs_lock_excludes_and_is_reusable() {
+    let counter = Arc::new(Lock::<usize, McsLock>::new(0));
+    let mut handles = Vec::new();
+    for _ in 0..8 {
+        let counter = counter.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..1000 {
+                *counter.lock().unwrap() += 1;
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let guard = counter.lock().unwrap();
+    assert_eq!(*guard, 8000);
+    assert!(counter.try_lock().is_err());
+    drop(guard);
+    assert!(!counter.raw.is_locked());
+    let q = Multiq::<i32, McsLock>::with_lock(1);
+    q.push(2);
+    assert_eq!(q.pop(), Some(1));
+}
+
+#[test]
+fn default_lock_excludes_across_threads() {
+    // the platform lock with the futex or os-lock features, std's Mutex otherwise
+    let counter = Arc::new(Lock::<usize, DefaultLock>::new(0));
+    let mut handles = Vec::new();
+    for _ in 0..8 {
+        let counter = counter.clone();
+        handles.push(thread::spawn(move || {
+            for _ in 0..1000 {
+                *counter.lock().unwrap() += 1;
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let guard = counter.lock().unwrap();
+    assert_eq!(*guard, 8000);
+    assert!(counter.try_lock().is_err());
+    drop(guard);
+    let q = Multiq::<i32, DefaultLock>::with_lock(1);
+    q.push(2);
+    assert_eq!(q.pop(), Some(1));
+}
+
+#[test]
+fn reentrant_mutex_relocks_on_same_thread() {
+    let log = Arc::new(ReentrantMutex::<std::cell::RefCell<Vec<i32>>>::new(
+        Default::default(),
+    ));
+    let outer = log.lock();
+    outer.borrow_mut().push(1);
+    // a callback running under the outer guard can take the lock again
+    let callback = || log.lock().borrow_mut().push(2);
+    callback();
+    assert!(log.is_owned_by_current_thread());
+    let other = {
+        let log = log.clone();
+        thread::spawn(move || {
+            assert!(log.try_lock().is_none());
+            log.lock().borrow_mut().push(3);
+        })
+    };
+    thread::sleep(Duration::from_millis(10));
+    drop(outer);
+    other.join().unwrap();
+    assert_eq!(*log.lock().borrow(), vec![1, 2, 3]);
+}
+
+#[test]
+fn reentrant_mutex_owner_does_not_pass_to_a_later_thread() {
+    let mutex = Arc::new(ReentrantMutex::<i32>::new(0));
+    {
+        let mutex = mutex.clone();
+        // exits while owning the mutex
+        thread::spawn(move || std::mem::forget(mutex.lock()))
+            .join()
+            .unwrap();
+    }
+    // later threads may get the thread-local storage of the exited one
+    for _ in 0..8 {
+        let mutex = mutex.clone();
+        thread::spawn(move || {
+            assert!(!mutex.is_owned_by_current_thread());
+            assert!(mutex.try_lock().is_none());
+        })
+        .join()
+        .unwrap();
+    }
+}
+
+#[cfg(feature = "debug-locks")]
+#[test]
+#[should_panic(expected = "lock order cycle")]
+fn lock_order_cycle_detected() {
+    let a: Lock<i32> = Lock::new(0);
+    let b: Lock<i32> = Lock::new(0);
+    {
+        let _a = a.lock().unwrap();
+        let _b = b.lock().unwrap();
+    }
+    // no second thread needed, the reversed order alone can deadlock
+    let _b = b.lock().unwrap();
+    let _a = a.lock().unwrap();
+}
+
+#[test]
+fn queue_poison_policy() {
+    let poison = |q: &Multiq<i32>| {
+        let q = q.clone();
+        thread::spawn(move || {
+            let _tail = q.queue.tail.lock();
+            panic!("producer failed");
+        })
+        .join()
+        .unwrap_err();
+    };
+    let ignoring = Multiq::new(1);
+    poison(&ignoring);
+    assert!(ignoring.is_poisoned());
+    ignoring.push(2);
+    assert_eq!(ignoring.try_pop(), Ok(Some(1)));
+    assert_eq!(ignoring.wait_and_pop().unwrap(), 2);
+
+    let propagating = Multiq::with_poison_policy(1, PoisonPolicy::Propagate);
+    poison(&propagating);
+    assert_eq!(propagating.try_push(2), Err(QueuePoisoned));
+    assert_eq!(propagating.try_is_empty(), Err(QueuePoisoned));
+    // taking the last head value has to look at the poisoned tail
+    assert_eq!(propagating.try_pop(), Err(QueuePoisoned));
+
+    // the last handle moves the values out of the poisoned locks as well
+    let last = Multiq::new(1);
+    poison(&last);
+    last.push(2);
+    assert_eq!(last.into_vec(), [1, 2]);
+}
+
+#[test]
+fn pool_survives_panicking_jobs() {
+    for (policy, same_worker) in [(PanicPolicy::Ignore, true), (PanicPolicy::Restart, false)] {
+        let pool = ThreadPool::with_panic_policy(1, policy);
+        let reported = Arc::new(AtomicUsize::new(0));
+        let handler_reported = reported.clone();
+        pool.set_panic_handler(move |payload| {
+            assert_eq!(payload.downcast_ref::<&str>(), Some(&"job failed"));
+            handler_reported.fetch_add(1, Ordering::SeqCst);
+        });
+        let ids = Arc::new(std::sync::Mutex::new(Vec::new()));
+        for fail in [false, true, false] {
+            let ids = ids.clone();
+            pool.execute(move || {
+                ids.lock().unwrap().push(thread::current().id());
+                assert!(!fail, "job failed");
+            });
+        }
+        let panicked = pool.pool.clone();
+        pool.join();
+        assert_eq!(panicked.panicked.load(Ordering::SeqCst), 1);
+        assert_eq!(reported.load(Ordering::SeqCst), 1);
+        let ids = ids.lock().unwrap();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0], ids[1]);
+        assert_eq!(ids[1] == ids[2], same_worker);
+    }
+}
+
+#[test]
+fn pool_submit_results() {
+    let pool = ThreadPool::new(2);
+    let started = Arc::new(Event::new());
+    let release = Arc::new(Event::new());
+    let mut slow = {
+        let (started, release) = (started.clone(), release.clone());
+        pool.submit(move || {
+            started.set();
+            release.wait();
+            6 * 7
+        })
+    };
+    started.wait();
+    assert_eq!(slow.try_get(), None);
+    assert_eq!(slow.wait_timeout(Duration::from_millis(10)), None);
+    release.set();
+    assert_eq!(slow.wait_timeout(Duration::from_secs(5)), Some(Ok(42)));
+    assert_eq!(slow.try_get(), Some(Err(JobError::Taken)));
+
+    let failing = pool.submit(|| -> i32 { panic!("job failed") });
+    assert_eq!(failing.wait(), Err(JobError::Panicked));
+    assert_eq!(pool.submit(|| "done").wait(), Ok("done"));
+}
+
+#[test]
+fn par_consume_visits_every_item() {
+    let sum = AtomicUsize::new(0);
+    let workers = std::sync::Mutex::new(std::collections::HashSet::new());
+    (1..=1000).into_par_consume(4, |item| {
+        sum.fetch_add(item, Ordering::SeqCst);
+        workers.lock().unwrap().insert(thread::current().id());
+    });
+    assert_eq!(sum.load(Ordering::SeqCst), 500500);
+    assert!(!workers.lock().unwrap().contains(&thread::current().id()));
+
+    let result = std::panic::catch_unwind(|| {
+        (0..100).into_par_consume(2, |item| assert!(item != 50, "bad item"));
+    });
+    assert!(result.is_err());
+}
+
+#[test]
+fn parallel_algorithms() {
+    let pool = ThreadPool::new(4);
+    let values: Vec<u64> = (1..=10_000).collect();
+    assert_eq!(
+        parallel::parallel_accumulate(&pool, values.clone(), 0, |a, b| a + b),
+        50_005_000
+    );
+    assert_eq!(
+        parallel::parallel_accumulate(&pool, vec![], 7, |a, b| a + b),
+        7
+    );
+
+    let seen = Arc::new(AtomicUsize::new(0));
+    let counter = seen.clone();
+    parallel::parallel_for_each(&pool, values.clone(), move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+    });
+    assert_eq!(seen.load(Ordering::SeqCst), 10_000);
+
+    // a cheap generator so the input is shuffled and contains duplicates
+    let shuffled: Vec<u64> = (0..10_000u64).map(|i| i * 7919 % 1000).collect();
+    let mut expected = shuffled.clone();
+    expected.sort();
+    assert_eq!(parallel::parallel_quick_sort(&pool, shuffled), expected);
+    assert_eq!(parallel::parallel_quick_sort(&pool, values.clone()), values);
+}
+
+#[test]
+fn cancellation_token() {
+    let parent = CancellationToken::new();
+    let child = parent.child_token();
+    let grandchild = child.child_token();
+    let sibling = parent.child_token();
+    child.cancel();
+    assert!(child.is_cancelled() && grandchild.is_cancelled());
+    assert!(!parent.is_cancelled() && !sibling.is_cancelled());
+
+    let q = Multiq::new(1);
+    assert_eq!(q.wait_and_pop_cancellable(&sibling), Ok(1));
+    let semaphore = Arc::new(crate::semaphore::Semaphore::new(1));
+    semaphore.acquire(1);
+    let waiters = {
+        let (q, semaphore, token) = (q.clone(), semaphore.clone(), sibling.clone());
+        thread::spawn(move || {
+            let popped = q.wait_and_pop_cancellable(&token);
+            (popped, semaphore.acquire_cancellable(1, &token))
+        })
+    };
+    thread::sleep(Duration::from_millis(20));
+    parent.cancel();
+    assert_eq!(waiters.join().unwrap(), (Err(Cancelled), Err(Cancelled)));
+    assert_eq!(semaphore.available_permits(), 0);
+
+    let pool = ThreadPool::new(1);
+    let (started, release) = (Arc::new(Event::new()), Arc::new(Event::new()));
+    let blocker = {
+        let (started, release) = (started.clone(), release.clone());
+        pool.submit(move || {
+            started.set();
+            release.wait();
+        })
+    };
+    let dropped = pool.submit(|| 1);
+    started.wait();
+    let token = CancellationToken::new();
+    token.cancel();
+    let releaser = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        release.set();
+    });
+    assert_eq!(pool.join_cancellable(&token), Err(Cancelled));
+    releaser.join().unwrap();
+    assert_eq!(blocker.wait(), Ok(()));
+    assert_eq!(dropped.wait(), Err(JobError::Abandoned));
+}
+
+#[test]
+fn stack_snapshot_blocks_pops_only() {
+    let stack = Arc::new(Stackus::new(0));
+    for value in 1..10 {
+        stack.push(value);
+    }
+    let snapshot = stack.snapshot();
+    let popper = {
+        let stack = stack.clone();
+        thread::spawn(move || stack.pop())
+    };
+    stack.push(10);
+    thread::sleep(Duration::from_millis(20));
+    let values: Vec<i32> = snapshot.iter().copied().collect();
+    assert_eq!(values, (0..10).rev().collect::<Vec<_>>());
+    assert_eq!((&snapshot).into_iter().sum::<i32>(), 45);
+    drop(snapshot);
+    assert_eq!(popper.join().unwrap(), Some(10));
+    assert_eq!(stack.pop_all().count(), 10);
+}
+
+#[test]
+fn stack_try_push() {
+    let stack = Stackus::new(String::from("a"));
+    assert_eq!(stack.try_push(String::from("b")), Ok(()));
+    assert_eq!(stack.pop().as_deref(), Some("b"));
+    let error = PushError(String::from("c"));
+    assert_eq!(
+        error.to_string(),
+        "out of memory while pushing onto the stack"
+    );
+    assert_eq!(error.0, "c");
+}
+
+#[test]
+fn deque_both_ends() {
+    let deque = Dequeus::new();
+    let pushers: Vec<_> = (0..4)
+        .map(|i| {
+            let deque = deque.clone();
+            thread::spawn(move || {
+                for value in 0..100 {
+                    if i % 2 == 0 {
+                        deque.push_front(value);
+                    } else {
+                        deque.push_back(value);
+                    }
+                }
+            })
+        })
+        .collect();
+    for pusher in pushers {
+        pusher.join().unwrap();
+    }
+    assert_eq!(deque.len(), 400);
+    let poppers: Vec<_> = (0..4)
+        .map(|i| {
+            let deque = deque.clone();
+            thread::spawn(move || {
+                let pop = || {
+                    if i % 2 == 0 {
+                        deque.pop_front()
+                    } else {
+                        deque.pop_back()
+                    }
+                };
+                std::iter::from_fn(pop).count()
+            })
+        })
+        .collect();
+    let popped: usize = poppers.into_iter().map(|p| p.join().unwrap()).sum();
+    assert_eq!(popped, 400);
+    assert!(deque.is_empty());
+    deque.push_back(1);
+    deque.push_front(0);
+    deque.push_back(2);
+    assert_eq!(deque.pop_back(), Some(2));
+    assert_eq!(deque.pop_front(), Some(0));
+}
+
+#[test]
+fn grouped_queue_serializes_keys() {
+    let queue = Arc::new(GroupedQueue::new());
+    for value in 0..100 {
+        queue.push(value % 4, value);
+    }
+    let active: Arc<Vec<AtomicUsize>> = Arc::new((0..4).map(|_| AtomicUsize::new(0)).collect());
+    let seen = Arc::new(std::sync::Mutex::new(vec![Vec::new(); 4]));
+    let consumers: Vec<_> = (0..4)
+        .map(|_| {
+            let (queue, active, seen) = (queue.clone(), active.clone(), seen.clone());
+            thread::spawn(move || {
+                while let Some(claim) = queue.try_pop() {
+                    assert_eq!(active[claim.key].fetch_add(1, Ordering::SeqCst), 0);
+                    seen.lock().unwrap()[claim.key].push(*claim);
+                    thread::yield_now();
+                    active[claim.key].fetch_sub(1, Ordering::SeqCst);
+                }
+            })
+        })
+        .collect();
+    for consumer in consumers {
+        consumer.join().unwrap();
+    }
+    assert!(queue.is_empty());
+    for (key, values) in seen.lock().unwrap().iter().enumerate() {
+        assert_eq!(values, &(key..100).step_by(4).collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn sequencer_restores_order() {
+    let jobs = Multiq::new((0u64, 0u64));
+    for sequence in 1..100 {
+        jobs.push((sequence, sequence));
+    }
+    let sequencer = Arc::new(Sequencer::new(0));
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let (jobs, sequencer) = (jobs.clone(), sequencer.clone());
+            thread::spawn(move || {
+                while let Some((sequence, value)) = jobs.pop() {
+                    if value % 7 == 0 {
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                    sequencer.complete(sequence, value * 2);
+                }
+            })
+        })
+        .collect();
+    let received: Vec<u64> = (0..100).map(|_| sequencer.recv()).collect();
+    assert_eq!(
+        received,
+        (0..100).map(|value| value * 2).collect::<Vec<_>>()
+    );
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    sequencer.complete(101, 0);
+    assert_eq!(sequencer.try_recv(), None);
+    sequencer.complete(100, 0);
+    assert_eq!(sequencer.drain_ready().len(), 2);
+    assert_eq!(sequencer.next_sequence(), 102);
+}
+
+#[test]
+fn rate_limiter_refills() {
+    let limiter = RateLimiter::with_interval(Duration::from_millis(10), 5);
+    assert_eq!(limiter.available(), 5);
+    assert!(limiter.try_acquire(3));
+    assert!(limiter.try_acquire(2));
+    assert!(!limiter.try_acquire(1));
+    let start = Instant::now();
+    limiter.acquire(2);
+    assert!(start.elapsed() >= Duration::from_millis(15));
+    thread::sleep(Duration::from_millis(60));
+    assert_eq!(limiter.available(), 5);
+}
+
+#[test]
+fn queue_push_all_wakes_waiters() {
+    let q = Multiq::new(0);
+    assert_eq!(q.pop(), Some(0));
+    let consumer = {
+        let q = q.clone();
+        thread::spawn(move || q.wait_and_pop().unwrap())
+    };
+    while q.queue.waiting.load(Ordering::SeqCst) != 1 {
+        thread::yield_now();
+    }
+    q.push_all([1]);
+    assert_eq!(consumer.join().unwrap(), 1);
+    assert_eq!(q.queue.waiting.load(Ordering::SeqCst), 0);
+    q.push_all([2, 3, 4]);
+    q.push_all([]);
+    assert_eq!((q.pop(), q.pop(), q.pop()), (Some(2), Some(3), Some(4)));
+    assert_eq!(q.pop(), None);
+}
+
+#[test]
+fn queue_load_stats() {
+    let q = Multiq::new(0);
+    for value in 1..10 {
+        q.push(value);
+    }
+    let stats = q.load_stats();
+    assert_eq!(stats.depth, 10);
+    assert!(stats.average_depth > 1.0 && stats.average_depth < 10.0);
+    while q.pop().is_some() {}
+    assert_eq!(q.load_stats().depth, 0);
+    assert!(q.load_stats().average_depth < stats.average_depth);
+
+    let consumer = q.clone();
+    let waiter = thread::spawn(move || consumer.wait_and_pop().unwrap());
+    thread::sleep(Duration::from_millis(30));
+    q.push(1);
+    waiter.join().unwrap();
+    // one sample moves the average a tenth of the way
+    assert!(q.load_stats().average_wait >= Duration::from_millis(2));
+}
+
+#[test]
+fn stack_pop_raw_defers_reclamation() {
+    let tracked = Arc::new(());
+    let stack = Stackus::new(tracked.clone());
+    stack.push(tracked.clone());
+    let node = stack.pop_raw().unwrap();
+    assert_eq!(Arc::strong_count(&node), 3);
+    assert_eq!(stack.pending_retired(), 0);
+    let value = node.into_value();
+    assert_eq!(stack.pending_retired(), 1);
+    drop(value);
+    stack.pop_raw().unwrap().retire();
+    assert_eq!(Arc::strong_count(&tracked), 1);
+    assert!(stack.pop_raw().is_none());
+    assert_eq!(stack.reclaim_now(), 2);
+}
+
+#[test]
+fn inline_stack_spills_past_capacity() {
+    let stack: Arc<InlineStackus<usize, 8>> = Arc::new(InlineStackus::new());
+    let pushers: Vec<_> = (0..4)
+        .map(|i| {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                for value in 0..1000 {
+                    stack.push(value);
+                    if value % 2 == i % 2 {
+                        stack.pop();
+                    }
+                }
+            })
+        })
+        .collect();
+    for pusher in pushers {
+        pusher.join().unwrap();
+    }
+    let mut left = 0;
+    while stack.pop().is_some() {
+        left += 1;
+    }
+    assert_eq!(left, 2000);
+    assert!(stack.is_empty() && stack.has_spilled());
+
+    let shallow: InlineStackus<String, 4> = InlineStackus::new();
+    shallow.push("a".to_string());
+    shallow.push("b".to_string());
+    assert_eq!(shallow.pop().as_deref(), Some("b"));
+    assert_eq!(shallow.inline_len(), 1);
+    assert!(!shallow.has_spilled());
+}
+
+#[test]
+fn single_producer_stack() {
+    let (pusher, stack) = SpStackus::new();
+    let total = Arc::new(AtomicUsize::new(0));
+    let done = Arc::new(Event::new());
+    let poppers: Vec<_> = (0..3)
+        .map(|_| {
+            let (stack, total, done) = (stack.clone(), total.clone(), done.clone());
+            thread::spawn(move || loop {
+                match stack.pop() {
+                    Some(value) => {
+                        total.fetch_add(value, Ordering::SeqCst);
+                    }
+                    None if done.is_set() => break,
+                    None => thread::yield_now(),
+                }
+            })
+        })
+        .collect();
+    let producer = thread::spawn(move || {
+        for value in 1..=10_000 {
+            pusher.push(value);
+        }
+        pusher
+    });
+    let pusher = producer.join().unwrap();
+    done.set();
+    for popper in poppers {
+        popper.join().unwrap();
+    }
+    assert_eq!(total.load(Ordering::SeqCst), 50_005_000);
+    pusher.push(1);
+    pusher.push(2);
+    assert_eq!(stack.pop(), Some(2));
+    assert!(!stack.is_empty());
+}
+
+#[test]
+fn queue_round_robin_wakeups() {
+    let q = Multiq::new(0);
+    assert_eq!(q.pop(), Some(0));
+    q.set_round_robin(true);
+    let consumers: Vec<_> = (0..3)
+        .map(|_| {
+            let q = q.clone();
+            thread::spawn(move || {
+                let mut popped = 0;
+                while q.wait_and_pop().unwrap() != usize::MAX {
+                    popped += 1;
+                }
+                popped
+            })
+        })
+        .collect();
+    // one consumer waits for a value, the others wait for their turn
+    while q.queue.waiting.load(Ordering::SeqCst) != 1
+        || q.queue.turnstile.state.lock().unwrap().1.len() != 2
+    {
+        thread::yield_now();
+    }
+    for value in (1..=30).chain([usize::MAX; 3]) {
+        q.push(value);
+        thread::sleep(Duration::from_millis(2));
+    }
+    for consumer in consumers {
+        assert!(consumer.join().unwrap() >= 5);
+    }
+}
+
+#[test]
+fn task_queue_runs_closures() {
+    let counter = Arc::new(AtomicUsize::new(0));
+    let tasks = TaskQueue::new();
+    assert!(!tasks.run_one());
+    for _ in 0..3 {
+        let counter = counter.clone();
+        tasks.submit(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    // tasks can submit more tasks through their own handle
+    let nested = tasks.clone();
+    let inner = counter.clone();
+    tasks.submit(move || {
+        nested.submit(move || {
+            inner.fetch_add(10, Ordering::SeqCst);
+        })
+    });
+    assert_eq!(tasks.run_until_empty(), 5);
+    assert_eq!(counter.load(Ordering::SeqCst), 13);
+    assert!(tasks.is_empty());
+
+    let worker = tasks.clone();
+    let handle = thread::spawn(move || worker.wait_and_run_one());
+    let done = counter.clone();
+    tasks.submit(move || {
+        done.fetch_add(1, Ordering::SeqCst);
+    });
+    handle.join().unwrap();
+    assert_eq!(counter.load(Ordering::SeqCst), 14);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn pool_pins_workers() {
+    let pool = ThreadPool::builder().threads(2).pin_threads(true).build();
+    assert_eq!(pool.threads(), 2);
+    let allowed_cpus = pool
+        .submit(|| unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set);
+            libc::CPU_COUNT(&set)
+        })
+        .wait();
+    assert_eq!(allowed_cpus, Ok(1));
+}
+
+#[test]
+fn pool_builder_names_hooks_and_grows() {
+    let started = Arc::new(AtomicUsize::new(0));
+    let stopped = Arc::new(AtomicUsize::new(0));
+    let pool = {
+        let (started, stopped) = (started.clone(), stopped.clone());
+        ThreadPool::builder()
+            .min_threads(1)
+            .max_threads(3)
+            .thread_name("worker")
+            .stack_size(256 * 1024)
+            .on_thread_start(move |_| {
+                registry::current();
+                started.fetch_add(1, Ordering::SeqCst);
+            })
+            .on_thread_stop(move |_| {
+                stopped.fetch_add(1, Ordering::SeqCst);
+            })
+            .build()
+    };
+    assert_eq!(pool.threads(), 1);
+    // three jobs that wait for each other only finish if the pool grows to three workers
+    let barrier = Arc::new(Barrier::new(3));
+    let names: Vec<_> = (0..3)
+        .map(|_| {
+            let barrier = barrier.clone();
+            pool.submit(move || {
+                barrier.wait();
+                thread::current().name().map(String::from)
+            })
+        })
+        .collect();
+    let mut names: Vec<_> = names.into_iter().map(|name| name.wait().unwrap()).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        ["worker-0", "worker-1", "worker-2"].map(|name| Some(String::from(name)))
+    );
+    assert_eq!(pool.threads(), 3);
+    pool.join();
+    assert_eq!(started.load(Ordering::SeqCst), 3);
+    assert_eq!(stopped.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn pool_autoscales_with_load() {
+    let pool = ThreadPool::builder()
+        .min_threads(1)
+        .max_threads(4)
+        .autoscale(Autoscale {
+            keep_alive: Duration::from_millis(20),
+            ..Autoscale::default()
+        })
+        .build();
+    let (sender, receiver) = std::sync::mpsc::channel::<()>();
+    let receiver = Arc::new(std::sync::Mutex::new(receiver));
+    // a single queued job isn't load yet, only the backlog behind it makes the pool grow
+    for _ in 0..20 {
+        let receiver = receiver.clone();
+        pool.execute(move || {
+            let _ = receiver.lock().unwrap().recv();
+        });
+    }
+    assert_eq!(pool.threads(), 4);
+    assert!(pool.load_stats().average_depth >= 1.0);
+    drop(sender);
+    let start = Instant::now();
+    while pool.threads() > 1 {
+        assert!(
+            start.elapsed() < Duration::from_secs(5),
+            "idle workers never retired"
+        );
+        thread::sleep(Duration::from_millis(5));
+    }
+    pool.join();
+}
+
+#[test]
+fn pool_priorities_with_aging() {
+    let run_order = |aging: Duration, wait_between: Duration| {
+        let pool = ThreadPool::builder().threads(1).aging(aging).build();
+        let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (sender, receiver) = std::sync::mpsc::channel::<()>();
+        pool.execute(move || {
+            let _ = receiver.recv();
+        });
+        for priority in [Priority::Low, Priority::Normal, Priority::High] {
+            let order = order.clone();
+            pool.execute_with_priority(move || order.lock().unwrap().push(priority), priority);
+            thread::sleep(wait_between);
+        }
+        drop(sender);
+        pool.join();
+        Arc::try_unwrap(order).unwrap().into_inner().unwrap()
+    };
+    assert_eq!(
+        run_order(Duration::from_secs(60), Duration::ZERO),
+        [Priority::High, Priority::Normal, Priority::Low]
+    );
+    // after waiting two intervals the low priority job counts as high and is older
+    assert_eq!(
+        run_order(Duration::from_millis(5), Duration::from_millis(10)),
+        [Priority::Low, Priority::Normal, Priority::High]
+    );
+}
+
+#[test]
+fn pool_scope_borrows_and_cancels() {
+    let pool = ThreadPool::new(2);
+    let values: Vec<u64> = (1..=100).collect();
+    let total = AtomicUsize::new(0);
+    let returned = pool.scope(|s| {
+        for chunk in values.chunks(10) {
+            let total = &total;
+            s.spawn(move |_| {
+                total.fetch_add(chunk.iter().sum::<u64>() as usize, Ordering::SeqCst);
+            });
+        }
+        "body result"
+    });
+    assert_eq!(returned, "body result");
+    assert_eq!(total.load(Ordering::SeqCst), 5050);
+
+    // the failing task cancels its sibling, which stops waiting for work that never comes
+    let result: Result<(), &str> = pool.try_scope(|s| {
+        s.try_spawn(|token| {
+            while !token.is_cancelled() {
+                thread::sleep(Duration::from_millis(1));
+            }
+            Ok(())
+        });
+        s.try_spawn(|_| Err("failed"));
+    });
+    assert_eq!(result, Err("failed"));
+
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        pool.scope(|s| s.spawn(|_| panic!("task failed")))
+    }));
+    assert_eq!(
+        panicked.unwrap_err().downcast_ref::<&str>(),
+        Some(&"task failed")
+    );
+    assert_eq!(pool.panicked_jobs(), 0);
+}
+
+#[test]
+fn pool_scope_drops_skipped_tasks_before_returning() {
+    struct Flag<'a>(&'a mut bool);
+    impl Drop for Flag<'_> {
+        fn drop(&mut self) {
+            *self.0 = true;
+        }
+    }
+    let pool = ThreadPool::new(1);
+    let mut dropped = false;
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        pool.scope(|s| {
+            s.spawn(|_| panic!("task failed"));
+            let flag = Flag(&mut dropped);
+            // skipped after the cancellation, the worker drops it with the borrow it holds
+            s.spawn(move |_| drop(flag));
+        })
+    }));
+    assert!(panicked.is_err());
+    assert!(dropped);
+}
+
+#[test]
+fn intrusive_stack_links_borrowed_values() {
+    struct Buffer {
+        link: Link<Buffer>,
+        id: usize,
+    }
+    unsafe impl Linked for Buffer {
+        fn link(&self) -> &Link<Buffer> {
+            &self.link
+        }
+    }
+    let buffers: Vec<Buffer> = (0..100)
+        .map(|id| Buffer {
+            link: Link::new(),
+            id,
+        })
+        .collect();
+    let stack = IntrusiveStackus::new();
+    for buffer in &buffers[..3] {
+        stack.push(buffer);
+    }
+    let again = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| stack.push(&buffers[0])));
+    assert!(again.is_err());
+    assert_eq!(stack.pop().map(|b| b.id), Some(2));
+    assert_eq!(stack.pop_all().map(|b| b.id).collect::<Vec<_>>(), [1, 0]);
+    assert!(stack.is_empty() && !buffers[0].link.is_linked());
+
+    // threads keep pushing back whatever buffers they popped
+    thread::scope(|s| {
+        for chunk in buffers.chunks(25) {
+            let stack = &stack;
+            s.spawn(move || {
+                let mut held: Vec<&Buffer> = chunk.iter().collect();
+                for _ in 0..100 {
+                    held.drain(..).for_each(|buffer| stack.push(buffer));
+                    held.extend((0..chunk.len()).map_while(|_| stack.pop()));
+                }
+                held.into_iter().for_each(|buffer| stack.push(buffer));
+            });
+        }
+    });
+    assert_eq!(stack.pop_all().count(), 100);
+    assert!(stack.is_empty());
+    assert!(buffers.iter().all(|buffer| !buffer.link.is_linked()));
+}
+
+// Replays the ABA interleaving on a Stackus: a pop reads the head A, and before its exchange
+// A and the node below it are popped and A's value is pushed again. If the popped nodes went
+// back to the allocator right away the new node could get A's address, the stale exchange
+// would succeed and link the popped node below A back in. The preempted pop is played by hand
+// with the steps of Stackus::pop.
+#[test]
+fn stack_aba_replay_during_pop() {
+    let stack = Stackus::new(1);
+    stack.push(2);
+    stack.push(3);
+    stack.enter_pop();
+    let head = stack.head.load(Ordering::Acquire);
+    let next = unsafe { head.as_ref() }
+        .expect("stack is not empty")
+        .next
+        .load(Ordering::Relaxed);
+    assert_eq!((stack.pop(), stack.pop()), (Some(3), Some(2)));
+    // both nodes wait for the pop in flight instead of being freed
+    assert_eq!(stack.pending_retired(), 2);
+    stack.push(3);
+    // the new node can't have A's address, so the stale exchange fails
+    assert!(stack
+        .head
+        .compare_exchange(head, next, Ordering::Acquire, Ordering::Acquire)
+        .is_err());
+    stack.threads_in_pop.fetch_sub(1, Ordering::Release);
+    assert_eq!(stack.reclaim_now(), 2);
+    assert_eq!(
+        (stack.pop(), stack.pop(), stack.pop()),
+        (Some(3), Some(1), None)
+    );
+    assert_eq!(stack.len(), 0);
+}
+
+#[test]
+fn generic_stacks_and_queues() {
+    fn fill_and_drain_stack<S: ConcurrentStack<i32>>(stack: &S) -> Vec<i32> {
+        (1..=5).for_each(|value| stack.push(value));
+        assert_eq!(stack.len(), 5);
+        std::iter::from_fn(|| stack.try_pop()).collect()
+    }
+    fn fill_and_drain_queue<Q: ConcurrentQueue<i32>>(queue: &Q) -> Vec<i32> {
+        (1..=5).for_each(|value| queue.push(value));
+        assert_eq!(queue.len(), 5);
+        let drained = std::iter::from_fn(|| queue.try_pop()).collect();
+        assert!(queue.is_empty());
+        drained
+    }
+    let stack = Stackus::new(0);
+    assert_eq!(stack.pop(), Some(0));
+    assert_eq!(fill_and_drain_stack(&stack), [5, 4, 3, 2, 1]);
+    assert_eq!(
+        fill_and_drain_stack(&InlineStackus::<i32, 2>::new()),
+        [5, 4, 3, 2, 1]
+    );
+
+    let multiq = Multiq::new(0);
+    assert_eq!(multiq.pop(), Some(0));
+    assert_eq!(fill_and_drain_queue(&multiq), [1, 2, 3, 4, 5]);
+    assert_eq!(fill_and_drain_queue(&Boundq::new(8)), [1, 2, 3, 4, 5]);
+    assert_eq!(fill_and_drain_queue(&Dequeus::new()), [1, 2, 3, 4, 5]);
+}
+
+#[cfg(feature = "chaos")]
+#[test]
+fn queue_survives_injected_yields() {
+    for seed in 0..30 {
+        chaos::set_seed(seed);
+        let q = Multiq::new(0);
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let q = q.clone();
+                thread::spawn(move || {
+                    let mut sum = 0;
+                    loop {
+                        match q.wait_and_pop().unwrap() {
+                            u64::MAX => return sum,
+                            value => sum += value,
+                        }
+                    }
+                })
+            })
+            .collect();
+        let producers: Vec<_> = (0..2)
+            .map(|producer| {
+                let q = q.clone();
+                thread::spawn(move || {
+                    for value in 1..=20 {
+                        if value % 5 == 0 {
+                            q.push_all([value, 100 * producer]);
+                        } else {
+                            q.push(value);
+                        }
+                    }
+                })
+            })
+            .collect();
+        producers.into_iter().for_each(|p| p.join().unwrap());
+        q.push_all([u64::MAX, u64::MAX]);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let total: u64 = consumers.into_iter().map(|c| c.join().unwrap()).sum();
+            sender.send(total).unwrap();
+        });
+        let total = receiver
+            .recv_timeout(Duration::from_secs(10))
+            .unwrap_or_else(|_| panic!("a consumer missed a wakeup with seed {seed}"));
+        assert_eq!(total, 2 * 210 + 4 * 100, "values lost with seed {seed}");
+    }
+}
+
+#[test]
+fn queue_drain_takes_everything_in_order() {
+    let q = Multiq::with_byte_budget(String::from("a"), 4, String::len);
+    q.push_all([String::from("b"), String::from("c")]);
+    q.push(String::from("d"));
+    let drained = q.drain();
+    assert_eq!(drained.len(), 4);
+    assert_eq!(drained.collect::<Vec<_>>(), ["a", "b", "c", "d"]);
+    assert!(q.is_empty());
+    assert_eq!(q.load_stats().depth, 0);
+    assert_eq!(q.drain().next(), None);
+    // the budget was given back, so a full one fits again
+    q.push(String::from("efgh"));
+    assert_eq!(q.pop().as_deref(), Some("efgh"));
+}
+
+#[test]
+fn queue_peek_leaves_the_front_value() {
+    let q = Multiq::new(1);
+    assert_eq!(q.peek(), Some(1));
+    q.push(2);
+    assert_eq!(q.pop(), Some(1));
+    assert_eq!(q.peek(), Some(2));
+    assert_eq!(q.wait_and_peek().unwrap(), 2);
+    assert_eq!(q.pop(), Some(2));
+    assert_eq!(q.peek(), None);
+
+    let producer = {
+        let q = q.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            q.push(3);
+        })
+    };
+    assert_eq!(q.wait_and_peek().unwrap(), 3);
+    producer.join().unwrap();
+    assert_eq!(q.pop(), Some(3));
+    assert!(q.is_empty());
+}
+
+#[test]
+fn queue_push_front_requeues_at_the_head() {
+    let q = Multiq::new(1);
+    q.push_all([2, 3]);
+    let failed = q.pop().unwrap();
+    q.push_front(failed);
+    q.push(4);
+    assert_eq!(q.drain().collect::<Vec<_>>(), [1, 2, 3, 4]);
+
+    // values only in the tail stay behind the requeued one
+    q.push(5);
+    q.push(6);
+    assert_eq!(q.pop(), Some(5));
+    q.push_front(0);
+    assert_eq!(q.drain().collect::<Vec<_>>(), [0, 6]);
+
+    // a consumer blocked on the empty queue gets the requeued value
+    let consumer = {
+        let q = q.clone();
+        thread::spawn(move || q.wait_and_pop().unwrap())
+    };
+    thread::sleep(Duration::from_millis(20));
+    q.push_front(7);
+    assert_eq!(consumer.join().unwrap(), 7);
+    assert!(q.is_empty());
+}
+
+#[test]
+fn queue_counts_waiting_consumers() {
+    let q = Multiq::new(0);
+    assert_eq!(q.pop(), Some(0));
+    assert_eq!(q.waiting_consumers(), 0);
+    let consumers: Vec<_> = (0..2)
+        .map(|_| {
+            let q = q.clone();
+            thread::spawn(move || q.wait_and_pop().unwrap())
+        })
+        .collect();
+    while q.waiting_consumers() < 2 {
+        thread::yield_now();
+    }
+    q.push_all([1, 2]);
+    let mut popped: Vec<_> = consumers.into_iter().map(|c| c.join().unwrap()).collect();
+    popped.sort();
+    assert_eq!(popped, [1, 2]);
+    assert_eq!(q.waiting_consumers(), 0);
+}
+
+#[test]
+fn stack_pop_if_checks_the_top() {
+    let stack = Stackus::new(1);
+    stack.push(10);
+    assert_eq!(stack.pop_if(|deadline| *deadline < 5), None);
+    assert_eq!(stack.len(), 2);
+    assert_eq!(stack.pop_if(|deadline| *deadline >= 5), Some(10));
+    assert_eq!(stack.pop_if(|_| true), Some(1));
+    assert_eq!(stack.pop_if(|_| true), None);
+
+    // concurrent poppers only ever take values that passed while on top
+    let stack = Arc::new(Stackus::new(0));
+    for value in 1..1000 {
+        stack.push(value);
+    }
+    let poppers: Vec<_> = (0..4)
+        .map(|_| {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                let mut taken = Vec::new();
+                while let Some(value) = stack.pop_if(|value| *value >= 500) {
+                    taken.push(value);
+                }
+                taken
+            })
+        })
+        .collect();
+    let mut taken: Vec<_> = poppers
+        .into_iter()
+        .flat_map(|p| p.join().unwrap())
+        .collect();
+    taken.sort();
+    assert_eq!(taken, (500..1000).collect::<Vec<_>>());
+    assert_eq!(stack.pop_all().len(), 500);
+}
+
+#[test]
+fn stack_replace_top_keeps_the_latest() {
+    let stack = Stackus::new(1);
+    assert_eq!(stack.replace_top(2), Some(1));
+    assert_eq!(stack.len(), 1);
+    assert_eq!(stack.pop(), Some(2));
+    assert_eq!(stack.replace_top(3), None);
+    assert_eq!(stack.len(), 1);
+
+    // coalescing updates from several threads leaves exactly one of them on top
+    let stack = Arc::new(stack);
+    let updaters: Vec<_> = (0..4)
+        .map(|thread| {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                for update in 0..500 {
+                    stack.replace_top(thread * 1000 + update);
+                }
+            })
+        })
+        .collect();
+    updaters.into_iter().for_each(|u| u.join().unwrap());
+    assert_eq!(stack.len(), 1);
+    assert_eq!(stack.pop().map(|latest| latest % 1000), Some(499));
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn stack_pop_if_eq_matches_the_observed_top() {
+    let stack = Stackus::new("idle");
+    stack.push("running");
+    assert_eq!(stack.pop_if_eq(&"idle"), None);
+    assert_eq!(stack.pop_if_eq(&"running"), Some("running"));
+    // a second pop of the same observed state finds it gone
+    assert_eq!(stack.pop_if_eq(&"running"), None);
+    assert_eq!(stack.pop_if_eq(&"idle"), Some("idle"));
+    assert!(stack.is_empty());
+
+    // the comparison reads owned values while another thread pops them
+    let states = Stackus::new(String::from("idle"));
+    for _ in 0..20 {
+        states.push(String::from("running"));
+    }
+    let running = String::from("running");
+    let popped = AtomicUsize::new(0);
+    thread::scope(|s| {
+        s.spawn(|| {
+            while states.pop_if_eq(&running).is_some() {
+                popped.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        s.spawn(|| {
+            while states.pop().is_some() {
+                popped.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+    });
+    assert_eq!(popped.into_inner(), 21);
+}
+
+#[test]
+fn bounded_queue_pops_batches() {
+    let q = Boundq::new(4);
+    for value in 1..=3 {
+        q.push(value);
+    }
+    assert_eq!(q.pop_up_to(2), [1, 2]);
+    assert_eq!(q.pop_up_to(8), [3]);
+    assert!(q.pop_up_to(0).is_empty());
+
+    // a batch consumer waits for the first value and frees room for blocked producers
+    let producer = {
+        let q = q.clone();
+        thread::spawn(move || (0..100).for_each(|value| q.push(value)))
+    };
+    let mut received = Vec::new();
+    while received.len() < 100 {
+        let batch = q.pop_up_to(3);
+        assert!(!batch.is_empty() && batch.len() <= 3);
+        received.extend(batch);
+    }
+    producer.join().unwrap();
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn queue_producer_pushes_in_batches() {
+    let q = Multiq::new(0);
+    assert_eq!(q.pop(), Some(0));
+    let mut producer = q.producer(3);
+    producer.push(1);
+    producer.push(2);
+    assert_eq!(producer.buffered(), 2);
+    assert!(q.is_empty());
+    producer.push(3);
+    assert_eq!(producer.buffered(), 0);
+    assert_eq!(q.load_stats().depth, 3);
+    producer.push(4);
+    producer.flush();
+    producer.push(5);
+    drop(producer);
+    assert_eq!(q.drain().collect::<Vec<_>>(), [1, 2, 3, 4, 5]);
+
+    let producers: Vec<_> = (0..4)
+        .map(|thread| {
+            let mut producer = q.producer(16);
+            thread::spawn(move || (0..100).for_each(|value| producer.push(thread * 100 + value)))
+        })
+        .collect();
+    producers.into_iter().for_each(|p| p.join().unwrap());
+    let mut values: Vec<_> = q.drain().collect();
+    values.sort();
+    assert_eq!(values, (0..400).collect::<Vec<_>>());
+}
+
+#[test]
+fn queue_consumer_prefetches_batches() {
+    let q = Multiq::new(1);
+    q.push_all([2, 3, 4, 5]);
+    assert_eq!(q.pop_up_to(2), [1, 2]);
+    q.push(6);
+    let mut consumer = q.consumer(3);
+    assert_eq!(consumer.recv().unwrap(), 3);
+    assert_eq!(consumer.buffered(), 2);
+    assert_eq!(q.load_stats().depth, 1);
+    assert_eq!(consumer.try_recv(), Some(4));
+    q.push(7);
+    // values left in the buffer go back in front of newer ones
+    drop(consumer);
+    assert_eq!(q.drain().collect::<Vec<_>>(), [5, 6, 7]);
+
+    let mut consumer = q.consumer(4);
+    assert_eq!(consumer.try_recv(), None);
+    let producer = {
+        let q = q.clone();
+        thread::spawn(move || {
+            for value in 0..100 {
+                q.push(value);
+            }
+        })
+    };
+    let received: Vec<_> = (0..100).map(|_| consumer.recv().unwrap()).collect();
+    producer.join().unwrap();
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn memory_usage_follows_the_values() {
+    let stack = Stackus::new([0u8; 64]);
+    let one = stack.approx_memory_usage();
+    assert!(one >= 64);
+    stack.push([1; 64]);
+    assert_eq!(stack.approx_memory_usage(), 2 * one);
+    stack.pop_all();
+    assert_eq!(stack.approx_memory_usage(), 0);
+
+    let inline: InlineStackus<[u8; 64], 1> = InlineStackus::new();
+    inline.push([0; 64]);
+    assert_eq!(inline.approx_memory_usage(), 0);
+    inline.push([1; 64]);
+    assert_eq!(inline.approx_memory_usage(), one);
+    while inline.pop().is_some() {}
+
+    let q = Multiq::new([0u8; 64]);
+    let empty = {
+        q.pop();
+        q.approx_memory_usage()
+    };
+    q.push_all([[1; 64], [2; 64]]);
+    assert!(q.approx_memory_usage() >= empty + 2 * 64);
+}
+
+#[test]
+fn bytes_queue_shares_and_reuses_segments() {
+    let q = BytesQueue::new(16);
+    q.push(b"hello");
+    q.push(b"world");
+    q.push(&[7; 40]);
+    q.push(b"");
+    assert_eq!(q.len(), 4);
+    let hello = q.pop().unwrap();
+    let world = q.pop().unwrap();
+    assert_eq!(&*hello, b"hello");
+    assert_eq!(&*world, b"world");
+    // small messages share a segment, large ones get their own
+    assert!(Arc::ptr_eq(&hello.segment, &world.segment));
+    assert_eq!(&*q.pop().unwrap(), &[7; 40]);
+    assert!(q.pop().unwrap().is_empty());
+    assert!(q.pop().is_none());
+
+    // the first segment is back in the pool once its guards are gone
+    q.push(&[1; 16]);
+    drop((hello, world));
+    assert_eq!(q.queue.state.lock().unwrap().spare.len(), 1);
+    q.push(b"again");
+    assert!(q.queue.state.lock().unwrap().spare.is_empty());
+    assert_eq!(&*q.pop().unwrap(), &[1; 16]);
+    assert_eq!(&*q.pop().unwrap(), b"again");
+
+    let consumer = {
+        let q = q.clone();
+        thread::spawn(move || {
+            (0..1000u32)
+                .map(|_| u32::from_le_bytes(q.wait_and_pop()[..].try_into().unwrap()))
+                .collect::<Vec<_>>()
+        })
+    };
+    for value in 0..1000u32 {
+        q.push(&value.to_le_bytes());
+    }
+    assert_eq!(consumer.join().unwrap(), (0..1000).collect::<Vec<_>>());
+}
+
+#[test]
+fn append_only_log_publishes_in_place() {
+    let log = Appendus::new();
+    assert_eq!(log.get(0), None);
+    assert_eq!(log.push(String::from("a")), 0);
+    assert_eq!(log.push(String::from("b")), 1);
+    assert_eq!(log.get(1).map(String::as_str), Some("b"));
+    let first = log.get(0).unwrap();
+    // later pushes allocate new buckets instead of moving published values
+    for value in 2..100 {
+        log.push(value.to_string());
+    }
+    assert_eq!(first, "a");
+    assert_eq!(log.len(), 100);
+    assert_eq!(log.get(100), None);
+
+    let log = Arc::new(Appendus::new());
+    let producers: Vec<_> = (0..4)
+        .map(|thread| {
+            let log = log.clone();
+            thread::spawn(move || {
+                for value in 0..1000 {
+                    log.push(thread * 1000 + value);
+                }
+            })
+        })
+        .collect();
+    // a late reader only ever sees a gap-free prefix
+    let mut seen = 0;
+    while seen < 4000 {
+        let prefix = log.iter().count();
+        assert!(prefix >= seen);
+        seen = prefix;
+    }
+    producers.into_iter().for_each(|p| p.join().unwrap());
+    let mut values: Vec<_> = log.iter().copied().collect();
+    values.sort();
+    assert_eq!(values, (0..4000).collect::<Vec<_>>());
+}
+
+#[test]
+fn map_snapshot_and_retain() {
+    let map = Arc::new(Mapus::with_shards(4));
+    for key in 0..100 {
+        assert_eq!(map.insert(key, key * 10), None);
+    }
+    assert_eq!(map.insert(7, 0), Some(70));
+    assert_eq!(map.get(&7), Some(0));
+    assert_eq!(map.with(&8, |value| value + 1), Some(81));
+    assert_eq!(map.remove(&7), Some(0));
+    assert!(!map.contains_key(&7));
+    assert_eq!(map.len(), 99);
+
+    map.retain(|key, value| {
+        *value += 1;
+        key % 2 == 0
+    });
+    let mut entries: Vec<_> = map.iter_snapshot().collect();
+    entries.sort();
+    assert_eq!(
+        entries,
+        (0..100)
+            .step_by(2)
+            .map(|key| (key, key * 10 + 1))
+            .collect::<Vec<_>>()
+    );
+
+    // keys that stay put are seen exactly once while others come and go
+    let writer = {
+        let map = map.clone();
+        thread::spawn(move || {
+            for key in 1000..3000 {
+                map.insert(key, 0);
+                map.remove(&(key - 1));
+            }
+        })
+    };
+    for _ in 0..20 {
+        let mut stable: Vec<_> = map
+            .iter_snapshot()
+            .map(|(key, _)| key)
+            .filter(|key| *key < 100)
+            .collect();
+        stable.sort();
+        assert_eq!(stable, (0..100).step_by(2).collect::<Vec<_>>());
+    }
+    writer.join().unwrap();
+}
+
+#[test]
+fn map_entry_read_modify_write() {
+    let map = Arc::new(Mapus::new());
+    {
+        let mut value = map.entry("a").or_insert(1);
+        *value += 10;
+    }
+    assert_eq!(map.get(&"a"), Some(11));
+    let entry = map.entry("a").and_modify(|value| *value *= 2);
+    assert_eq!(*entry.key(), "a");
+    assert_eq!(*entry.or_insert_with(|| unreachable!()), 22);
+    assert_eq!(*map.entry("b").or_default(), 0);
+
+    // increments through entries are never lost
+    let counters: Vec<_> = (0..4)
+        .map(|_| {
+            let map = map.clone();
+            thread::spawn(move || {
+                for round in 0..1000 {
+                    let key = if round % 2 == 0 { "even" } else { "odd" };
+                    map.entry(key).and_modify(|count| *count += 1).or_insert(1);
+                }
+            })
+        })
+        .collect();
+    counters.into_iter().for_each(|c| c.join().unwrap());
+    assert_eq!(map.get(&"even"), Some(2000));
+    assert_eq!(map.get(&"odd"), Some(2000));
+}
+
+#[test]
+fn counter_map_counts_from_many_threads() {
+    let counts = Arc::new(CounterMap::new());
+    assert_eq!(counts.get(&"GET /"), 0);
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let counts = counts.clone();
+            thread::spawn(move || {
+                for request in 0..1000 {
+                    counts.increment(if request % 4 == 0 { "POST /" } else { "GET /" });
+                }
+            })
+        })
+        .collect();
+    workers.into_iter().for_each(|w| w.join().unwrap());
+    counts.add("PUT /", 5);
+    assert_eq!(counts.get(&"GET /"), 3000);
+    assert_eq!(counts.len(), 3);
+    assert_eq!(
+        counts.snapshot(),
+        HashMap::from([("GET /", 3000), ("POST /", 1000), ("PUT /", 5)])
+    );
+}
+
+#[test]
+fn accumulator_sums_partial_results() {
+    let q = Multiq::new(0.5);
+    q.push_all((1..1000).map(|value| value as f64 + 0.5));
+    let total = Arc::new(Accumulator::with_stripes(3));
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let q = q.clone();
+            let total = total.clone();
+            thread::spawn(move || {
+                while let Some(value) = q.pop() {
+                    total.add(value);
+                }
+            })
+        })
+        .collect();
+    workers.into_iter().for_each(|w| w.join().unwrap());
+    let expected = (0..1000).map(|value| value as f64 + 0.5).sum::<f64>();
+    assert_eq!(total.sum(), expected);
+    assert_eq!(total.reset(), expected);
+    assert_eq!(total.sum(), 0.0);
+}
+
+#[test]
+fn single_thread_containers_behind_trait_objects() {
+    // business logic written against the traits, tested without spawning threads
+    fn route(jobs: &dyn ConcurrentQueue<u32>, retries: &dyn ConcurrentStack<u32>) -> Vec<u32> {
+        let mut done = Vec::new();
+        while let Some(job) = jobs.try_pop() {
+            if job % 3 == 0 {
+                retries.push(job);
+            } else {
+                done.push(job);
+            }
+        }
+        while let Some(job) = retries.try_pop() {
+            done.push(job);
+        }
+        done
+    }
+    let jobs = SingleThreadQueue::new();
+    let retries = SingleThreadStack::new();
+    (1..=7).for_each(|job| jobs.push(job));
+    assert_eq!(jobs.len(), 7);
+    assert_eq!(route(&jobs, &retries), [1, 2, 4, 5, 7, 6, 3]);
+    assert!(jobs.is_empty() && retries.is_empty());
+}
+
+#[test]
+fn stack_pop_spin_gives_up_at_the_limit() {
+    let stack = Arc::new(Stackus::new(1));
+    assert_eq!(stack.pop_spin(SpinLimit::Rounds(3)), Some(1));
+    assert_eq!(stack.pop_spin(SpinLimit::Rounds(3)), None);
+    let start = Instant::now();
+    assert_eq!(
+        stack.pop_spin(SpinLimit::Timeout(Duration::from_millis(10))),
+        None
+    );
+    assert!(start.elapsed() >= Duration::from_millis(10));
+
+    let pusher = {
+        let stack = stack.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(5));
+            stack.push(2);
+        })
+    };
+    assert_eq!(
+        stack.pop_spin(SpinLimit::Timeout(Duration::from_secs(10))),
+        Some(2)
+    );
+    pusher.join().unwrap();
+}
+
+#[test]
+fn static_queue_passes_values_in_order() {
+    static QUEUE: StaticQueue<u32, 4> = StaticQueue::new();
+    assert!(QUEUE.consumer().is_some());
+    let mut consumer = QUEUE.consumer().unwrap();
+    assert!(QUEUE.consumer().is_none());
+    let producer = thread::spawn(|| {
+        let mut producer = QUEUE.producer().unwrap();
+        for i in 0..10_000 {
+            let mut value = i;
+            while let Err(back) = producer.push(value) {
+                value = back;
+                thread::yield_now();
+            }
+        }
+    });
+    let mut expected = 0;
+    while expected < 10_000 {
+        match consumer.pop() {
+            Some(value) => {
+                assert_eq!(value, expected);
+                expected += 1;
+            }
+            None => thread::yield_now(),
+        }
+    }
+    producer.join().unwrap();
+    assert!(QUEUE.is_empty());
+    let dropped = Arc::new(());
+    let queue = StaticQueue::<Arc<()>, 2>::new();
+    let mut producer = queue.producer().unwrap();
+    producer.push(dropped.clone()).unwrap();
+    producer.push(dropped.clone()).unwrap();
+    assert!(producer.is_full());
+    assert!(producer.push(dropped.clone()).is_err());
+    drop(producer);
+    drop(queue);
+    assert_eq!(Arc::strong_count(&dropped), 1);
+}
+
+#[test]
+fn static_queue_takes_values_from_an_interrupt() {
+    // a thread standing in for a timer interrupt that fires while the main thread consumes
+    static SAMPLES: StaticQueue<u32, 8> = StaticQueue::new();
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    let interrupt = thread::spawn(|| {
+        for sample in 0..2000 {
+            // the only producer
+            if unsafe { SAMPLES.push_unchecked(sample) }.is_err() {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+            if sample % 64 == 0 {
+                thread::yield_now();
+            }
+        }
+    });
+    let mut received = Vec::new();
+    let mut finished = false;
+    while !finished {
+        finished = interrupt.is_finished();
+        // the only consumer
+        while let Some(sample) = unsafe { SAMPLES.pop_unchecked() } {
+            received.push(sample);
+        }
+    }
+    interrupt.join().unwrap();
+    assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+    assert_eq!(received.len() + DROPPED.load(Ordering::Relaxed), 2000);
+    assert!(SAMPLES.is_empty());
+}
+
+#[test]
+fn dispatcher_routes_keys_and_rebalances() {
+    let dispatcher = Dispatcher::new(4);
+    for i in 0..400 {
+        dispatcher.push(i % 40, i);
+    }
+    let mut seen = HashMap::new();
+    for index in 0..4 {
+        let consumer = dispatcher.consumer(index);
+        while let Some(value) = consumer.pop() {
+            // every value of a key lands in one queue, in push order
+            assert_eq!(dispatcher.queue_of(&(value % 40)), index);
+            let last = seen.insert(value % 40, value);
+            assert!(last.is_none_or(|last| last < value));
+        }
+    }
+    assert_eq!(seen.len(), 40);
+    for i in 0..400 {
+        dispatcher.push(i % 40, i);
+    }
+    let before: Vec<_> = (0..40).map(|key| dispatcher.queue_of(&key)).collect();
+    dispatcher.resize(5);
+    let moved = (0..40)
+        .filter(|key| dispatcher.queue_of(key) != before[*key])
+        .collect::<Vec<_>>();
+    // only keys that now map to the new queue move
+    assert!(moved.iter().all(|key| dispatcher.queue_of(key) == 4));
+    let fifth = dispatcher.consumer(4);
+    let waiting = thread::scope(|scope| {
+        let consumer = dispatcher.consumer(4);
+        let waiter = scope.spawn(move || {
+            while consumer.pop().is_some() {}
+            consumer.wait_and_pop()
+        });
+        while dispatcher.queues.read().unwrap()[4].waiting_consumers() == 0 {
+            thread::yield_now();
+        }
+        dispatcher.resize(2);
+        waiter.join().unwrap()
+    });
+    assert_eq!(waiting, None);
+    assert!(fifth.is_removed());
+    assert_eq!(fifth.pop(), None);
+    let mut count = 0;
+    for index in 0..2 {
+        let consumer = dispatcher.consumer(index);
+        let mut last = HashMap::new();
+        while let Some(value) = consumer.pop() {
+            assert!(last
+                .insert(value % 40, value)
+                .is_none_or(|last| last < value));
+            count += 1;
+        }
+    }
+    assert_eq!(count + moved.len() * 10, 400);
+    assert!(dispatcher.is_empty());
+}
+
+#[test]
+fn scheduler_overflows_and_steals() {
+    let scheduler = Scheduler::with_local_capacity(2, 4);
+    let mut first = scheduler.worker(0);
+    for i in 0..5 {
+        first.push(i);
+    }
+    // the older half moved to the injector when the fifth value came in
+    assert_eq!(
+        scheduler.injector.drain().flatten().collect::<Vec<_>>(),
+        vec![0, 1]
+    );
+    let mut second = scheduler.worker(1);
+    // the second worker has nothing local and steals the oldest half of the first
+    assert_eq!(second.pop(), Some(2));
+    assert_eq!(second.pop(), Some(3));
+    assert_eq!(first.pop(), Some(4));
+    assert_eq!(first.pop(), None);
+
+    // a tree of work that the workers split among each other
+    let scheduler = Scheduler::with_local_capacity(4, 16);
+    let done = AtomicUsize::new(0);
+    scheduler.inject(10u32);
+    let total = (1 << 11) - 1;
+    thread::scope(|scope| {
+        for index in 0..4 {
+            let (scheduler, done) = (&scheduler, &done);
+            scope.spawn(move || {
+                let mut worker = scheduler.worker(index);
+                while done.load(Ordering::SeqCst) < total {
+                    match worker.pop() {
+                        Some(depth) => {
+                            if depth > 0 {
+                                worker.push(depth - 1);
+                                worker.push(depth - 1);
+                            }
+                            done.fetch_add(1, Ordering::SeqCst);
+                        }
+                        None => thread::yield_now(),
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(done.into_inner(), total);
+    assert!(scheduler.is_empty());
+}
+
+#[cfg(feature = "executor")]
+#[test]
+fn executor_runs_tasks_that_spawn_and_await() {
+    /// Returns pending once, waking itself, so the task goes through the scheduler again.
+    struct YieldNow(bool);
+    impl std::future::Future for YieldNow {
+        type Output = ();
+        fn poll(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<()> {
+            if self.0 {
+                return std::task::Poll::Ready(());
+            }
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        }
+    }
+
+    let executor = Executor::with_threads(4);
+    let spawner = executor.spawner();
+    let sums: Vec<_> = (0..50u64)
+        .map(|i| {
+            let spawner = spawner.clone();
+            executor.spawn(async move {
+                let children: Vec<_> = (0..10)
+                    .map(|j| {
+                        spawner.spawn(async move {
+                            YieldNow(false).await;
+                            i * 10 + j
+                        })
+                    })
+                    .collect();
+                let mut sum = 0;
+                for child in children {
+                    sum += child.await.unwrap();
+                }
+                sum
+            })
+        })
+        .collect();
+    let total = block_on(async {
+        let mut total = 0;
+        for sum in sums {
+            total += sum.await.unwrap();
+        }
+        total
+    });
+    assert_eq!(total, (0..500).sum::<u64>());
+    let panicked = executor.spawn(async { panic!("task panicked") });
+    assert_eq!(panicked.join(), Err(JobError::Panicked));
+    drop(executor);
+    assert_eq!(spawner.spawn(async {}).join(), Err(JobError::Abandoned));
+}
+
+#[test]
+fn timer_driver_fires_in_deadline_order() {
+    let driver = TimerDriver::new();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    for (delay, label) in [
+        (30, "third"),
+        (10, "first"),
+        (20, "second"),
+        (25, "cancelled"),
+    ] {
+        let sender = sender.clone();
+        let id = driver.schedule_after(Duration::from_millis(delay), move || {
+            sender.send(label).unwrap();
+        });
+        if label == "cancelled" {
+            assert!(driver.cancel(id));
+            assert!(!driver.cancel(id));
+        }
+    }
+    driver.schedule_after(Duration::ZERO, || panic!("callback panicked"));
+    let fired: Vec<_> = (0..3).map(|_| receiver.recv().unwrap()).collect();
+    assert_eq!(fired, ["first", "second", "third"]);
+    assert_eq!(driver.pending(), 0);
+
+    let start = Instant::now();
+    let mut sleep = std::pin::pin!(driver.sleep(Duration::from_millis(20)));
+    let waker = std::task::Waker::noop();
+    let mut cx = std::task::Context::from_waker(waker);
+    while std::future::Future::poll(sleep.as_mut(), &mut cx).is_pending() {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert!(sleep.is_elapsed());
+    assert!(receiver.try_recv().is_err());
+}
+
+#[test]
+fn bus_routes_topics_with_wildcards_and_backpressure() {
+    let bus = Bus::new();
+    let kitchen = bus.subscribe("sensors.*.temperature", 8, Backpressure::Block);
+    let everything = bus.subscribe("sensors.#", 2, Backpressure::DropOldest);
+    assert_eq!(bus.publish("sensors.kitchen.temperature", 21), 2);
+    assert_eq!(bus.publish("sensors.kitchen.humidity", 40), 1);
+    assert_eq!(bus.publish("sensors.garage.temperature", 12), 2);
+    assert_eq!(bus.publish("alerts.fire", 1), 0);
+    let received: Vec<_> = std::iter::from_fn(|| kitchen.try_recv())
+        .map(|message| (message.topic.to_string(), message.payload))
+        .collect();
+    assert_eq!(
+        received,
+        [
+            ("sensors.kitchen.temperature".to_string(), 21),
+            ("sensors.garage.temperature".to_string(), 12)
+        ]
+    );
+    // the oldest message made room for the newest
+    assert_eq!(everything.dropped(), 1);
+    assert_eq!(everything.recv().payload, 40);
+    assert_eq!(everything.recv().payload, 12);
+
+    // a full blocking subscriber slows the publisher down until it catches up
+    let slow = bus.subscribe("jobs", 1, Backpressure::Block);
+    bus.publish("jobs", 1);
+    thread::scope(|scope| {
+        let publisher = scope.spawn(|| bus.publish("jobs", 2));
+        thread::sleep(Duration::from_millis(20));
+        assert!(!publisher.is_finished());
+        assert_eq!(slow.recv().payload, 1);
+        publisher.join().unwrap();
+    });
+    assert_eq!(slow.recv().payload, 2);
+    drop((kitchen, slow));
+    assert_eq!(bus.subscribers(), 1);
+}
+
+#[test]
+fn bus_dropped_subscription_releases_all_blocked_publishers() {
+    let bus = Bus::new();
+    let slow = bus.subscribe("jobs", 1, Backpressure::Block);
+    bus.publish("jobs", 0);
+    let (sender, receiver) = mpsc::channel();
+    for i in 1..4 {
+        let bus = bus.clone();
+        let sender = sender.clone();
+        thread::spawn(move || sender.send(bus.publish("jobs", i)).unwrap());
+    }
+    while slow.subscriber.publishing.load(Ordering::SeqCst) != 3 {
+        thread::yield_now();
+    }
+    drop(slow);
+    for _ in 0..3 {
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(1));
+    }
+    assert_eq!(bus.publish("jobs", 4), 0);
+}
+
+#[test]
+fn bounded_queue_overflow_policies() {
+    let oldest = Boundq::with_overflow(2, OverflowPolicy::DropOldest);
+    for i in 0..5 {
+        oldest.push(i);
+    }
+    assert_eq!(oldest.dropped(), 3);
+    assert_eq!(oldest.push_with_policy(5), Ok(Some(3)));
+    assert_eq!((oldest.pop(), oldest.pop()), (4, 5));
+
+    let newest = Boundq::with_overflow(2, OverflowPolicy::DropNewest);
+    for i in 0..5 {
+        newest.push(i);
+    }
+    assert_eq!(newest.dropped(), 3);
+    assert_eq!((newest.pop(), newest.pop()), (0, 1));
+
+    let fail = Boundq::with_overflow(1, OverflowPolicy::Fail);
+    assert_eq!(fail.push_with_policy(1), Ok(None));
+    assert_eq!(fail.push_with_policy(2), Err(2));
+    assert_eq!(fail.dropped(), 0);
+    assert_eq!(fail.pop(), 1);
+}
+
+#[test]
+fn queue_records_dwell_times() {
+    let q = Multiq::<u32>::with_latency_histogram(0);
+    thread::sleep(Duration::from_millis(20));
+    q.push(1);
+    q.push_all([2, 3]);
+    q.push_front(4);
+    assert_eq!(q.pop(), Some(4));
+    assert_eq!(q.pop(), Some(0));
+    let snapshot = q.latency_snapshot().unwrap();
+    assert_eq!(snapshot.count(), 2);
+    // the first value waited through the sleep, the requeued one didn't
+    assert!(snapshot.max() >= Duration::from_millis(20));
+    assert!(snapshot.quantile(0.5) < Duration::from_millis(20));
+    assert_eq!(q.pop_up_to(2), vec![1, 2]);
+    assert_eq!(q.drain().collect::<Vec<_>>(), vec![3]);
+    let snapshot = q.latency_snapshot().unwrap();
+    assert_eq!(snapshot.count(), 5);
+    assert!(snapshot.quantile(1.0) >= Duration::from_millis(20));
+    assert!(snapshot.mean() <= snapshot.max());
+    assert!(Multiq::new(0).latency_snapshot().is_none());
+}
+
+#[test]
+fn histogram_quantiles_within_bucket_precision() {
+    let histogram = Histogram::new();
+    for micros in 1..=1000 {
+        histogram.record(Duration::from_micros(micros));
+    }
+    let snapshot = histogram.snapshot();
+    for (quantile, exact) in [(0.5, 500.0), (0.9, 900.0), (0.99, 990.0)] {
+        let measured = snapshot.quantile(quantile).as_secs_f64() * 1e6;
+        assert!(
+            (measured - exact).abs() / exact <= 0.125,
+            "{quantile}: {measured}"
+        );
+    }
+    assert_eq!(snapshot.max(), Duration::from_micros(1000));
+    assert_eq!(snapshot.quantile(1.0), Duration::from_micros(1000));
+    assert_eq!(Histogram::new().snapshot().quantile(0.5), Duration::ZERO);
+}
+
+// Run under Miri to also catch a node that is freed while another pop still reads it:
+// cargo +nightly miri test --lib stack_drops_every_value_once
+#[test]
+fn stack_drops_every_value_once() {
+    struct Counted(Arc<AtomicUsize>);
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    let drops = Arc::new(AtomicUsize::new(0));
+    let stack = Arc::new(Stackus::new(Counted(drops.clone())));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let stack = stack.clone();
+            let drops = drops.clone();
+            thread::spawn(move || {
+                for _ in 0..20 {
+                    stack.push(Counted(drops.clone()));
+                    stack.push(Counted(drops.clone()));
+                    drop(stack.pop());
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(drops.load(Ordering::SeqCst), 80);
+    // the values left in the stack are dropped with it, popped nodes are freed without theirs
+    assert_eq!(stack.len(), 81);
+    drop(Arc::into_inner(stack).unwrap());
+    assert_eq!(drops.load(Ordering::SeqCst), 161);
+}
+
+#[test]
+fn stack_reclaims_after_contended_pops() {
+    let stack = Arc::new(Stackus::new(0));
+    let barrier = Arc::new(Barrier::new(4));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let stack = stack.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                barrier.wait();
+                let mut popped = 0;
+                for value in 0..50 {
+                    stack.push(value);
+                    popped += stack.pop().is_some() as usize;
+                }
+                popped
+            })
+        })
+        .collect();
+    let popped: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+    assert_eq!(popped + stack.len(), 201);
+    while stack.pop().is_some() {}
+    // a pop alone frees every pending node, none may be lost or freed twice
+    stack.reclaim_now();
+    assert_eq!(stack.pending_retired(), 0);
+}
+
+#[test]
+fn stack_returns_claimed_nodes_under_contention() {
+    let stack = Arc::new(Stackus::new(0));
+    let running = Arc::new(AtomicUsize::new(2));
+    // stands in for pops that start while a reclaim holds the claimed list, so it has to
+    // give the list back
+    let popping = {
+        let stack = stack.clone();
+        let running = running.clone();
+        thread::spawn(move || {
+            while running.load(Ordering::SeqCst) != 0 {
+                stack.threads_in_pop.fetch_add(1, Ordering::SeqCst);
+                thread::yield_now();
+                stack.threads_in_pop.fetch_sub(1, Ordering::SeqCst);
+                thread::yield_now();
+            }
+        })
+    };
+    let handles: Vec<_> = (0..2)
+        .map(|_| {
+            let stack = stack.clone();
+            let running = running.clone();
+            thread::spawn(move || {
+                for value in 0..2000 {
+                    stack.push(value);
+                    // retired nodes are only freed by a reclaim, keeps the pending list long
+                    stack.pop_raw().unwrap().retire();
+                    stack.reclaim_now();
+                }
+                running.fetch_sub(1, Ordering::SeqCst);
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    popping.join().unwrap();
+    assert_eq!(stack.len(), 1);
+    stack.reclaim_now();
+    // a node lost while giving a claimed list back would never be freed
+    assert_eq!(stack.pending_retired(), 0);
+}
+
+#[test]
+fn queue_close_wakes_waiting_consumers() {
+    let q = Multiq::new(1);
+    q.pop();
+    let consumers: Vec<_> = (0..2)
+        .map(|_| {
+            let q = q.clone();
+            thread::spawn(move || q.wait_and_pop())
+        })
+        .collect();
+    while q.queue.waiting.load(Ordering::SeqCst) != 2 {
+        thread::yield_now();
+    }
+    // a waiting consumer holds no lock, so other threads use the queue meanwhile
+    assert!(q.is_empty());
+    assert_eq!(q.pop(), None);
+    assert_eq!(q.drain().count(), 0);
+    q.close();
+    for consumer in consumers {
+        assert_eq!(consumer.join().unwrap(), Err(QueueClosed));
+    }
+    assert!(q.is_closed());
+    // values still queued are handed out before the error
+    q.push(2);
+    assert_eq!(q.wait_and_pop(), Ok(2));
+    assert_eq!(q.wait_and_peek(), Err(QueueClosed));
+    let token = CancellationToken::new();
+    assert_eq!(q.wait_and_pop_cancellable(&token), Err(Cancelled));
+}
+
+#[test]
+fn queue_holds_values_without_debug() {
+    struct Opaque(u32);
+    let q = Multiq::new(Opaque(1));
+    q.push(Opaque(2));
+    let jobs: Multiq<Box<dyn FnOnce() -> u32 + Send>> = Multiq::new(Box::new(|| 3));
+    assert_eq!(q.pop().map(|value| value.0), Some(1));
+    assert_eq!(jobs.wait_and_pop().map(|job| job()), Ok(3));
+
+    let q = Multiq::new(1);
+    q.push(2);
+    q.pop();
+    q.push_all([3, 4]);
+    q.push_front(0);
+    assert_eq!(q.dump(), "[0, 2, 3, 4]");
+}
+
+#[test]
+fn queue_channel_ends_when_senders_drop() {
+    let (sender, receiver) = Multiq::channel();
+    assert_eq!(receiver.try_recv(), None);
+    let producers: Vec<_> = (0..2)
+        .map(|producer| {
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for value in 0..100 {
+                    sender.send(producer * 100 + value);
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+    let consumers: Vec<_> = (0..2)
+        .map(|_| {
+            let receiver = receiver.clone();
+            thread::spawn(move || {
+                let mut received = Vec::new();
+                while let Ok(value) = receiver.recv() {
+                    received.push(value);
+                }
+                received
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+    let mut received: Vec<_> = consumers
+        .into_iter()
+        .flat_map(|consumer| consumer.join().unwrap())
+        .collect();
+    received.sort();
+    assert_eq!(received, (0..200).collect::<Vec<_>>());
+    assert!(receiver.is_disconnected());
+    assert_eq!(receiver.recv(), Err(QueueClosed));
+
+    // an existing queue keeps its values
+    let (sender, receiver) = Multiq::new(1).split();
+    sender.send_all([2, 3]);
+    assert!(!receiver.is_disconnected());
+    drop(sender);
+    assert_eq!(receiver.recv(), Ok(1));
+    assert_eq!(receiver.queue.drain().collect::<Vec<_>>(), [2, 3]);
+    assert_eq!(receiver.recv(), Err(QueueClosed));
+}
+
+#[test]
+fn stack_handle_is_shared_between_threads() {
+    let stack = StackusHandle::new(0);
+    let handles: Vec<_> = (1..=4)
+        .map(|thread| {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                for value in 0..100 {
+                    stack.push(thread * 100 + value);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(stack.len(), 401);
+    let mut values: Vec<_> = stack.pop_all().collect();
+    values.sort();
+    assert_eq!(values[1..], (100..500).collect::<Vec<_>>());
+
+    // a stack used by scoped threads converts too
+    let stack: StackusHandle<_> = Stackus::new(1).into();
+    assert_eq!(stack.clone().pop(), Some(1));
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn stack_reclaim_config_batches_frees() {
+    let stack = Stackus::with_reclaim_config(
+        0,
+        ReclaimConfig {
+            scan_threshold: 4,
+            scan_interval: 1,
+        },
+    );
+    for value in 1..=4 {
+        stack.push(value);
+        stack.pop_raw().unwrap().retire();
+        stack.push(value);
+        stack.pop();
+        // a lone pop frees its own node, the retired ones wait for the threshold
+        assert_eq!(stack.pending_retired(), value % 4);
+    }
+
+    let stack = Stackus::with_reclaim_config(
+        0,
+        ReclaimConfig {
+            scan_threshold: 0,
+            scan_interval: 3,
+        },
+    );
+    let pending: Vec<_> = (1..=4)
+        .map(|value| {
+            stack.push(value);
+            stack.pop_raw().unwrap().retire();
+            stack.push(value);
+            stack.pop();
+            stack.pending_retired()
+        })
+        .collect();
+    assert_eq!(pending, [0, 1, 2, 0]);
+    assert_eq!(stack.pop(), Some(0));
+}
+
+#[test]
+fn forget_on_drop_skips_freeing_values() {
+    struct Counted(Arc<AtomicUsize>);
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    let drops = Arc::new(AtomicUsize::new(0));
+    let stack = Stackus::new(Counted(drops.clone()));
+    let q = Multiq::new(Counted(drops.clone()));
+    for _ in 0..10 {
+        stack.push(Counted(drops.clone()));
+        q.push(Counted(drops.clone()));
+    }
+    stack.forget_on_drop();
+    q.forget_on_drop();
+    drop(stack);
+    // the queue is freed with its last handle
+    drop(q.clone());
+    assert!(q.pop().is_some());
+    assert_eq!(drops.load(Ordering::Relaxed), 1);
+    drop(q);
+    assert_eq!(drops.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn debug_shows_state_and_first_values() {
+    let q = Multiq::new(0);
+    q.push_all(1..10);
+    assert_eq!(
+        format!("{q:?}"),
+        "Multiq { len: 10, closed: false, waiting_consumers: 0, \
+         front: [0, 1, 2, 3, 4, 5, 6, 7] }"
+    );
+    let head = q.queue.head.lock().unwrap();
+    assert!(format!("{q:?}").ends_with("front: <locked> }"));
+    drop(head);
+    q.drain();
+    q.close();
+    assert_eq!(
+        format!("{q:?}"),
+        "Multiq { len: 0, closed: true, waiting_consumers: 0, front: [] }"
+    );
+
+    let stack = Stackus::new(1);
+    stack.push(2);
+    assert_eq!(
+        format!("{stack:?}"),
+        "Stackus { len: 2, pending_retired: 0, threads_in_pop: 0, top: [2, 1] }"
+    );
+}
+
+#[test]
+fn stack_debug_inside_pop_if_does_not_wait() {
+    let stack = Arc::new(Stackus::new(1));
+    let (sender, receiver) = mpsc::channel();
+    let popper = {
+        let stack = stack.clone();
+        // a Debug that waited for the pops in progress would wait for its own
+        thread::spawn(move || {
+            stack.pop_if(|_| {
+                sender.send(format!("{stack:?}")).unwrap();
+                true
+            })
+        })
+    };
+    let formatted = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(
+        formatted,
+        "Stackus { len: 1, pending_retired: 0, threads_in_pop: 0, top: [1] }"
+    );
+    assert_eq!(popper.join().unwrap(), Some(1));
+    assert_eq!(
+        format!("{stack:?}"),
+        "Stackus { len: 0, pending_retired: 0, threads_in_pop: 0, top: [] }"
+    );
+}
+
+#[cfg(feature = "debug-dump")]
+#[test]
+fn stack_dump_dot_shows_both_lists() {
+    let stack = Stackus::new(1);
+    stack.push(2);
+    stack.push(3);
+    stack.pop_raw().unwrap().retire();
+    let dot = stack.dump_dot();
+    assert!(dot.starts_with("digraph Stackus {"));
+    let edges: Vec<_> = dot.lines().filter(|line| line.contains("->")).collect();
+    assert_eq!(edges.len(), 3);
+    assert!(edges[0].trim_start().starts_with("head -> "));
+    assert!(edges[2].trim_start().starts_with("pending -> "));
+    assert_eq!(dot.matches("style=dashed").count(), 1);
+    // the walk left threads_in_pop as it found it, so the node can still be freed
+    assert_eq!(stack.reclaim_now(), 1);
+}
+
+#[test]
+fn pool_bounded_queue_applies_rejection_policy() {
+    /// Returns a pool whose only worker is stuck until the returned sender is used, with one
+    /// queued job filling its queue.
+    fn full_pool(policy: RejectionPolicy) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = ThreadPool::builder()
+            .threads(1)
+            .queue_capacity(1)
+            .rejection_policy(policy)
+            .build();
+        let (release, released) = mpsc::channel();
+        let (started, running) = mpsc::channel();
+        pool.execute(move || {
+            started.send(()).unwrap();
+            released.recv().unwrap();
+        });
+        running.recv().unwrap();
+        pool.execute(|| {});
+        (pool, release)
+    }
+
+    let (pool, release) = full_pool(RejectionPolicy::Drop);
+    let rejected = pool.try_execute(|| {}).unwrap_err();
+    assert_eq!(rejected.to_string(), "the job queue of the pool is full");
+    let ran = Arc::new(AtomicUsize::new(0));
+    let counter = ran.clone();
+    pool.execute(move || {
+        counter.fetch_add(1, Ordering::Relaxed);
+    });
+    assert_eq!(pool.rejected_jobs(), 1);
+    release.send(()).unwrap();
+    pool.join();
+    assert_eq!(ran.load(Ordering::Relaxed), 0);
+
+    // a dropped scoped task fails its scope instead of going missing
+    let (pool, release) = full_pool(RejectionPolicy::Drop);
+    let mut ran = false;
+    let scoped = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        pool.scope(|scope| {
+            scope.spawn(|_| ran = true);
+            assert!(scope.token().is_cancelled());
+            release.send(()).unwrap();
+        })
+    }));
+    assert!(scoped.is_err());
+    assert!(!ran);
+    assert_eq!(pool.rejected_jobs(), 1);
+    pool.join();
+
+    let (pool, release) = full_pool(RejectionPolicy::CallerRuns);
+    let caller = thread::current().id();
+    let (ran_on, ran) = mpsc::channel();
+    pool.execute(move || ran_on.send(thread::current().id()).unwrap());
+    assert_eq!(ran.recv().unwrap(), caller);
+    release.send(()).unwrap();
+
+    let (pool, release) = full_pool(RejectionPolicy::Block);
+    let pool = Arc::new(pool);
+    let producer = {
+        let pool = pool.clone();
+        thread::spawn(move || pool.execute(|| {}))
+    };
+    thread::sleep(Duration::from_millis(20));
+    assert!(!producer.is_finished());
+    assert_eq!(pool.pending_jobs(), 1);
+    release.send(()).unwrap();
+    producer.join().unwrap();
+}
+
+#[test]
+fn spin_policy_escalates_to_parking() {
+    let mut backoff = Backoff {
+        policy: SpinPolicy {
+            spin_rounds: 1,
+            yield_rounds: 1,
+            park: Some(Duration::from_millis(5)),
+        },
+        round: 0,
+    };
+    // a round of spinning and one of yielding, then every round sleeps
+    let start = Instant::now();
+    backoff.snooze();
+    backoff.snooze();
+    assert!(start.elapsed() < Duration::from_millis(5));
+    backoff.snooze();
+    assert!(start.elapsed() >= Duration::from_millis(5));
+    assert_eq!(backoff.round, 3);
+
+    assert_eq!(spin::spin_policy(), SpinPolicy::DEFAULT);
+    spin::set_spin_policy(SpinPolicy::OVERSUBSCRIBED);
+    assert_eq!(spin::spin_policy(), SpinPolicy::OVERSUBSCRIBED);
+    // the busy-wait loops keep working, they only wait differently
+    let stack = Stackus::new(1);
+    stack.pop();
+    assert_eq!(stack.pop_spin(SpinLimit::Rounds(12)), None);
+    let lock = Arc::new(Lock::<_, TicketLock>::new(0));
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                for _ in 0..100 {
+                    *lock.lock().unwrap() += 1;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(*lock.lock().unwrap(), 400);
+    spin::set_spin_policy(SpinPolicy::default());
+}
+
+#[test]
+fn weak_queue_handle_inside_a_job_does_not_leak_the_queue() {
+    type Job = Box<dyn FnOnce() + Send>;
+    struct Requeue(WeakMultiq<Job>, Arc<AtomicUsize>);
+    impl Drop for Requeue {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let dropped = Arc::new(AtomicUsize::new(0));
+    let queue: Multiq<Job> = Multiq::new(Box::new(|| {}));
+    queue.pop();
+    let requeue = Requeue(queue.downgrade(), dropped.clone());
+    queue.push(Box::new(move || {
+        let queue = requeue
+            .0
+            .upgrade()
+            .expect("the queue is alive while its job runs");
+        queue.push(Box::new(|| {}));
+        drop(requeue);
+    }));
+    queue.pop().unwrap()();
+    assert_eq!(queue.len(), 1);
+    assert_eq!(dropped.load(Ordering::SeqCst), 1);
+
+    // a job holding its queue weakly is dropped with the queue
+    let requeue = Requeue(queue.downgrade(), dropped.clone());
+    let weak = requeue.0.clone();
+    queue.push(Box::new(move || drop(requeue)));
+    drop(queue);
+    assert_eq!(dropped.load(Ordering::SeqCst), 2);
+    assert!(weak.upgrade().is_none());
+}
+
+#[test]
+fn stack_and_queue_into_vec_take_the_values() {
+    let stack = Stackus::new(1);
+    stack.push(2);
+    stack.push(3);
+    stack.pop_raw().unwrap().into_value();
+    // the popped node is still pending when the stack is consumed
+    assert_eq!(stack.into_vec(), vec![2, 1]);
+
+    let queue = Multiq::new(1);
+    queue.push(2);
+    queue.push(3);
+    queue.pop();
+    queue.push(4);
+    assert_eq!(queue.clone().into_vec(), vec![2, 3, 4]);
+    // another handle was alive, so the values were drained from the shared queue
+    assert!(queue.is_empty());
+    queue.push(5);
+    queue.push(6);
+    assert_eq!(queue.into_vec(), vec![5, 6]);
+}
+
+#[test]
+fn queues_compare_equal_by_contents() {
+    let queue = Multiq::new(1);
+    queue.push(2);
+    let other = Multiq::new(0);
+    other.pop();
+    other.push(1);
+    assert_ne!(queue, other);
+    other.push(2);
+    assert_eq!(queue, other);
+    assert_eq!(other, queue);
+    assert_eq!(queue, queue.clone());
+    // both halves of each queue are compared, wherever the values sit
+    queue.pop();
+    queue.push(3);
+    other.pop();
+    other.push(3);
+    assert_eq!(queue, other);
+    assert_contents_eq!(queue, [2, 3]);
+    assert_contents_eq!(queue, vec![2, 3], "checked {} times", 2);
+    assert_eq!(queue.len(), 2);
+
+    let stack = Stackus::new(1);
+    stack.push(2);
+    assert_contents_eq!(stack, [2, 1]);
+    assert_eq!(stack.pop(), Some(2));
+
+    // two threads comparing the same pair in opposite order
+    let handles: Vec<_> = [(queue.clone(), other.clone()), (other, queue)]
+        .into_iter()
+        .map(|(left, right)| {
+            thread::spawn(move || {
+                for _ in 0..1000 {
+                    assert!(left == right);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+#[test]
+fn appendus_reservations_collect_pool_results_in_submission_order() {
+    let results = Appendus::new();
+    let pool = ThreadPool::new(4);
+    pool.scope(|scope| {
+        for job in 0..64u64 {
+            let slot = results.reserve();
+            assert_eq!(slot.index(), job as usize);
+            scope.spawn(move |_| {
+                // later jobs tend to finish first
+                thread::sleep(Duration::from_micros(64 - job));
+                slot.set(job * job);
+            });
+        }
+    });
+    pool.join();
+    assert_eq!(results.len(), 64);
+    assert_eq!(results.get(9), Some(&81));
+    assert_eq!(results.iter().count(), 64);
+    assert_eq!(
+        results.into_vec(),
+        (0..64).map(|job| job * job).collect::<Vec<_>>()
+    );
+
+    let values = Appendus::new();
+    values.push("a".to_string());
+    let pending = values.reserve();
+    values.push("c".to_string());
+    // the pending reservation stops the iteration
+    assert_eq!(values.get(1), None);
+    assert_eq!(values.iter().count(), 1);
+    // abandoned, the index stays empty
+    assert_eq!(pending.index(), 1);
+    assert_eq!(values.len(), 3);
+    assert_eq!(values.into_vec(), ["a", "c"]);
+}
+
+#[test]
+fn queue_push_wakes_popper_behind_a_peeker() {
+    let q = Multiq::new(0);
+    q.pop();
+    let peeker = {
+        let q = q.clone();
+        thread::spawn(move || q.wait_and_peek())
+    };
+    while q.queue.peeking.load(Ordering::SeqCst) != 1 {
+        thread::yield_now();
+    }
+    let (sender, receiver) = mpsc::channel();
+    let popper = {
+        let q = q.clone();
+        thread::spawn(move || sender.send(q.wait_and_pop()).unwrap())
+    };
+    while q.queue.waiting.load(Ordering::SeqCst) != 1 {
+        thread::yield_now();
+    }
+    // the peeker blocked first, the push must still reach the popper
+    q.push(7);
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)),
+        Ok(Ok(7)),
+        "the popper missed the push"
+    );
+    popper.join().unwrap();
+    q.close();
+    // the peeker saw the value or missed it to the popper and then the close
+    assert!(matches!(peeker.join().unwrap(), Ok(7) | Err(QueueClosed)));
+}
+
+// A consumer that registered and then found a value in its look can have its entry taken by
+// the wake of a later push. It has to pass the wakeup on, or the consumer registered behind it
+// sleeps while that push's value sits in the queue. The lock of the queue holds the first
+// consumer right after its look released the head, with the value taken and still registered.
+#[test]
+fn queue_wakeup_of_a_consumer_that_found_a_value_is_passed_on() {
+    static GATE: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    /// Unlocks done by the thread named "first".
+    static UNLOCKS: AtomicUsize = AtomicUsize::new(0);
+    /// The unlock of the thread named "first" after which it waits for the gate.
+    static GATED_UNLOCK: AtomicUsize = AtomicUsize::new(usize::MAX);
+    #[derive(Debug, Default)]
+    struct GatedLock(DefaultLock);
+    unsafe impl RawLock for GatedLock {
+        fn lock(&self) {
+            self.0.lock()
+        }
+
+        fn try_lock(&self) -> bool {
+            self.0.try_lock()
+        }
+
+        unsafe fn unlock(&self) {
+            self.0.unlock();
+            if thread::current().name() == Some("first")
+                && UNLOCKS.fetch_add(1, Ordering::SeqCst) + 1 == GATED_UNLOCK.load(Ordering::SeqCst)
+            {
+                drop(GATE.lock().unwrap());
+            }
+        }
+    }
+    let q = Multiq::<u32, GatedLock>::with_lock(0);
+    q.pop();
+    let first = {
+        let q = q.clone();
+        thread::Builder::new()
+            .name("first".into())
+            .spawn(move || q.wait_and_pop())
+            .unwrap()
+    };
+    // a look at the empty queue unlocks the tail and then the head, the first consumer parks
+    // after its second look
+    while UNLOCKS.load(Ordering::SeqCst) != 4 {
+        thread::yield_now();
+    }
+    let gate = GATE.lock().unwrap();
+    GATED_UNLOCK.store(6, Ordering::SeqCst);
+    q.push(100);
+    while UNLOCKS.load(Ordering::SeqCst) != 6 {
+        thread::yield_now();
+    }
+    let (sender, receiver) = mpsc::channel();
+    let second = {
+        let q = q.clone();
+        thread::spawn(move || sender.send(q.wait_and_pop()).unwrap())
+    };
+    while q.queue.waiting.load(Ordering::SeqCst) != 2 {
+        thread::yield_now();
+    }
+    // the wake takes the entry of the first consumer, which returns 100 anyway
+    q.push(1);
+    drop(gate);
+    assert_eq!(first.join().unwrap(), Ok(100));
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)),
+        Ok(Ok(1)),
+        "the second consumer slept through the push"
+    );
+    second.join().unwrap();
+}
+
+#[test]
+fn queue_push_front_never_waits_for_the_byte_budget() {
+    let q = Multiq::with_byte_budget(String::from("ab"), 4, String::len);
+    q.push(String::from("cd"));
+    let failed = q.pop().unwrap();
+    // a producer takes the budget the popped value gave back
+    q.push(String::from("ef"));
+    let budget = q.queue.budget.as_ref().unwrap();
+    assert_eq!(budget.semaphore.available_permits(), 0);
+    q.push_front(failed);
+    assert_eq!(budget.overdrawn.load(Ordering::Relaxed), 2);
+    // the first pop pays back the overdraft, the next ones release the budget again
+    assert_eq!(q.pop().as_deref(), Some("ab"));
+    assert_eq!(budget.semaphore.available_permits(), 0);
+    assert_eq!(q.pop().as_deref(), Some("cd"));
+    assert_eq!(q.pop().as_deref(), Some("ef"));
+    assert_eq!(budget.overdrawn.load(Ordering::Relaxed), 0);
+    assert_eq!(budget.semaphore.available_permits(), 4);
+}
+
+#[test]
+fn queue_consumer_drop_requeues_over_a_full_budget() {
+    let q = Multiq::with_byte_budget(String::from("a"), 2, String::len);
+    q.push(String::from("b"));
+    let mut consumer = q.consumer(2);
+    assert_eq!(consumer.try_recv().as_deref(), Some("a"));
+    assert_eq!(consumer.buffered(), 1);
+    // producers take the whole budget while the consumer still holds "b"
+    q.push(String::from("c"));
+    q.push(String::from("d"));
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        drop(consumer);
+        sender.send(()).unwrap();
+    });
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)),
+        Ok(()),
+        "dropping the consumer blocked"
+    );
+    assert_eq!(q.drain().collect::<Vec<_>>(), ["b", "c", "d"]);
+    let budget = q.queue.budget.as_ref().unwrap();
+    assert_eq!(budget.semaphore.available_permits(), 2);
+}
+
+#[test]
+fn left_right_write_goes_on_after_a_panicking_read() {
+    let value = Arc::new(LeftRight::new(1));
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        value.read(|_| panic!("reader failed"))
+    }));
+    assert!(panicked.is_err());
+    let (sender, receiver) = mpsc::channel();
+    {
+        let value = value.clone();
+        thread::spawn(move || {
+            value.write(|value| *value += 1);
+            sender.send(()).unwrap();
+        });
+    }
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_secs(5)),
+        Ok(()),
+        "the write waited for the reader that panicked"
+    );
+    assert_eq!(value.read(|value| *value), 2);
+}
+
+#[test]
+fn seqlock_stays_usable_after_a_panicking_update() {
+    let lock = SeqLock::new(1);
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        lock.update(|value| {
+            *value = 2;
+            panic!("update failed");
+        })
+    }));
+    assert!(panicked.is_err());
+    // the half done update is dropped, reads and writes go on
+    assert_eq!(lock.read(), 1);
+    assert_eq!(lock.version(), 0);
+    lock.update(|value| *value += 2);
+    assert_eq!(lock.read(), 3);
+    assert_eq!(lock.version(), 1);
+}
+
+#[test]
+fn stack_pop_if_survives_a_panicking_predicate() {
+    let stack = Stackus::new(1);
+    stack.push(2);
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        stack.pop_if(|_| panic!("predicate failed"))
+    }));
+    assert!(panicked.is_err());
+    // pops still find themselves alone and free their nodes right away
+    assert_eq!(stack.pop(), Some(2));
+    assert_eq!(stack.pop_if(|top| *top == 1), Some(1));
+    assert_eq!(stack.pending_retired(), 0);
+}
+
+#[test]
+fn stack_pop_if_reads_values_alongside_pops() {
+    let stack = Stackus::new(String::from("0"));
+    for value in 1..40 {
+        stack.push(value.to_string());
+    }
+    let popped = AtomicUsize::new(0);
+    thread::scope(|s| {
+        s.spawn(|| {
+            while stack.pop().is_some() {
+                popped.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        s.spawn(|| {
+            // a pop that moved the top out while the predicate reads it would free the string
+            while stack
+                .pop_if(|top| top.parse::<u32>().is_ok_and(|value| value < 40))
+                .is_some()
+            {
+                popped.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        s.spawn(|| {
+            let all = stack.pop_all().count();
+            popped.fetch_add(all, Ordering::Relaxed);
+        });
+    });
+    assert_eq!(popped.into_inner(), 40);
+    assert!(stack.is_empty());
+}
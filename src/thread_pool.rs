@@ -0,0 +1,830 @@
+use crate::affinity;
+use crate::cancellation::{CancellationToken, Cancelled};
+use crate::ewma::Ewma;
+use crate::multiq::{LoadStats, LOAD_ALPHA};
+use crate::promise::{self, JobHandle};
+use std::{
+    any::Any,
+    collections::VecDeque,
+    convert::Infallible,
+    fmt,
+    marker::PhantomData,
+    mem,
+    panic::{self, AssertUnwindSafe},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, RwLock,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// A unit of work run by a [ThreadPool].
+pub type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Called with the payload of every job that panicked, before the [PanicPolicy] is applied.
+pub type PanicHandler = Box<dyn Fn(&(dyn Any + Send)) + Send + Sync>;
+
+/// Called on a worker thread with the worker's index, see [PoolBuilder::on_thread_start].
+pub type ThreadHook = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// A thread pool, following the simple pool from chapter 9 of "C++ Concurrency in
+/// Action" by Anthony Williams: workers take jobs from a shared queue until the pool is joined.
+/// A panicking job is caught and never takes the worker down with it unless the
+/// [PanicPolicy] says so. The pool starts with its minimum number of workers and adds one
+/// whenever a job is queued while every worker is busy, up to its maximum, or only under
+/// sustained load with [PoolBuilder::autoscale].
+pub struct ThreadPool {
+    pub pool: Arc<InnerPool>,
+}
+
+pub struct InnerPool {
+    pub available: Condvar,
+    /// Notified when a worker takes a job from a bounded queue, see [RejectionPolicy::Block].
+    pub room: Condvar,
+    pub jobs: Mutex<Jobs>,
+    pub workers: Mutex<Vec<JoinHandle<()>>>,
+    pub config: PoolBuilder,
+    pub panic_handler: RwLock<Option<PanicHandler>>,
+    pub panicked: AtomicUsize,
+    /// Number of jobs dropped by [RejectionPolicy::Drop].
+    pub rejected: AtomicUsize,
+    pub depth_average: Ewma,
+    /// Seconds jobs spent in the queue before a worker took them.
+    pub wait_average: Ewma,
+}
+
+#[derive(Default)]
+pub struct Jobs {
+    /// Jobs with the time they were queued, one queue per [Priority].
+    pub queues: [VecDeque<(Job, Instant)>; 3],
+    /// Set by join, workers exit once the queue is empty.
+    pub shutdown: bool,
+    /// Number of running workers.
+    pub workers: usize,
+    /// Number of workers waiting for a job.
+    pub idle: usize,
+}
+
+/// Configures a [ThreadPool] before its workers start.
+#[derive(Clone, Default)]
+pub struct PoolBuilder {
+    /// Number of workers started with the pool, the available parallelism if not set.
+    pub min_threads: Option<usize>,
+    /// Number of workers the pool may grow to, the minimum if not set.
+    pub max_threads: Option<usize>,
+    pub panic_policy: PanicPolicy,
+    pub pin_threads: bool,
+    /// Worker `i` is named `{thread_name}-{i}`.
+    pub thread_name: Option<String>,
+    pub stack_size: Option<usize>,
+    pub on_thread_start: Option<ThreadHook>,
+    pub on_thread_stop: Option<ThreadHook>,
+    pub autoscale: Option<Autoscale>,
+    /// How long a job waits before it counts as one [Priority] higher.
+    pub aging: Option<Duration>,
+    /// Number of jobs that may wait for a worker, unbounded if not set.
+    pub queue_capacity: Option<usize>,
+    pub rejection_policy: RejectionPolicy,
+}
+
+/// How long a job waits before it is taken as if it had the next higher [Priority].
+const DEFAULT_AGING: Duration = Duration::from_millis(100);
+
+/// Where a job is queued, workers take the highest priority job first. A job that keeps
+/// waiting is promoted one level per aging interval, see [PoolBuilder::aging], so bulk work
+/// still makes progress while latency-sensitive jobs keep coming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+/// Spawns tasks that may borrow from the caller of [ThreadPool::scope], all of them finish
+/// before the scope returns. Unlike the other types of the crate its fields are private: the
+/// tasks' borrows are only sound because [ThreadPool::try_scope], the only place that creates
+/// a scope, waits for them.
+pub struct Scope<'scope, E = Infallible> {
+    pool: &'scope ThreadPool,
+    /// Cancelled once a task fails or the scope body panics, tasks can check it to stop early.
+    token: CancellationToken,
+    state: Arc<ScopeState<E>>,
+    /// Invariant in 'scope, like std::thread::Scope.
+    scope: PhantomData<&'scope mut &'scope ()>,
+}
+
+struct ScopeState<E> {
+    /// Number of spawned tasks that haven't finished or been dropped.
+    pending: Mutex<usize>,
+    done: Condvar,
+    /// The first task that failed.
+    failure: Mutex<Option<Failure<E>>>,
+}
+
+/// How a scoped task failed.
+pub enum Failure<E> {
+    Panicked(Box<dyn Any + Send>),
+    Error(E),
+    /// The full queue of the pool dropped the task, see [RejectionPolicy::Drop].
+    Rejected,
+}
+
+/// Owns a scoped task and counts it as finished when dropped, whether it ran or the pool
+/// dropped it. The task is dropped first, so its borrows are gone before the scope can return.
+struct ScopedTask<E, F> {
+    task: Option<F>,
+    state: Arc<ScopeState<E>>,
+}
+
+/// When an autoscaling [ThreadPool] adds and retires workers, see [PoolBuilder::autoscale].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Autoscale {
+    /// Add a worker while the average queue depth is at least this.
+    pub depth_threshold: f64,
+    /// Add a worker while jobs wait at least this long for a worker on average.
+    pub wait_threshold: Duration,
+    /// Retire a worker above the minimum after it waited this long without a job.
+    pub keep_alive: Duration,
+}
+
+impl Default for Autoscale {
+    /// Grows once jobs queue up behind busy workers and retires workers idle for a minute.
+    fn default() -> Self {
+        Autoscale {
+            depth_threshold: 1.0,
+            wait_threshold: Duration::from_millis(10),
+            keep_alive: Duration::from_secs(60),
+        }
+    }
+}
+
+/// What [ThreadPool::execute] does with a job while the bounded queue of the pool is full, see
+/// [PoolBuilder::queue_capacity]. [ThreadPool::try_execute] hands the job back instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RejectionPolicy {
+    /// Wait until a worker takes a queued job. Jobs that queue more jobs on their own pool
+    /// can deadlock it this way once every worker waits.
+    #[default]
+    Block,
+    /// Run the job on the calling thread, which slows the producer down to the pace of the
+    /// pool. A panic of the job unwinds into the caller.
+    CallerRuns,
+    /// Drop the job, counted in [ThreadPool::rejected_jobs]. A task of a [Scope] that is
+    /// dropped fails the scope, see [ThreadPool::try_scope].
+    Drop,
+}
+
+/// Error returned by [ThreadPool::try_execute] when the queue of the pool is full, holds the
+/// job that was not queued.
+pub struct RejectedJob(pub Job);
+
+/// What a worker does after a job panicked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PanicPolicy {
+    /// Abort the process, for programs where a panic means the state can't be trusted.
+    Abort,
+    /// Replace the worker with a new thread, so thread-locals touched by the job start fresh.
+    Restart,
+    /// Keep running the next job on the same worker.
+    #[default]
+    Ignore,
+}
+
+impl ThreadPool {
+    /// Creates a pool with `threads` workers that ignore panicking jobs.
+    pub fn new(threads: usize) -> ThreadPool {
+        Self::with_panic_policy(threads, PanicPolicy::default())
+    }
+
+    /// Creates a pool with `threads` workers that handle panicking jobs according to `policy`.
+    pub fn with_panic_policy(threads: usize, policy: PanicPolicy) -> ThreadPool {
+        Self::builder()
+            .threads(threads)
+            .panic_policy(policy)
+            .build()
+    }
+
+    /// Returns a builder to configure a new pool.
+    pub fn builder() -> PoolBuilder {
+        PoolBuilder::new()
+    }
+
+    /// Queues `job` to run on one of the workers, adding a worker if all of them are busy and
+    /// the pool is below its maximum size.
+    pub fn execute<F: FnOnce() + Send + 'static>(&self, job: F) {
+        self.execute_with_priority(job, Priority::default())
+    }
+
+    /// Like [ThreadPool::execute], but the job is taken before queued jobs of lower priority.
+    pub fn execute_with_priority<F: FnOnce() + Send + 'static>(&self, job: F, priority: Priority) {
+        self.queue_job(Box::new(job), priority);
+    }
+
+    /// Like [ThreadPool::execute], but returns the job in [RejectedJob] instead of applying the
+    /// [RejectionPolicy] if the queue is full. Never fails on a pool with an unbounded queue.
+    pub fn try_execute<F: FnOnce() + Send + 'static>(&self, job: F) -> Result<(), RejectedJob> {
+        self.try_queue_job(Box::new(job), Priority::default(), false)
+    }
+
+    fn queue_job(&self, job: Job, priority: Priority) {
+        let policy = self.pool.config.rejection_policy;
+        let Err(RejectedJob(job)) =
+            self.try_queue_job(job, priority, policy == RejectionPolicy::Block)
+        else {
+            return;
+        };
+        match policy {
+            RejectionPolicy::CallerRuns => job(),
+            RejectionPolicy::Drop => {
+                self.pool.rejected.fetch_add(1, Ordering::Relaxed);
+                drop(job);
+            }
+            RejectionPolicy::Block => unreachable!("blocking queues never reject"),
+        }
+    }
+
+    /// Queues `job`, waiting for room in a full queue if `block` is set and rejecting it
+    /// otherwise.
+    fn try_queue_job(&self, job: Job, priority: Priority, block: bool) -> Result<(), RejectedJob> {
+        let mut jobs = self.pool.jobs.lock().expect("lock acquire failed");
+        assert!(!jobs.shutdown, "pool is shut down");
+        if let Some(capacity) = self.pool.config.queue_capacity {
+            while jobs.len() >= capacity {
+                if !block {
+                    return Err(RejectedJob(job));
+                }
+                jobs = self.pool.room.wait(jobs).expect("lock acquire failed");
+            }
+        }
+        jobs.queues[priority as usize].push_back((job, Instant::now()));
+        let queued = jobs.len();
+        self.pool.depth_average.update(queued as f64);
+        // idle workers that were notified but haven't taken a job yet still count as idle
+        let grow =
+            queued > jobs.idle && jobs.workers < self.pool.max_threads() && self.pool.overloaded();
+        if grow {
+            jobs.workers += 1;
+        }
+        let index = jobs.workers - 1;
+        drop(jobs);
+        if grow {
+            InnerPool::spawn_worker(&self.pool, index);
+        } else {
+            self.pool.available.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Queues `job` and returns a handle to wait for its result. If the job panics the handle
+    /// reports [crate::promise::JobError::Panicked] and the panic is still handled by the pool.
+    pub fn submit<R, F>(&self, job: F) -> JobHandle<R>
+    where
+        R: Send + 'static,
+        F: FnOnce() -> R + Send + 'static,
+    {
+        self.submit_with_priority(job, Priority::default())
+    }
+
+    /// Like [ThreadPool::submit], but the job is taken before queued jobs of lower priority.
+    pub fn submit_with_priority<R, F>(&self, job: F, priority: Priority) -> JobHandle<R>
+    where
+        R: Send + 'static,
+        F: FnOnce() -> R + Send + 'static,
+    {
+        let (promise, handle) = promise::promise();
+        self.execute_with_priority(move || promise.set(job()), priority);
+        handle
+    }
+
+    /// Runs `f` with a [Scope] to spawn tasks that may borrow local variables, and waits for
+    /// all of them before returning. If a task panics the remaining tasks are cancelled
+    /// through the scope's token, tasks that haven't started are skipped, and the panic is
+    /// resumed on the caller once every task is done. Must not be called from a job of the
+    /// same pool, the caller blocks while the tasks need workers.
+    pub fn scope<'scope, R, F>(&'scope self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'scope>) -> R,
+    {
+        match self.try_scope(f) {
+            Ok(result) => result,
+            Err(never) => match never {},
+        }
+    }
+
+    /// Like [ThreadPool::scope] for tasks spawned with [Scope::try_spawn] that can fail, the
+    /// first error cancels the other tasks and is returned. A task dropped by a full queue
+    /// under [RejectionPolicy::Drop] cancels the other tasks too and makes the scope panic, it
+    /// has no error to return.
+    pub fn try_scope<'scope, R, E, F>(&'scope self, f: F) -> Result<R, E>
+    where
+        E: Send + 'scope,
+        F: FnOnce(&Scope<'scope, E>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            token: CancellationToken::new(),
+            state: Arc::new(ScopeState {
+                pending: Mutex::new(0),
+                done: Condvar::new(),
+                failure: Mutex::new(None),
+            }),
+            scope: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        if result.is_err() {
+            scope.token.cancel();
+        }
+        // tasks borrow from the caller, so this has to wait even if the body panicked
+        let mut pending = scope.state.pending.lock().expect("lock acquire failed");
+        while *pending > 0 {
+            pending = scope.state.done.wait(pending).expect("lock acquire failed");
+        }
+        drop(pending);
+        // taken here so a task's error is never dropped on a worker after the scope ended
+        let failure = scope
+            .state
+            .failure
+            .lock()
+            .expect("lock acquire failed")
+            .take();
+        let result = result.unwrap_or_else(|payload| panic::resume_unwind(payload));
+        match failure {
+            Some(Failure::Panicked(payload)) => panic::resume_unwind(payload),
+            Some(Failure::Error(error)) => Err(error),
+            Some(Failure::Rejected) => panic!("a scoped task was dropped, the job queue is full"),
+            None => Ok(result),
+        }
+    }
+
+    /// Sets the callback that is given the payload of every panicking job.
+    pub fn set_panic_handler<F: Fn(&(dyn Any + Send)) + Send + Sync + 'static>(&self, handler: F) {
+        *self
+            .pool
+            .panic_handler
+            .write()
+            .expect("lock acquire failed") = Some(Box::new(handler));
+    }
+
+    /// Returns the number of jobs waiting for a worker.
+    pub fn pending_jobs(&self) -> usize {
+        self.pool.jobs.lock().expect("lock acquire failed").len()
+    }
+
+    /// Returns the number of queued jobs and moving averages of queue depth and the time jobs
+    /// wait for a worker.
+    pub fn load_stats(&self) -> LoadStats {
+        LoadStats {
+            depth: self.pending_jobs(),
+            average_depth: self.pool.depth_average.get(),
+            average_wait: Duration::from_secs_f64(self.pool.wait_average.get()),
+        }
+    }
+
+    /// Returns the number of jobs that panicked so far.
+    pub fn panicked_jobs(&self) -> usize {
+        self.pool.panicked.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of jobs dropped because the queue was full, see
+    /// [RejectionPolicy::Drop].
+    pub fn rejected_jobs(&self) -> usize {
+        self.pool.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of running workers.
+    pub fn threads(&self) -> usize {
+        self.pool.jobs.lock().expect("lock acquire failed").workers
+    }
+
+    /// Runs all queued jobs and stops the workers.
+    pub fn join(self) {
+        // dropping does the work, the method just makes the intent visible at the call site
+    }
+
+    /// Like [ThreadPool::join], but once `token` is cancelled the jobs that haven't started
+    /// are dropped, so it only waits for the running ones. Returns [Cancelled] if the token
+    /// was cancelled before the pool stopped.
+    pub fn join_cancellable(self, token: &CancellationToken) -> Result<(), Cancelled> {
+        let pool = self.pool.clone();
+        let _cancel_guard = token.on_cancel(move || {
+            let dropped =
+                std::mem::take(&mut pool.jobs.lock().expect("lock acquire failed").queues);
+            pool.room.notify_all();
+            // dropped outside the lock, a job's captures may run arbitrary code on drop
+            drop(dropped);
+        });
+        drop(self);
+        token.check()
+    }
+
+    fn shutdown(&self) {
+        self.pool.jobs.lock().expect("lock acquire failed").shutdown = true;
+        self.pool.available.notify_all();
+        // a restarting worker adds its replacement before it exits, so keep going until empty
+        loop {
+            let worker = self.pool.workers.lock().expect("lock acquire failed").pop();
+            match worker {
+                Some(worker) => {
+                    let _ = worker.join();
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+impl PoolBuilder {
+    /// Creates a builder with the defaults of [ThreadPool::new] and one worker per CPU.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets a fixed number of workers.
+    pub fn threads(self, threads: usize) -> Self {
+        self.min_threads(threads).max_threads(threads)
+    }
+
+    /// Sets the number of workers started with the pool.
+    pub fn min_threads(mut self, threads: usize) -> Self {
+        self.min_threads = Some(threads);
+        self
+    }
+
+    /// Sets the number of workers the pool may grow to when jobs queue up.
+    pub fn max_threads(mut self, threads: usize) -> Self {
+        self.max_threads = Some(threads);
+        self
+    }
+
+    /// Names worker `i` `{prefix}-{i}`, shown in debuggers and panic messages.
+    pub fn thread_name(mut self, prefix: impl Into<String>) -> Self {
+        self.thread_name = Some(prefix.into());
+        self
+    }
+
+    /// Sets the stack size of the workers in bytes, the std default if not set.
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Runs `hook` on every new worker before it takes its first job, e.g. to register the
+    /// thread with a [crate::registry::Registry].
+    pub fn on_thread_start<F: Fn(usize) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.on_thread_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// Runs `hook` on every worker right before its thread exits, including workers replaced
+    /// after a panic.
+    pub fn on_thread_stop<F: Fn(usize) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.on_thread_stop = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets what workers do after a job panicked.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Sets how long a queued job waits before it is taken as if it had the next higher
+    /// [Priority], 100 milliseconds if not set. A low priority job overtakes newly queued high
+    /// priority jobs after waiting twice this long.
+    pub fn aging(mut self, interval: Duration) -> Self {
+        self.aging = Some(interval);
+        self
+    }
+
+    /// Limits the number of jobs waiting for a worker to `capacity`, a job queued while the
+    /// queue is full is handled according to the [RejectionPolicy], so a producer that is
+    /// faster than the workers can't fill the memory with jobs. Panics if `capacity` is zero.
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// Sets what happens to a job queued while the bounded queue is full, blocking the caller
+    /// if not set.
+    pub fn rejection_policy(mut self, policy: RejectionPolicy) -> Self {
+        self.rejection_policy = policy;
+        self
+    }
+
+    /// Makes the pool size itself between the minimum and maximum number of workers: a worker
+    /// is added when a job is queued while every worker is busy and the load averages exceed
+    /// the thresholds of `autoscale`, and a worker above the minimum retires once it sat idle
+    /// for the keep-alive. Bursty workloads then get workers while the burst lasts without a
+    /// fixed size tuned for the peak.
+    pub fn autoscale(mut self, autoscale: Autoscale) -> Self {
+        self.autoscale = Some(autoscale);
+        self
+    }
+
+    /// Pins worker `i` to the `i`-th CPU the process may use, wrapping around if there are more
+    /// workers than CPUs, so a worker keeps its caches warm instead of being moved around by
+    /// the scheduler. Uses sched_setaffinity on Linux and SetThreadAffinityMask on Windows,
+    /// elsewhere it does nothing. A worker replaced after a panic is pinned to the same CPU.
+    pub fn pin_threads(mut self, pin: bool) -> Self {
+        self.pin_threads = pin;
+        self
+    }
+
+    /// Creates the pool and starts its workers.
+    pub fn build(mut self) -> ThreadPool {
+        let threads = *self.min_threads.get_or_insert_with(|| {
+            thread::available_parallelism().map_or(1, |threads| threads.get())
+        });
+        assert!(threads > 0, "pool needs at least one thread");
+        assert!(
+            self.max_threads.is_none_or(|max| max >= threads),
+            "maximum number of threads is below the minimum"
+        );
+        let pool: Arc<InnerPool> = InnerPool {
+            available: Condvar::new(),
+            room: Condvar::new(),
+            jobs: Mutex::new(Jobs {
+                workers: threads,
+                ..Jobs::default()
+            }),
+            workers: Mutex::new(Vec::with_capacity(threads)),
+            config: self,
+            panic_handler: RwLock::new(None),
+            panicked: AtomicUsize::new(0),
+            rejected: AtomicUsize::new(0),
+            depth_average: Ewma::new(LOAD_ALPHA),
+            wait_average: Ewma::new(LOAD_ALPHA),
+        }
+        .into();
+        for index in 0..threads {
+            InnerPool::spawn_worker(&pool, index);
+        }
+        ThreadPool { pool }
+    }
+}
+
+impl InnerPool {
+    fn max_threads(&self) -> usize {
+        let min = self.config.min_threads.unwrap_or(1);
+        self.config.max_threads.unwrap_or(min)
+    }
+
+    /// Returns true if a busy pool should grow.
+    fn overloaded(&self) -> bool {
+        self.config.autoscale.is_none_or(|autoscale| {
+            self.depth_average.get() >= autoscale.depth_threshold
+                || self.wait_average.get() >= autoscale.wait_threshold.as_secs_f64()
+        })
+    }
+
+    fn spawn_worker(pool: &Arc<InnerPool>, index: usize) {
+        let mut builder = thread::Builder::new();
+        if let Some(name) = &pool.config.thread_name {
+            builder = builder.name(format!("{name}-{index}"));
+        }
+        if let Some(stack_size) = pool.config.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        let worker = {
+            let pool = pool.clone();
+            builder
+                .spawn(move || {
+                    if pool.config.pin_threads {
+                        // best effort, an unpinned worker still works
+                        affinity::pin_current_thread(index);
+                    }
+                    if let Some(hook) = &pool.config.on_thread_start {
+                        hook(index);
+                    }
+                    pool.clone().work(index);
+                    if let Some(hook) = &pool.config.on_thread_stop {
+                        hook(index);
+                    }
+                })
+                .expect("failed to spawn worker thread")
+        };
+        let mut workers = pool.workers.lock().expect("lock acquire failed");
+        // retired workers are done, forget them so a long running pool doesn't pile them up
+        workers.retain(|worker| !worker.is_finished());
+        workers.push(worker);
+    }
+
+    /// Takes the next job, waiting for one unless the pool is shut down and drained or the
+    /// worker should retire.
+    fn next_job(&self) -> Option<Job> {
+        let mut jobs = self.jobs.lock().expect("lock acquire failed");
+        loop {
+            if let Some((job, queued_at)) = jobs.pop(self.config.aging.unwrap_or(DEFAULT_AGING)) {
+                self.wait_average.update(queued_at.elapsed().as_secs_f64());
+                if self.config.queue_capacity.is_some() {
+                    self.room.notify_one();
+                }
+                return Some(job);
+            }
+            if jobs.shutdown {
+                return None;
+            }
+            let min_threads = self.config.min_threads.unwrap_or(1);
+            jobs.idle += 1;
+            match self.config.autoscale {
+                Some(autoscale) if jobs.workers > min_threads => {
+                    let timed_out;
+                    (jobs, timed_out) = self
+                        .available
+                        .wait_timeout(jobs, autoscale.keep_alive)
+                        .map(|(jobs, result)| (jobs, result.timed_out()))
+                        .expect("lock acquire failed");
+                    jobs.idle -= 1;
+                    // another worker may have retired meanwhile
+                    if timed_out && jobs.len() == 0 && jobs.workers > min_threads {
+                        jobs.workers -= 1;
+                        return None;
+                    }
+                }
+                _ => {
+                    jobs = self.available.wait(jobs).expect("lock acquire failed");
+                    jobs.idle -= 1;
+                }
+            }
+        }
+    }
+
+    fn work(self: Arc<Self>, index: usize) {
+        while let Some(job) = self.next_job() {
+            // the job is gone after the panic, nothing observes its broken state
+            let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) else {
+                continue;
+            };
+            self.panicked.fetch_add(1, Ordering::Relaxed);
+            if let Some(handler) = &*self.panic_handler.read().expect("lock acquire failed") {
+                handler(&*payload);
+            }
+            match self.config.panic_policy {
+                PanicPolicy::Abort => process::abort(),
+                PanicPolicy::Restart => {
+                    InnerPool::spawn_worker(&self, index);
+                    return;
+                }
+                PanicPolicy::Ignore => {}
+            }
+        }
+    }
+}
+
+impl<'scope, E: Send + 'scope> Scope<'scope, E> {
+    /// Queues `task` on the pool, it is given the scope's token to check for cancellation.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: FnOnce(&CancellationToken) + Send + 'scope,
+    {
+        self.try_spawn(move |token| {
+            task(token);
+            Ok(())
+        });
+    }
+
+    /// Like [Scope::spawn] for a task that can fail, its error cancels the other tasks and is
+    /// returned by [ThreadPool::try_scope] unless a task failed before.
+    pub fn try_spawn<F>(&self, task: F)
+    where
+        F: FnOnce(&CancellationToken) -> Result<(), E> + Send + 'scope,
+    {
+        *self.state.pending.lock().expect("lock acquire failed") += 1;
+        let mut scoped = ScopedTask {
+            task: Some(task),
+            state: self.state.clone(),
+        };
+        let token = self.token.clone();
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let task = scoped.task.take().expect("a scoped task runs once");
+            if token.is_cancelled() {
+                // dropped before scoped counts it as finished
+                return drop(task);
+            }
+            let failure = match panic::catch_unwind(AssertUnwindSafe(|| task(&token))) {
+                Ok(Ok(())) => None,
+                Ok(Err(error)) => Some(Failure::Error(error)),
+                Err(payload) => Some(Failure::Panicked(payload)),
+            };
+            if let Some(failure) = failure {
+                scoped.state.fail(failure);
+                token.cancel();
+            }
+            drop(scoped);
+        });
+        // the scope waits until this job ran or was dropped, so nothing it borrows is gone
+        // before it is done, std::thread::scope erases the lifetime the same way
+        let job: Job = unsafe { mem::transmute(job) };
+        if self.pool.pool.config.rejection_policy != RejectionPolicy::Drop {
+            return self.pool.queue_job(job, Priority::default());
+        }
+        if let Err(RejectedJob(job)) = self.pool.try_queue_job(job, Priority::default(), false) {
+            self.pool.pool.rejected.fetch_add(1, Ordering::Relaxed);
+            self.state.fail(Failure::Rejected);
+            self.token.cancel();
+            drop(job);
+        }
+    }
+
+    /// Returns the token cancelled once a task fails.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+impl<E> ScopeState<E> {
+    /// Records `failure` unless a task failed before.
+    fn fail(&self, failure: Failure<E>) {
+        self.failure
+            .lock()
+            .expect("lock acquire failed")
+            .get_or_insert(failure);
+    }
+}
+
+impl<E, F> Drop for ScopedTask<E, F> {
+    fn drop(&mut self) {
+        drop(self.task.take());
+        let mut pending = self.state.pending.lock().expect("lock acquire failed");
+        *pending -= 1;
+        if *pending == 0 {
+            self.state.done.notify_all();
+        }
+    }
+}
+
+impl Jobs {
+    /// Returns the number of queued jobs.
+    fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    /// Takes the oldest job of the highest priority, counting every `aging` a job waited as
+    /// one level higher. Only the oldest job of each priority can be the most promoted one.
+    fn pop(&mut self, aging: Duration) -> Option<(Job, Instant)> {
+        let now = Instant::now();
+        let aging = aging.as_nanos().max(1);
+        let (_, _, level) = (0..self.queues.len())
+            .filter_map(|level| {
+                let (_, queued_at) = self.queues[level].front()?;
+                let promoted = now.saturating_duration_since(*queued_at).as_nanos() / aging;
+                // ties go to the job that waited longest
+                Some(((level as u128).saturating_sub(promoted), *queued_at, level))
+            })
+            .min()?;
+        self.queues[level].pop_front()
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for ThreadPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadPool")
+            .field("threads", &self.threads())
+            .field("pending_jobs", &self.pending_jobs())
+            .field("panicked_jobs", &self.panicked_jobs())
+            .field("panic_policy", &self.pool.config.panic_policy)
+            .field("pin_threads", &self.pool.config.pin_threads)
+            .finish()
+    }
+}
+
+impl fmt::Debug for RejectedJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("RejectedJob").finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for RejectedJob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the job queue of the pool is full")
+    }
+}
+
+impl std::error::Error for RejectedJob {}
+
+impl fmt::Debug for PoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolBuilder")
+            .field("min_threads", &self.min_threads)
+            .field("max_threads", &self.max_threads)
+            .field("panic_policy", &self.panic_policy)
+            .field("pin_threads", &self.pin_threads)
+            .field("thread_name", &self.thread_name)
+            .field("stack_size", &self.stack_size)
+            .field("autoscale", &self.autoscale)
+            .field("aging", &self.aging)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("rejection_policy", &self.rejection_policy)
+            .finish_non_exhaustive()
+    }
+}
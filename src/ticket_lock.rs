@@ -0,0 +1,59 @@
+use crate::lock::RawLock;
+use crate::spin::Backoff;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A FIFO-fair spin lock. Every thread draws a ticket and waits until its number is served,
+/// so the lock is handed out strictly in arrival order and no thread can be overtaken
+/// indefinitely, unlike with [std::sync::Mutex]. Waiting threads spin and then yield,
+/// so it suits short critical sections. Use it through [crate::lock::Lock], e.g. as
+/// `Multiq<T, TicketLock>`.
+#[derive(Debug, Default)]
+pub struct TicketLock {
+    pub next_ticket: AtomicUsize,
+    pub now_serving: AtomicUsize,
+}
+
+impl TicketLock {
+    /// Creates a new unlocked ticket lock.
+    pub const fn new() -> Self {
+        TicketLock {
+            next_ticket: AtomicUsize::new(0),
+            now_serving: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of threads holding or waiting for the lock.
+    pub fn queue_len(&self) -> usize {
+        let next = self.next_ticket.load(Ordering::Relaxed);
+        next.wrapping_sub(self.now_serving.load(Ordering::Relaxed))
+    }
+}
+
+unsafe impl RawLock for TicketLock {
+    fn lock(&self) {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let mut backoff = Backoff::new();
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            backoff.snooze();
+        }
+    }
+
+    fn try_lock(&self) -> bool {
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        // only take a ticket if it would be served right away
+        self.next_ticket
+            .compare_exchange(
+                serving,
+                serving.wrapping_add(1),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+    }
+
+    unsafe fn unlock(&self) {
+        let serving = self.now_serving.load(Ordering::Relaxed);
+        self.now_serving
+            .store(serving.wrapping_add(1), Ordering::Release);
+    }
+}
@@ -0,0 +1,262 @@
+use crate::thread_pool::Job;
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashSet},
+    fmt,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A thread that fires timers at their deadline, either by running a callback or by waking a
+/// [Sleep] future, so a job that should run in 30 seconds doesn't need its own sleeping thread.
+/// Timers wait in a heap ordered by deadline, the thread sleeps on a condvar until the earliest
+/// one is due or an earlier one is scheduled. Callbacks run on the driver thread and should be
+/// short, e.g. push to a queue or submit to a [crate::thread_pool::ThreadPool]. A callback that
+/// panics doesn't stop the driver. Dropping the driver stops the thread, pending timers never
+/// fire.
+#[derive(Debug)]
+pub struct TimerDriver {
+    pub shared: Arc<TimerShared>,
+    pub thread: Option<thread::JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+pub struct TimerShared {
+    pub timers: Mutex<Timers>,
+    pub cvar: Condvar,
+}
+
+#[derive(Debug)]
+pub struct Timers {
+    pub heap: BinaryHeap<Timer>,
+    /// Ids of the timers that are neither fired nor cancelled, cancelled timers stay in the
+    /// heap until their deadline and are skipped.
+    pub pending: HashSet<u64>,
+    pub next_id: u64,
+    pub shutdown: bool,
+}
+
+/// A scheduled timer, ordered so the earliest deadline is at the top of the heap.
+pub struct Timer {
+    pub deadline: Instant,
+    pub id: u64,
+    pub action: Action,
+}
+
+/// What happens when a [Timer] fires.
+pub enum Action {
+    Callback(Job),
+    Wake(Arc<Mutex<SleepState>>),
+}
+
+/// Identifies a timer scheduled on a [TimerDriver], to cancel it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TimerId(pub u64);
+
+/// A future that completes once its deadline passed, returned by [TimerDriver::sleep].
+#[derive(Debug)]
+pub struct Sleep {
+    pub state: Arc<Mutex<SleepState>>,
+}
+
+#[derive(Debug, Default)]
+pub struct SleepState {
+    pub fired: bool,
+    pub waker: Option<Waker>,
+}
+
+impl TimerDriver {
+    /// Starts the driver thread.
+    pub fn new() -> Self {
+        let shared = Arc::new(TimerShared {
+            timers: Mutex::new(Timers {
+                heap: BinaryHeap::new(),
+                pending: HashSet::new(),
+                next_id: 0,
+                shutdown: false,
+            }),
+            cvar: Condvar::new(),
+        });
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || shared.run())
+        };
+        TimerDriver {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    /// Runs `f` on the driver thread once `delay` passed.
+    pub fn schedule_after<F: FnOnce() + Send + 'static>(&self, delay: Duration, f: F) -> TimerId {
+        self.schedule_at(Instant::now() + delay, f)
+    }
+
+    /// Runs `f` on the driver thread once `deadline` passed.
+    pub fn schedule_at<F: FnOnce() + Send + 'static>(&self, deadline: Instant, f: F) -> TimerId {
+        self.add(deadline, Action::Callback(Box::new(f)))
+    }
+
+    /// Returns a future that completes once `delay` passed.
+    pub fn sleep(&self, delay: Duration) -> Sleep {
+        self.sleep_until(Instant::now() + delay)
+    }
+
+    /// Returns a future that completes once `deadline` passed.
+    pub fn sleep_until(&self, deadline: Instant) -> Sleep {
+        let state = Arc::new(Mutex::new(SleepState::default()));
+        self.add(deadline, Action::Wake(state.clone()));
+        Sleep { state }
+    }
+
+    /// Cancels the timer, returns false if it already fired or was cancelled.
+    pub fn cancel(&self, id: TimerId) -> bool {
+        self.shared.lock().pending.remove(&id.0)
+    }
+
+    /// Returns the number of timers that will still fire.
+    pub fn pending(&self) -> usize {
+        self.shared.lock().pending.len()
+    }
+
+    fn add(&self, deadline: Instant, action: Action) -> TimerId {
+        let mut timers = self.shared.lock();
+        let id = timers.next_id;
+        timers.next_id += 1;
+        timers.pending.insert(id);
+        let earliest = timers.heap.peek().is_none_or(|top| deadline < top.deadline);
+        timers.heap.push(Timer {
+            deadline,
+            id,
+            action,
+        });
+        drop(timers);
+        // the driver sleeps until the previous earliest deadline
+        if earliest {
+            self.shared.cvar.notify_one();
+        }
+        TimerId(id)
+    }
+}
+
+impl Default for TimerDriver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TimerDriver {
+    fn drop(&mut self) {
+        self.shared.lock().shutdown = true;
+        self.shared.cvar.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl TimerShared {
+    fn run(&self) {
+        let mut timers = self.lock();
+        while !timers.shutdown {
+            let now = Instant::now();
+            match timers.heap.peek().map(|top| top.deadline) {
+                Some(deadline) if deadline <= now => {
+                    let timer = timers.heap.pop().expect("peeked a timer");
+                    if !timers.pending.remove(&timer.id) {
+                        continue;
+                    }
+                    drop(timers);
+                    timer.action.fire();
+                    timers = self.lock();
+                }
+                Some(deadline) => {
+                    timers = self
+                        .cvar
+                        .wait_timeout(timers, deadline - now)
+                        .expect("lock acquire failed")
+                        .0;
+                }
+                None => timers = self.cvar.wait(timers).expect("lock acquire failed"),
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Timers> {
+        self.timers.lock().expect("lock acquire failed")
+    }
+}
+
+impl Action {
+    fn fire(self) {
+        match self {
+            Action::Callback(f) => {
+                let _ = panic::catch_unwind(AssertUnwindSafe(f));
+            }
+            Action::Wake(state) => {
+                let mut state = state.lock().expect("lock acquire failed");
+                state.fired = true;
+                let waker = state.waker.take();
+                drop(state);
+                if let Some(waker) = waker {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+impl Sleep {
+    /// Returns true once the deadline passed.
+    pub fn is_elapsed(&self) -> bool {
+        self.state.lock().expect("lock acquire failed").fired
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = self.state.lock().expect("lock acquire failed");
+        if state.fired {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl PartialEq for Timer {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Timer {}
+
+impl PartialOrd for Timer {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Timer {
+    /// Reversed, so [BinaryHeap] pops the earliest deadline first, ties in scheduling order.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.deadline, other.id).cmp(&(self.deadline, self.id))
+    }
+}
+
+impl fmt::Debug for Timer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timer")
+            .field("deadline", &self.deadline)
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
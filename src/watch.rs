@@ -0,0 +1,107 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+/// A lock-based latest-value cell. Writers publish new values, readers either read the
+/// latest one or wait until it changes. Every publish bumps a version counter, each
+/// [Watcher] remembers the last version it saw, so it never misses that a change happened
+/// even if intermediate values were overwritten before it looked.
+#[derive(Debug, Clone)]
+pub struct Watch<T: Clone> {
+    pub cell: Arc<InnerWatch<T>>,
+}
+
+#[derive(Debug)]
+pub struct InnerWatch<T: Clone> {
+    pub cvar: Condvar,
+    pub current: Mutex<Versioned<T>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Versioned<T: Clone> {
+    pub value: T,
+    pub version: u64,
+}
+
+/// Reading half of [Watch] which tracks the last seen version, created by [Watch::watcher].
+#[derive(Debug, Clone)]
+pub struct Watcher<T: Clone> {
+    pub cell: Arc<InnerWatch<T>>,
+    pub seen: u64,
+}
+
+impl<T: Clone> Watch<T> {
+    /// Creates a new cell holding `value`.
+    pub fn new(value: T) -> Watch<T> {
+        Watch {
+            cell: InnerWatch {
+                cvar: Condvar::new(),
+                current: Mutex::new(Versioned { value, version: 0 }),
+            }
+            .into(),
+        }
+    }
+
+    /// Replaces the current value and wakes up every waiting watcher.
+    pub fn publish(&self, value: T) {
+        let mut current = self.cell.current.lock().expect("lock acquire failed");
+        current.value = value;
+        current.version += 1;
+        drop(current);
+        self.cell.cvar.notify_all();
+    }
+
+    /// Returns a copy of the latest value.
+    pub fn get(&self) -> T {
+        self.cell
+            .current
+            .lock()
+            .expect("lock acquire failed")
+            .value
+            .clone()
+    }
+
+    /// Returns the number of values published since the cell was created.
+    pub fn version(&self) -> u64 {
+        self.cell
+            .current
+            .lock()
+            .expect("lock acquire failed")
+            .version
+    }
+
+    /// Creates a watcher which considers the current value as already seen.
+    pub fn watcher(&self) -> Watcher<T> {
+        Watcher {
+            cell: self.cell.clone(),
+            seen: self.version(),
+        }
+    }
+}
+
+impl<T: Clone> Watcher<T> {
+    /// Returns true if a value was published since this watcher last read one.
+    pub fn has_changed(&self) -> bool {
+        self.cell
+            .current
+            .lock()
+            .expect("lock acquire failed")
+            .version
+            != self.seen
+    }
+
+    /// Returns a copy of the latest value and marks it as seen.
+    pub fn get(&mut self) -> T {
+        let current = self.cell.current.lock().expect("lock acquire failed");
+        self.seen = current.version;
+        current.value.clone()
+    }
+
+    /// Waits until a value this watcher has not seen yet is published and returns it.
+    pub fn wait_for_change(&mut self) -> T {
+        let mut current = self.cell.current.lock().expect("lock acquire failed");
+        while current.version == self.seen {
+            current = self.cell.cvar.wait(current).expect("lock acquire failed");
+        }
+        self.seen = current.version;
+        current.value.clone()
+    }
+}
@@ -0,0 +1,249 @@
+// Linearizability checks for the concurrent containers. Threads run random operations on a
+// container and record when each call started and returned, then the history is checked
+// against a sequential specification as described by Wing and Gong: it is linearizable if
+// the calls can be put in an order that respects real time and gives the same results when
+// replayed on the specification. New containers only need a line in the proptest block.
+use concurrency::boundq::Boundq;
+use concurrency::container::{ConcurrentQueue, ConcurrentStack};
+use concurrency::dequeus::Dequeus;
+use concurrency::inline_stackus::InlineStackus;
+use concurrency::multiq::Multiq;
+use concurrency::stackus::Stackus;
+use proptest::prelude::*;
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Barrier;
+use std::thread;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Push(u8),
+    Pop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ret {
+    Pushed,
+    Popped(Option<u8>),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Event {
+    op: Op,
+    ret: Ret,
+    invoked: usize,
+    returned: usize,
+}
+
+/// Sequential behaviour a history is checked against.
+trait Spec: Clone + Default + Hash + Eq {
+    fn apply(&mut self, op: Op) -> Ret;
+}
+
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq)]
+struct StackSpec(Vec<u8>);
+
+#[derive(Debug, Clone, Default, Hash, PartialEq, Eq)]
+struct QueueSpec(VecDeque<u8>);
+
+impl Spec for StackSpec {
+    fn apply(&mut self, op: Op) -> Ret {
+        match op {
+            Op::Push(value) => {
+                self.0.push(value);
+                Ret::Pushed
+            }
+            Op::Pop => Ret::Popped(self.0.pop()),
+        }
+    }
+}
+
+impl Spec for QueueSpec {
+    fn apply(&mut self, op: Op) -> Ret {
+        match op {
+            Op::Push(value) => {
+                self.0.push_back(value);
+                Ret::Pushed
+            }
+            Op::Pop => Ret::Popped(self.0.pop_front()),
+        }
+    }
+}
+
+/// Runs each list of operations on its own thread and records the history.
+fn record(threads: &[Vec<Op>], apply: impl Fn(Op) -> Ret + Sync) -> Vec<Event> {
+    let clock = AtomicUsize::new(0);
+    let barrier = Barrier::new(threads.len());
+    thread::scope(|s| {
+        let handles: Vec<_> = threads
+            .iter()
+            .map(|ops| {
+                let (clock, barrier, apply) = (&clock, &barrier, &apply);
+                s.spawn(move || {
+                    barrier.wait();
+                    ops.iter()
+                        .map(|&op| {
+                            let invoked = clock.fetch_add(1, Ordering::SeqCst);
+                            let ret = apply(op);
+                            let returned = clock.fetch_add(1, Ordering::SeqCst);
+                            Event {
+                                op,
+                                ret,
+                                invoked,
+                                returned,
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+/// Searches for an order of the calls that respects real time and matches `spec`.
+fn linearizable<S: Spec>(history: &[Event]) -> bool {
+    assert!(history.len() <= 64, "history too long to check");
+    fn search<S: Spec>(
+        history: &[Event],
+        remaining: u64,
+        spec: S,
+        failed: &mut HashSet<(u64, S)>,
+    ) -> bool {
+        if remaining == 0 {
+            return true;
+        }
+        if failed.contains(&(remaining, spec.clone())) {
+            return false;
+        }
+        let pending = |i: usize| remaining & (1 << i) != 0;
+        for (i, event) in history.iter().enumerate().filter(|(i, _)| pending(*i)) {
+            // a call can go first only if no other pending call returned before it started
+            let minimal = history
+                .iter()
+                .enumerate()
+                .all(|(j, other)| !pending(j) || other.returned > event.invoked);
+            if !minimal {
+                continue;
+            }
+            let mut next = spec.clone();
+            if next.apply(event.op) == event.ret
+                && search(history, remaining & !(1 << i), next, failed)
+            {
+                return true;
+            }
+        }
+        failed.insert((remaining, spec));
+        false
+    }
+    let all = if history.len() == 64 {
+        u64::MAX
+    } else {
+        (1 << history.len()) - 1
+    };
+    search(history, all, S::default(), &mut HashSet::new())
+}
+
+fn check_stack<S: ConcurrentStack<u8> + Sync>(stack: S, threads: &[Vec<Op>]) -> bool {
+    let history = record(threads, |op| match op {
+        Op::Push(value) => {
+            stack.push(value);
+            Ret::Pushed
+        }
+        Op::Pop => Ret::Popped(stack.try_pop()),
+    });
+    while stack.try_pop().is_some() {}
+    linearizable::<StackSpec>(&history)
+}
+
+fn check_queue<Q: ConcurrentQueue<u8> + Sync>(queue: Q, threads: &[Vec<Op>]) -> bool {
+    let history = record(threads, |op| match op {
+        Op::Push(value) => {
+            queue.push(value);
+            Ret::Pushed
+        }
+        Op::Pop => Ret::Popped(queue.try_pop()),
+    });
+    linearizable::<QueueSpec>(&history)
+}
+
+fn empty_stackus() -> Stackus<u8> {
+    let stack = Stackus::new(0);
+    stack.pop();
+    stack
+}
+
+fn empty_multiq() -> Multiq<u8> {
+    let queue = Multiq::new(0);
+    queue.pop();
+    queue
+}
+
+fn threads() -> impl Strategy<Value = Vec<Vec<Op>>> {
+    let op = prop_oneof![any::<u8>().prop_map(Op::Push), Just(Op::Pop)];
+    prop::collection::vec(prop::collection::vec(op, 1..5), 2..4)
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn inline_stack_is_linearizable(threads in threads()) {
+        // large enough to never spill
+        prop_assert!(check_stack(InlineStackus::<u8, 16>::new(), &threads));
+    }
+
+    #[test]
+    fn stackus_is_linearizable(threads in threads()) {
+        prop_assert!(check_stack(empty_stackus(), &threads));
+    }
+
+    #[test]
+    fn multiq_is_linearizable(threads in threads()) {
+        prop_assert!(check_queue(empty_multiq(), &threads));
+    }
+
+    #[test]
+    fn boundq_is_linearizable(threads in threads()) {
+        prop_assert!(check_queue(Boundq::new(16), &threads));
+    }
+
+    #[test]
+    fn dequeus_is_linearizable(threads in threads()) {
+        prop_assert!(check_queue(Dequeus::new(), &threads));
+    }
+}
+
+#[test]
+fn checker_rejects_impossible_histories() {
+    let event = |op, ret, invoked, returned| Event {
+        op,
+        ret,
+        invoked,
+        returned,
+    };
+    // the pop finished before the push started, so it can't have seen the value
+    let history = [
+        event(Op::Pop, Ret::Popped(Some(1)), 0, 1),
+        event(Op::Push(1), Ret::Pushed, 2, 3),
+    ];
+    assert!(!linearizable::<QueueSpec>(&history));
+    // overlapping calls may take effect in either order
+    let history = [
+        event(Op::Pop, Ret::Popped(Some(1)), 0, 3),
+        event(Op::Push(1), Ret::Pushed, 1, 2),
+    ];
+    assert!(linearizable::<QueueSpec>(&history));
+    // a stack pops in LIFO order
+    let history = [
+        event(Op::Push(1), Ret::Pushed, 0, 1),
+        event(Op::Push(2), Ret::Pushed, 2, 3),
+        event(Op::Pop, Ret::Popped(Some(1)), 4, 5),
+    ];
+    assert!(!linearizable::<StackSpec>(&history));
+    assert!(linearizable::<QueueSpec>(&history));
+}
@@ -0,0 +1,111 @@
+// Model checks of the memory orderings of Stackus. loom runs each model under every
+// interleaving and every value the C++ memory model allows a load to see, so an ordering that
+// is too weak shows up as a failed assertion or as a causality violation of a loom cell.
+// The values are loom cells written before the push and read after the pop, loom reports the
+// read if the push doesn't happen before it. Only builds with `--cfg loom`:
+// RUSTFLAGS="--cfg loom" cargo test --release --test loom
+#![cfg(loom)]
+use concurrency::stackus::Stackus;
+use loom::cell::UnsafeCell;
+use loom::sync::Arc;
+use loom::thread;
+
+fn value(value: usize) -> Box<UnsafeCell<usize>> {
+    Box::new(UnsafeCell::new(value))
+}
+
+fn read(cell: &UnsafeCell<usize>) -> usize {
+    cell.with(|value| unsafe { *value })
+}
+
+/// Returns a stack without values, a Stackus starts with one.
+fn empty_stack() -> Arc<Stackus<Box<UnsafeCell<usize>>>> {
+    let stack = Stackus::new(value(0));
+    stack.pop();
+    Arc::new(stack)
+}
+
+/// Pops every value left, once the other threads are joined.
+fn drain(stack: &Stackus<Box<UnsafeCell<usize>>>) -> Vec<usize> {
+    let mut values = Vec::new();
+    while let Some(cell) = stack.pop() {
+        values.push(read(&cell));
+    }
+    values
+}
+
+#[test]
+fn push_publishes_the_value_to_pop() {
+    loom::model(|| {
+        let stack = empty_stack();
+        let pusher = {
+            let stack = stack.clone();
+            thread::spawn(move || stack.push(value(1)))
+        };
+        let popped = stack.pop().as_deref().map(read);
+        pusher.join().unwrap();
+        let mut values: Vec<_> = popped.into_iter().collect();
+        values.extend(drain(&stack));
+        assert_eq!(values, vec![1]);
+    });
+}
+
+#[test]
+fn pushes_publish_the_nodes_below_the_top() {
+    loom::model(|| {
+        let stack = empty_stack();
+        let pushers: Vec<_> = (1..=2)
+            .map(|n| {
+                let stack = stack.clone();
+                thread::spawn(move || stack.push(value(n)))
+            })
+            .collect();
+        let first = stack.pop().as_deref().map(read);
+        for pusher in pushers {
+            pusher.join().unwrap();
+        }
+        let mut values: Vec<_> = first.into_iter().collect();
+        values.extend(drain(&stack));
+        values.sort();
+        assert_eq!(values, vec![1, 2]);
+    });
+}
+
+#[test]
+fn concurrent_pops_take_each_value_once() {
+    loom::model(|| {
+        let stack = empty_stack();
+        stack.push(value(1));
+        stack.push(value(2));
+        let popper = {
+            let stack = stack.clone();
+            thread::spawn(move || stack.pop().as_deref().map(read))
+        };
+        let mine = stack.pop().as_deref().map(read);
+        let theirs = popper.join().unwrap();
+        let mut values: Vec<_> = mine.into_iter().chain(theirs).collect();
+        values.sort();
+        assert_eq!(values, vec![1, 2]);
+        assert!(stack.is_empty());
+        stack.reclaim_now();
+        assert_eq!(stack.pending_retired(), 0);
+    });
+}
+
+#[test]
+fn pop_races_with_a_push() {
+    loom::model(|| {
+        let stack = empty_stack();
+        stack.push(value(1));
+        let pusher = {
+            let stack = stack.clone();
+            thread::spawn(move || stack.push(value(2)))
+        };
+        let popped = stack.pop().as_deref().map(read);
+        pusher.join().unwrap();
+        let mut values: Vec<_> = popped.into_iter().collect();
+        values.extend(drain(&stack));
+        values.sort();
+        assert_eq!(values, vec![1, 2]);
+    });
+}